@@ -0,0 +1,153 @@
+use crate::Number;
+use crate::Settings;
+use crate::currency::{self, ExchangeRates};
+use crate::datetime::Clock;
+use crate::error::Error;
+use crate::format::Separators;
+use crate::parser::AstNode;
+use crate::units::{self, CustomUnit, Unit};
+use fastnum::D128;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Everything an evaluation depends on besides the input itself, like the
+/// locale and exchange rates.
+///
+/// Unlike the global [`Settings`], a `Context` belongs to a single caller, so
+/// evaluations with different locales can run on different threads at the
+/// same time.
+///
+/// Example:
+/// ```rust
+/// use cpc::{eval_with_context, Context, EvalOptions};
+///
+/// let mut context = Context::new("en-US");
+/// let output = eval_with_context("10 $ + 5 $", &EvalOptions::new(), &mut context).unwrap();
+/// assert_eq!(output.value.to_string(), "15 USD");
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Context {
+	/// Locale like `en-US`, used for words like `$` that mean different things
+	/// in different regions
+	pub locale: String,
+	/// Exchange rates used for currency conversion. If they're missing, they
+	/// are loaded as needed depending on the
+	/// [`CurrencyPolicy`](crate::CurrencyPolicy).
+	pub exchange_rates: Option<Arc<ExchangeRates>>,
+	/// Variables defined with assignments like `rate = 45 EUR/h`. The result
+	/// of the last successful evaluation is stored as `ans`.
+	pub variables: HashMap<String, Number>,
+	/// Functions defined with assignments like `f(x) = x^2 + 3x`
+	pub functions: HashMap<String, UserFunction>,
+	/// Units defined with [`Context::define_unit`] or loaded from a unit
+	/// definition file
+	pub custom_units: Vec<CustomUnit>,
+	/// Read numbers with `.` as the decimal separator and `,` between
+	/// thousands, whatever the locale. Useful for scripts.
+	pub locale_independent: bool,
+	/// Where `now` and `today` get the current time from
+	pub clock: Clock,
+}
+
+/// A function defined with an assignment like `bmi(m, h) = m / h^2`
+#[derive(Clone, Debug)]
+pub struct UserFunction {
+	/// Names of the parameters, in order
+	pub parameters: Vec<String>,
+	/// The expression to the right of the `=`
+	pub body: AstNode,
+}
+
+impl Context {
+	pub fn new(locale: impl Into<String>) -> Context {
+		Context {
+			locale: locale.into(),
+			..Context::default()
+		}
+	}
+	/// A context with the locale from the global [`Settings`] and the exchange
+	/// rates in the global currency cache
+	pub fn from_globals() -> Context {
+		Context {
+			locale: Settings::read().locale.clone(),
+			exchange_rates: currency::cached_rates(),
+			..Context::default()
+		}
+	}
+	pub fn with_exchange_rates(mut self, exchange_rates: ExchangeRates) -> Context {
+		self.exchange_rates = Some(Arc::new(exchange_rates));
+		self
+	}
+	/// Use a different clock for `now` and `today`, like a fixed time in tests
+	pub fn with_clock(mut self, clock: Clock) -> Context {
+		self.clock = clock;
+		self
+	}
+	/// The region part of the locale, like `US` in `en-US`
+	pub fn region(&self) -> &str {
+		locale_region(&self.locale)
+	}
+	/// The separators numbers in the input are read with, like the decimal
+	/// comma in `1,5 kg` in `de-DE`
+	pub fn separators(&self) -> Separators {
+		match self.locale_independent {
+			true => Separators::INVARIANT,
+			false => Separators::for_locale(&self.locale),
+		}
+	}
+	/// Define a unit, like `pallet = 800 kg`.
+	///
+	/// The unit can be written as its name, its plural or one of its aliases,
+	/// and works like the built-in units in conversions and arithmetic. The
+	/// plural is the name with an `s` appended, unless the name contains
+	/// uppercase letters like `mAh`. Defining a unit with the same name again
+	/// replaces it.
+	///
+	/// Example:
+	/// ```rust
+	/// use cpc::{eval_with_context, Context, EvalOptions, Number};
+	/// use cpc::units::Unit;
+	/// use fastnum::dec128;
+	///
+	/// let mut context = Context::new("en-US");
+	/// let pallet = Number::with_basic_unit(dec128!(800), Unit::Kilogram);
+	/// context.define_unit("pallet", &["plt"], pallet).unwrap();
+	/// let output = eval_with_context("3 pallets to t", &EvalOptions::new(), &mut context).unwrap();
+	/// assert_eq!(output.value.to_string(), "2.4 metric tons");
+	/// ```
+	pub fn define_unit(
+		&mut self,
+		name: &str,
+		aliases: &[&str],
+		definition: Number,
+	) -> Result<Unit, Error> {
+		units::define_unit(self, name, None, aliases, definition)
+	}
+	/// Look up a variable. `_` is an alias for `ans`, the previous result,
+	/// which like other keywords can be written in any case.
+	pub fn variable(&self, name: &str) -> Option<&Number> {
+		match name {
+			"_" => self.variables.get("ans"),
+			_ if name.eq_ignore_ascii_case("ans") => self.variables.get("ans"),
+			_ => self.variables.get(name),
+		}
+	}
+	/// Get the exchange rate from one currency to another
+	pub fn exchange_rate(&self, from: Unit, to: Unit) -> Result<D128, Error> {
+		if from == to {
+			return Ok(D128::from(1));
+		}
+		match &self.exchange_rates {
+			Some(exchange_rates) => exchange_rates.get(from, to),
+			None => Err(currency::unavailable(
+				from,
+				"Exchange rates have not been loaded".to_string(),
+			)),
+		}
+	}
+}
+
+/// The region part of a locale, like `US` in `en-US`
+pub(crate) fn locale_region(locale: &str) -> &str {
+	locale.split('-').next_back().unwrap()
+}
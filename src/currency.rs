@@ -1,22 +1,38 @@
 //! Currency exchange rate fetching and management
 
-use crate::units::Unit;
+use crate::error::{Error, Span};
+use crate::parser::AstNode;
+use crate::units::{Unit, UnitType};
+use crate::{Context, Token};
 use fastnum::D128;
-use fastnum::decimal::Context;
+use fastnum::decimal::Context as DecimalContext;
 use serde::Deserialize;
 use std::collections::HashMap;
-use std::sync::RwLock;
+use std::sync::{Arc, RwLock};
 
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
-/// Global cache for currency exchange rates relative to EUR
-/// Maps currency Unit to its rate relative to EUR
-static CURRENCY_CACHE: RwLock<Option<HashMap<Unit, D128>>> = RwLock::new(None);
+/// Global cache for currency exchange rates, used by [`eval`](crate::eval)
+static CURRENCY_CACHE: RwLock<Option<Arc<ExchangeRates>>> = RwLock::new(None);
 
 /// The base currency for all exchange rates (EUR)
 pub const BASE_CURRENCY: Unit = Unit::EUR;
 
+/// What to do when an expression contains a currency
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CurrencyPolicy {
+	/// Use the global currency cache if the [`Context`] has no exchange rates,
+	/// and fetch them if that's empty too. On wasm, the rates must be loaded
+	/// with `init_currency_cache_with_json`.
+	#[default]
+	Fetch,
+	/// Only use exchange rates that have already been loaded
+	Offline,
+	/// Return an error when converting between currencies
+	Disabled,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct CurrencyRate {
 	#[allow(dead_code)]
@@ -26,24 +42,62 @@ pub struct CurrencyRate {
 	pub rate: serde_json::Number,
 }
 
-pub fn set_currency_cache(rates: Vec<CurrencyRate>) -> Result<(), String> {
-	let mut cache = HashMap::with_capacity(rates.len() + 1);
+/// Exchange rates of currencies relative to [`BASE_CURRENCY`]
+#[derive(Clone, Debug, Default)]
+pub struct ExchangeRates {
+	rates: HashMap<Unit, D128>,
+}
+impl ExchangeRates {
+	/// Create exchange rates from [`CurrencyRate`]s, like the ones returned by the Frankfurter API
+	pub fn from_rates(rates: Vec<CurrencyRate>) -> Result<ExchangeRates, String> {
+		let mut map = HashMap::with_capacity(rates.len() + 1);
 
-	// Add EUR as base
-	cache.insert(BASE_CURRENCY, D128::from(1));
+		// Add EUR as base
+		map.insert(BASE_CURRENCY, D128::from(1));
 
-	for entry in rates {
-		if entry.base != "EUR" {
-			return Err("Exchange rate base currency must be EUR".to_string());
+		for entry in rates {
+			if entry.base != "EUR" {
+				return Err("Exchange rate base currency must be EUR".to_string());
+			}
+			if let Ok(quote_unit) = currency_code_to_unit(&entry.quote.to_ascii_lowercase()) {
+				let rate_str = entry.rate.to_string();
+				let rate = D128::parse_str(&rate_str, DecimalContext::default());
+				map.insert(quote_unit, rate);
+			}
 		}
-		if let Ok(quote_unit) = currency_code_to_unit(&entry.quote.to_ascii_lowercase()) {
-			let rate_str = entry.rate.to_string();
-			let rate = D128::parse_str(&rate_str, Context::default());
-			cache.insert(quote_unit, rate);
+		Ok(ExchangeRates { rates: map })
+	}
+	/// Fetch the latest exchange rates from the Frankfurter API
+	#[cfg(not(target_arch = "wasm32"))]
+	pub fn fetch() -> Result<ExchangeRates, String> {
+		ExchangeRates::from_rates(fetch_currency_rates()?)
+	}
+	/// Get the exchange rate from one currency to another
+	/// Both currencies must be currency units
+	pub fn get(&self, from: Unit, to: Unit) -> Result<D128, Error> {
+		if from == to {
+			return Ok(D128::from(1));
 		}
+
+		// Get rates relative to EUR
+		let from_rate = self
+			.rates
+			.get(&from)
+			.ok_or_else(|| unavailable(from, "No exchange rate found".to_string()))?;
+		let to_rate = self
+			.rates
+			.get(&to)
+			.ok_or_else(|| unavailable(to, "No exchange rate found".to_string()))?;
+
+		let rate = *to_rate / *from_rate;
+		let rounded_rate = crate::round_to_sig_figs(rate, 6);
+		Ok(rounded_rate)
 	}
+}
 
-	*CURRENCY_CACHE.write().unwrap() = Some(cache);
+pub fn set_currency_cache(rates: Vec<CurrencyRate>) -> Result<(), String> {
+	let exchange_rates = ExchangeRates::from_rates(rates)?;
+	*CURRENCY_CACHE.write().unwrap() = Some(Arc::new(exchange_rates));
 	Ok(())
 }
 
@@ -53,47 +107,129 @@ pub fn initialize_currency_cache() -> Result<(), String> {
 		return Ok(()); // Already initialized
 	}
 
-	let rates = fetch_currency_rates()?;
-	set_currency_cache(rates)?;
+	let exchange_rates = ExchangeRates::fetch()?;
+	*CURRENCY_CACHE.write().unwrap() = Some(Arc::new(exchange_rates));
 	Ok(())
 }
 
-/// Limit the sig figs (precision) of the number
-fn round_to_sig_figs(x: D128, sig_figs: i32) -> D128 {
-	if x.is_zero() {
-		return x;
+/// The exchange rates in the global cache, if they have been loaded
+pub(crate) fn cached_rates() -> Option<Arc<ExchangeRates>> {
+	CURRENCY_CACHE.read().unwrap().clone()
+}
+
+/// Store exchange rates in the global cache, unless it already has some
+pub(crate) fn cache_rates(exchange_rates: &Arc<ExchangeRates>) {
+	let mut cache = CURRENCY_CACHE.write().unwrap();
+	if cache.is_none() {
+		*cache = Some(exchange_rates.clone());
 	}
-	let mag: i32 = x.log10().floor().try_into().unwrap();
-	let shift = sig_figs - 1 - mag;
-	let factor = D128::TEN.powi(shift);
-	(x * factor).round(0) / factor
 }
 
-/// Get the exchange rate from one currency to another
-/// Both currencies must be currency units
-pub fn get_exchange_rate(from: Unit, to: Unit) -> Result<D128, String> {
-	if from == to {
-		return Ok(D128::from(1));
+/// Find the currencies in an [`AstNode`], after ambiguities have been resolved.
+/// The bodies of called functions are searched once each, with the span of the call.
+fn find_currencies<'a>(
+	ast: &AstNode,
+	context: &'a Context,
+	currencies: &mut Vec<(Unit, Span)>,
+	called: &mut Vec<&'a str>,
+) {
+	let units = match &ast.token {
+		Token::Unit(units) => Some(units),
+		Token::Identifier(name) if ast.children.is_empty() => {
+			context.variable(name).map(|number| &number.unit)
+		}
+		_ => None,
+	};
+	if let Some(units) = units {
+		for (unit, _) in units {
+			if unit.category() == UnitType::Currency && !currencies.iter().any(|(c, _)| c == unit) {
+				currencies.push((unit.clone(), ast.span));
+			}
+		}
+	}
+	if let Token::Identifier(name) = &ast.token
+		&& !ast.children.is_empty()
+		&& let Some((name, function)) = context.functions.get_key_value(name)
+		&& !called.contains(&name.as_str())
+	{
+		called.push(name);
+		let mut body_currencies = Vec::new();
+		find_currencies(&function.body, context, &mut body_currencies, called);
+		for (unit, _) in body_currencies {
+			if !currencies.iter().any(|(c, _)| *c == unit) {
+				currencies.push((unit, ast.span));
+			}
+		}
+	}
+	for child in &ast.children {
+		find_currencies(child, context, currencies, called);
 	}
+}
 
-	// Ensure cache is initialized
-	#[cfg(not(target_arch = "wasm32"))]
-	initialize_currency_cache()?;
+/// Make sure the [`Context`] has exchange rates for an [`AstNode`], according to the [`CurrencyPolicy`]
+///
+/// Exchange rates are only needed if there are at least two different currencies.
+pub(crate) fn prepare_rates(
+	ast: &AstNode,
+	context: &mut Context,
+	policy: CurrencyPolicy,
+) -> Result<(), Error> {
+	let mut currencies = Vec::new();
+	find_currencies(ast, context, &mut currencies, &mut Vec::new());
+	load_rates(&currencies, context, policy)
+}
 
-	let cache = CURRENCY_CACHE.read().unwrap();
-	let cache = cache.as_ref().unwrap();
+/// Make sure the [`Context`] has exchange rates for a list of currencies,
+/// according to the [`CurrencyPolicy`]. The span of the second currency is
+/// used for errors.
+pub(crate) fn load_rates(
+	currencies: &[(Unit, Span)],
+	context: &mut Context,
+	policy: CurrencyPolicy,
+) -> Result<(), Error> {
+	let Some((currency, span)) = currencies.get(1) else {
+		return Ok(());
+	};
+	match policy {
+		CurrencyPolicy::Fetch => {
+			if context.exchange_rates.is_none() {
+				context.exchange_rates = cached_rates();
+			}
+			#[cfg(not(target_arch = "wasm32"))]
+			if context.exchange_rates.is_none() {
+				let exchange_rates = ExchangeRates::fetch()
+					.map_err(|reason| unavailable(currency.clone(), reason).with_span(*span))?;
+				context.exchange_rates = Some(Arc::new(exchange_rates));
+			}
+			Ok(())
+		}
+		CurrencyPolicy::Offline => Ok(()),
+		CurrencyPolicy::Disabled => Err(unavailable(
+			currency.clone(),
+			"Currency conversion is disabled".to_string(),
+		)
+		.with_span(*span)),
+	}
+}
 
-	// Get rates relative to EUR
-	let from_rate = cache
-		.get(&from)
-		.ok_or_else(|| format!("No exchange rate found for {:?}", from))?;
-	let to_rate = cache
-		.get(&to)
-		.ok_or_else(|| format!("No exchange rate found for {:?}", to))?;
+/// Get the exchange rate from one currency to another, using the global cache
+/// Both currencies must be currency units
+///
+/// The exchange rates must already be loaded, for example with [`set_currency_cache`].
+pub fn get_exchange_rate(from: Unit, to: Unit) -> Result<D128, Error> {
+	Context {
+		exchange_rates: cached_rates(),
+		..Context::default()
+	}
+	.exchange_rate(from, to)
+}
 
-	let rate = *to_rate / *from_rate;
-	let rounded_rate = round_to_sig_figs(rate, 6);
-	Ok(rounded_rate)
+pub(crate) fn unavailable(currency: Unit, reason: String) -> Error {
+	Error::CurrencyUnavailable {
+		currency,
+		reason,
+		span: Span::default(),
+	}
 }
 
 /// Fetch currency rates from the Frankfurter API (native version)
@@ -0,0 +1,440 @@
+use crate::evaluator::to_i128;
+use crate::rational::Rational;
+use crate::units::Unit::{self, *};
+use crate::units::{UnitType, convert};
+use crate::{Context, Error, Number};
+use fastnum::decimal::RoundingMode;
+use jiff::civil::{Date, DateTime, Time};
+use jiff::tz::TimeZone;
+use jiff::{SignedDuration, Span, Zoned};
+use std::fmt;
+
+/// Where `now` and `today` get the current time from
+#[derive(Clone, Debug, Default)]
+pub enum Clock {
+	/// The time of the system, in the system time zone
+	#[default]
+	System,
+	/// A fixed time, useful for tests
+	Fixed(Zoned),
+}
+impl Clock {
+	/// The current time
+	pub fn now(&self) -> Zoned {
+		match self {
+			Clock::System => Zoned::now(),
+			Clock::Fixed(now) => now.clone(),
+		}
+	}
+}
+
+/// A calendar date like `2026-10-16`, or a date with a time of day like
+/// `2026-10-16 14:30`, optionally in a time zone
+#[derive(Clone, Debug, PartialEq)]
+pub enum DateValue {
+	Date(Date),
+	DateTime(DateTime),
+	Zoned(Zoned),
+}
+impl DateValue {
+	/// The date and time, with midnight as the time of a date
+	fn datetime(&self) -> DateTime {
+		match self {
+			DateValue::Date(date) => date.to_datetime(Time::midnight()),
+			DateValue::DateTime(datetime) => *datetime,
+			DateValue::Zoned(zoned) => zoned.datetime(),
+		}
+	}
+	/// The date and time in a time zone. A date without a time zone is in
+	/// `local`, and one with a time zone is converted to the time zone.
+	pub(crate) fn to_zoned(&self, local: &TimeZone, zone: &TimeZone) -> Result<Zoned, Error> {
+		match self {
+			DateValue::Zoned(zoned) => Ok(zoned.with_time_zone(zone.clone())),
+			date => date
+				.datetime()
+				.to_zoned(local.clone())
+				.map(|zoned| zoned.with_time_zone(zone.clone()))
+				.map_err(|_| out_of_range()),
+		}
+	}
+}
+impl fmt::Display for DateValue {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			DateValue::Date(date) => write!(f, "{date}"),
+			DateValue::DateTime(datetime) => {
+				write!(f, "{} {}", datetime.date(), TimeOfDay(datetime.time()))
+			}
+			DateValue::Zoned(zoned) => {
+				let info = zoned.time_zone().to_offset_info(zoned.timestamp());
+				write!(
+					f,
+					"{} {} {}",
+					zoned.date(),
+					TimeOfDay(zoned.time()),
+					info.abbreviation()
+				)
+			}
+		}
+	}
+}
+
+/// Shows a time like `14:30`, with seconds only if there are any
+pub(crate) struct TimeOfDay(pub(crate) Time);
+impl fmt::Display for TimeOfDay {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let time = self.0;
+		match time.second() == 0 && time.subsec_nanosecond() == 0 {
+			true => write!(f, "{:02}:{:02}", time.hour(), time.minute()),
+			false => write!(f, "{time}"),
+		}
+	}
+}
+
+const NANOSECONDS_PER_DAY: i128 = 86_400_000_000_000;
+
+fn out_of_range() -> Error {
+	Error::domain("The date is out of range")
+}
+
+/// How many calendar months a unit is, for units that are added to dates by
+/// the calendar instead of by their average length
+fn calendar_months(unit: &Unit) -> Option<i64> {
+	match unit {
+		Month => Some(1),
+		Quarter => Some(3),
+		Year => Some(12),
+		Decade => Some(120),
+		Century => Some(1200),
+		Millennium => Some(12000),
+		_ => None,
+	}
+}
+
+/// Add a duration like `3 days` to a date, or subtract it.
+///
+/// Months and years are added by the calendar, so a month after January 31 is
+/// the last day of February. Other durations are added by their length.
+pub(crate) fn add_duration(
+	date: DateValue,
+	duration: &Number,
+	subtract: bool,
+	context: &Context,
+) -> Result<DateValue, Error> {
+	let unit = match duration.unit.as_slice() {
+		[(unit, 1)] if unit.category() == UnitType::Time => unit,
+		_ => {
+			return Err(Error::domain(
+				"Only durations can be added to or subtracted from dates",
+			));
+		}
+	};
+	let duration = match subtract {
+		true => duration.map(|value| -value, Rational::checked_neg, duration.unit.clone()),
+		false => duration.clone(),
+	};
+
+	if let Some(months) = calendar_months(unit) {
+		let whole = whole_number(&duration).ok_or_else(|| {
+			Error::domain("Months and years can only be added to dates in whole numbers")
+		})?;
+		let span = whole
+			.checked_mul(months)
+			.and_then(|months| Span::new().try_months(months).ok())
+			.ok_or_else(out_of_range)?;
+		return add_span(date, span);
+	}
+	// days in a time zone are calendar days, which are 23 or 25 hours long when
+	// the clocks change
+	if let DateValue::Zoned(_) = date
+		&& let Some(days) = calendar_days(unit)
+		&& let Some(whole) = whole_number(&duration)
+	{
+		let span = whole
+			.checked_mul(days)
+			.and_then(|days| Span::new().try_days(days).ok())
+			.ok_or_else(out_of_range)?;
+		return add_span(date, span);
+	}
+
+	let nanoseconds = convert(duration, vec![(Nanosecond, 1)], context)?;
+	let nanoseconds = nanoseconds
+		.value
+		.with_rounding_mode(RoundingMode::HalfUp)
+		.round(0);
+	let nanoseconds = to_i128(nanoseconds).ok_or_else(out_of_range)?;
+	// a date stays a date when whole days are added
+	if let DateValue::Date(_) = date
+		&& nanoseconds % NANOSECONDS_PER_DAY == 0
+	{
+		let days = i64::try_from(nanoseconds / NANOSECONDS_PER_DAY).map_err(|_| out_of_range())?;
+		let span = Span::new().try_days(days).map_err(|_| out_of_range())?;
+		return add_span(date, span);
+	}
+	let duration = SignedDuration::try_from_nanos_i128(nanoseconds).ok_or_else(out_of_range)?;
+	let result = match date {
+		DateValue::Zoned(zoned) => zoned.checked_add(duration).map(DateValue::Zoned),
+		date => date
+			.datetime()
+			.checked_add(duration)
+			.map(DateValue::DateTime),
+	};
+	result.map_err(|_| out_of_range())
+}
+
+fn whole_number(number: &Number) -> Option<i64> {
+	let rational = number.rational.filter(Rational::is_integer)?;
+	i64::try_from(rational.numerator()).ok()
+}
+
+/// How many calendar days a unit is, for units that are added to dates in a
+/// time zone by the calendar
+fn calendar_days(unit: &Unit) -> Option<i64> {
+	match unit {
+		Day => Some(1),
+		Week => Some(7),
+		_ => None,
+	}
+}
+
+fn add_span(date: DateValue, span: Span) -> Result<DateValue, Error> {
+	let result = match date {
+		DateValue::Date(date) => date.checked_add(span).map(DateValue::Date),
+		DateValue::DateTime(datetime) => datetime.checked_add(span).map(DateValue::DateTime),
+		DateValue::Zoned(zoned) => zoned.checked_add(span).map(DateValue::Zoned),
+	};
+	result.map_err(|_| out_of_range())
+}
+
+/// The duration from one date to another, in the largest of days, hours,
+/// minutes and seconds that it's a whole number of. If only one of the dates
+/// has a time zone, the other one is in `local`.
+pub(crate) fn duration_between(
+	start: &DateValue,
+	end: &DateValue,
+	local: &TimeZone,
+) -> Result<Number, Error> {
+	let duration = match (start, end) {
+		(DateValue::Zoned(_), _) | (_, DateValue::Zoned(_)) => {
+			let start = start.to_zoned(local, local)?;
+			end.to_zoned(local, local)?.duration_since(&start)
+		}
+		_ => end.datetime().duration_since(start.datetime()),
+	};
+	let nanoseconds = duration.as_nanos();
+	let (unit, unit_nanoseconds) = [
+		(Day, NANOSECONDS_PER_DAY),
+		(Hour, 3_600_000_000_000),
+		(Minute, 60_000_000_000),
+	]
+	.into_iter()
+	.find(|(_, unit_nanoseconds)| nanoseconds % unit_nanoseconds == 0)
+	.unwrap_or((Second, 1_000_000_000));
+	let rational = Rational::new(nanoseconds, unit_nanoseconds).ok_or_else(out_of_range)?;
+	Ok(Number::from_rational(rational, vec![(unit, 1)]))
+}
+
+/// Read a date at the start of the input, like `2026-10-16`, `16 Oct 2026` or
+/// `Oct 16, 2026`, optionally followed by a time like `14:30`.
+///
+/// Returns the date and the length of it in bytes. The date is an error if it
+/// looks like a date but doesn't exist, like `2026-02-30`.
+pub(crate) fn parse_date(input: &str) -> Option<(Result<DateValue, jiff::Error>, usize)> {
+	let (date, rest) = parse_iso_date(input)
+		.or_else(|| parse_day_month_year(input))
+		.or_else(|| parse_month_day_year(input))?;
+	// a time after the date, like `2026-10-16 14:30` or `2026-10-16T14:30`
+	let time = rest
+		.strip_prefix('T')
+		.or_else(|| rest.strip_prefix(' ').map(str::trim_start))
+		.and_then(parse_time);
+	let (value, rest) = match time {
+		Some((time, rest)) => {
+			let datetime = date.and_then(|date| Ok(date.to_datetime(time?)));
+			(datetime.map(DateValue::DateTime), rest)
+		}
+		None => (date.map(DateValue::Date), rest),
+	};
+	if rest.starts_with(|c: char| c.is_alphanumeric()) {
+		return None;
+	}
+	Some((value, input.len() - rest.len()))
+}
+
+/// Read a time of day at the start of the input, like `14:30` or `3pm`.
+///
+/// Returns the time and the length of it in bytes.
+pub(crate) fn parse_time_of_day(input: &str) -> Option<(Time, usize)> {
+	let (time, rest) = parse_time(input)?;
+	Some((time.ok()?, input.len() - rest.len()))
+}
+
+/// Read a time like `14:30`, `14:30:15.5`, `2:30 pm` or `2pm`
+fn parse_time(input: &str) -> Option<(Result<Time, jiff::Error>, &str)> {
+	let (mut hour, rest) = read_digits(input, 1, 2)?;
+	// the minutes can only be left out with am or pm, like in `3pm`
+	let has_minutes = rest.starts_with(':');
+	let (minute, mut rest) = match has_minutes {
+		true => read_digits(&rest[1..], 2, 2)?,
+		false => (0, rest),
+	};
+	let mut second = 0;
+	let mut nanosecond = 0;
+	if let Some((digits, after)) = rest.strip_prefix(':').and_then(|s| read_digits(s, 2, 2)) {
+		second = digits;
+		rest = after;
+		if let Some((fraction, after)) = rest.strip_prefix('.').and_then(|s| read_digits(s, 1, 9)) {
+			let length = rest.len() - after.len() - 1;
+			nanosecond = fraction * 10i64.pow(9 - length as u32);
+			rest = after;
+		}
+	}
+	let meridiem = rest.trim_start();
+	let meridiem_end = meridiem
+		.find(|c: char| !c.is_alphanumeric())
+		.unwrap_or(meridiem.len());
+	match meridiem[..meridiem_end].to_ascii_lowercase().as_str() {
+		"am" | "pm" if (1..=12).contains(&hour) => {
+			hour %= 12;
+			if meridiem.starts_with(['p', 'P']) {
+				hour += 12;
+			}
+			rest = &meridiem[meridiem_end..];
+		}
+		_ if !has_minutes => return None,
+		_ => {}
+	}
+	if rest.starts_with(|c: char| c.is_alphanumeric()) {
+		return None;
+	}
+	let time = Time::new(
+		hour.try_into().ok()?,
+		minute.try_into().ok()?,
+		second.try_into().ok()?,
+		nanosecond.try_into().ok()?,
+	);
+	Some((time, rest))
+}
+
+type DatePrefix<'a> = (Result<Date, jiff::Error>, &'a str);
+
+/// Read a date like `2026-10-16`
+fn parse_iso_date(input: &str) -> Option<DatePrefix<'_>> {
+	let (year, rest) = read_digits(input, 4, 4)?;
+	let (month, rest) = read_digits(rest.strip_prefix('-')?, 2, 2)?;
+	let (day, rest) = read_digits(rest.strip_prefix('-')?, 2, 2)?;
+	Some((new_date(year, month, day)?, rest))
+}
+
+/// Read a date like `16 Oct 2026` or `16 October 2026`
+fn parse_day_month_year(input: &str) -> Option<DatePrefix<'_>> {
+	let (day, rest) = read_digits(input, 1, 2)?;
+	let (month, rest) = read_month(rest.strip_prefix(' ')?.trim_start())?;
+	let (year, rest) = read_digits(rest.strip_prefix(' ')?.trim_start(), 4, 4)?;
+	Some((new_date(year, month, day)?, rest))
+}
+
+/// Read a date like `Oct 16 2026` or `October 16, 2026`
+fn parse_month_day_year(input: &str) -> Option<DatePrefix<'_>> {
+	let (month, rest) = read_month(input)?;
+	let (day, rest) = read_digits(rest.strip_prefix(' ')?.trim_start(), 1, 2)?;
+	let rest = rest.strip_prefix(',').unwrap_or(rest);
+	let (year, rest) = read_digits(rest.strip_prefix(' ')?.trim_start(), 4, 4)?;
+	Some((new_date(year, month, day)?, rest))
+}
+
+fn new_date(year: i64, month: i64, day: i64) -> Option<Result<Date, jiff::Error>> {
+	Some(Date::new(
+		year.try_into().ok()?,
+		month.try_into().ok()?,
+		day.try_into().ok()?,
+	))
+}
+
+/// Read from `min` to `max` ASCII digits. There can't be more digits after them.
+fn read_digits(input: &str, min: usize, max: usize) -> Option<(i64, &str)> {
+	let length = input
+		.find(|c: char| !c.is_ascii_digit())
+		.unwrap_or(input.len());
+	if !(min..=max).contains(&length) {
+		return None;
+	}
+	Some((input[..length].parse().ok()?, &input[length..]))
+}
+
+const MONTHS: [&str; 12] = [
+	"january",
+	"february",
+	"march",
+	"april",
+	"may",
+	"june",
+	"july",
+	"august",
+	"september",
+	"october",
+	"november",
+	"december",
+];
+
+/// Read the name of a month like `October`, or a short name like `Oct`
+fn read_month(input: &str) -> Option<(i64, &str)> {
+	let length = input
+		.find(|c: char| !c.is_ascii_alphabetic())
+		.unwrap_or(input.len());
+	let word = input[..length].to_ascii_lowercase();
+	let index = MONTHS.iter().position(|month| {
+		*month == word || word == month[..3] || (word == "sept" && *month == "september")
+	})?;
+	Some((index as i64 + 1, &input[length..]))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use jiff::civil::date;
+
+	fn parse(input: &str) -> Option<(String, usize)> {
+		parse_date(input).map(|(date, length)| (date.unwrap().to_string(), length))
+	}
+
+	#[test]
+	fn test_parse_date() {
+		assert_eq!(
+			parse("2026-10-16 + 1"),
+			Some(("2026-10-16".to_string(), 10))
+		);
+		assert_eq!(
+			parse("2026-10-16T14:30:05.25"),
+			Some(("2026-10-16 14:30:05.25".to_string(), 22))
+		);
+		assert_eq!(
+			parse("16 Oct 2026 2:30 pm"),
+			Some(("2026-10-16 14:30".to_string(), 19))
+		);
+		assert_eq!(
+			parse("September 5, 2026"),
+			Some(("2026-09-05".to_string(), 17))
+		);
+		assert_eq!(
+			parse("2026-10-16 14 m"),
+			Some(("2026-10-16".to_string(), 10))
+		);
+		assert_eq!(
+			parse("2026-10-16 3pm"),
+			Some(("2026-10-16 15:00".to_string(), 14))
+		);
+		assert_eq!(
+			parse("2026-10-16 3 days"),
+			Some(("2026-10-16".to_string(), 10))
+		);
+		assert_eq!(parse("2026-10-160"), None);
+		assert_eq!(parse("16 oct"), None);
+		assert_eq!(parse("10 mar 20260"), None);
+		assert!(parse_date("2026-02-30").unwrap().0.is_err());
+		assert_eq!(
+			DateValue::DateTime(date(2026, 1, 2).at(3, 4, 0, 0)).to_string(),
+			"2026-01-02 03:04"
+		);
+	}
+}
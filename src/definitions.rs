@@ -0,0 +1,153 @@
+use crate::units::{Unit, define_unit};
+use crate::{Context, CurrencyPolicy, Error, EvalOptions, Span, Value, eval_with_context};
+use std::fmt;
+use std::path::Path;
+
+/// An error from loading a unit definition file
+#[derive(Debug)]
+pub enum DefinitionError {
+	/// The file couldn't be read
+	Io(std::io::Error),
+	/// A line couldn't be parsed or defined. Lines are numbered from 1, and
+	/// the span of the error is relative to the line.
+	Line { line: usize, error: Error },
+}
+impl fmt::Display for DefinitionError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			DefinitionError::Io(error) => write!(f, "{error}"),
+			DefinitionError::Line { line, error } => write!(f, "Line {line}: {error}"),
+		}
+	}
+}
+impl std::error::Error for DefinitionError {}
+
+/// Define the units in a unit definition file in the context, and return them.
+///
+/// Each line defines a unit in terms of existing units, like `furlong = 220 yd`
+/// or `mAh = milliampere * hour`. The name can be followed by a `/` and the
+/// plural, and then a comma separated list of aliases. Lines starting with `#`
+/// are comments.
+///
+/// Lines can use the units defined above them. Loading stops at the first line
+/// with an error, and the units defined before it are kept.
+///
+/// Example:
+/// ```rust
+/// use cpc::{load_units, eval_with_context, Context, EvalOptions};
+///
+/// let definitions = "league/leagues, lea = 3 mi\nchain = 22 yd\nfurlong = 10 chain";
+/// let mut context = Context::new("en-US");
+/// load_units(definitions, &mut context).unwrap();
+/// let output = eval_with_context("6 mi to lea", &EvalOptions::new(), &mut context).unwrap();
+/// assert_eq!(output.value.to_string(), "2 leagues");
+/// let output = eval_with_context("1 furlong to m", &EvalOptions::new(), &mut context).unwrap();
+/// assert_eq!(output.value.to_string(), "201.168 meters");
+/// ```
+pub fn load_units(source: &str, context: &mut Context) -> Result<Vec<Unit>, DefinitionError> {
+	let mut units = Vec::new();
+	for (index, line) in source.lines().enumerate() {
+		let trimmed = line.trim();
+		if trimmed.is_empty() || trimmed.starts_with('#') {
+			continue;
+		}
+		let unit = define_line(line, context).map_err(|error| DefinitionError::Line {
+			line: index + 1,
+			error,
+		})?;
+		units.push(unit);
+	}
+	Ok(units)
+}
+
+/// Like [`load_units`], but reads the definitions from a file
+pub fn load_units_from_file(
+	path: impl AsRef<Path>,
+	context: &mut Context,
+) -> Result<Vec<Unit>, DefinitionError> {
+	let source = std::fs::read_to_string(path).map_err(DefinitionError::Io)?;
+	load_units(&source, context)
+}
+
+/// Define the unit on one line, like `league/leagues, lea = 3 mi`
+fn define_line(line: &str, context: &mut Context) -> Result<Unit, Error> {
+	let Some((names, definition)) = line.split_once('=') else {
+		return Err(
+			Error::invalid_definition("Expected a definition like `furlong = 220 yd`")
+				.with_span(Span::new(0, line.len())),
+		);
+	};
+	// spans of the definition are relative to the line
+	let offset = names.len() + 1;
+	let names_span = Span::new(0, names.trim_end().len());
+
+	let mut names = names.split(',').map(str::trim);
+	let first = names.next().unwrap_or_default();
+	let (name, plural) = match first.split_once('/') {
+		Some((name, plural)) => (name.trim(), Some(plural.trim())),
+		None => (first, None),
+	};
+	let aliases: Vec<&str> = names.collect();
+
+	// a context with only the units, so the definition can't use variables
+	let mut definition_context = Context {
+		custom_units: context.custom_units.clone(),
+		..Context::default()
+	};
+	let options = EvalOptions::new().currency_policy(CurrencyPolicy::Disabled);
+	let output = eval_with_context(definition, &options, &mut definition_context).map_err(|e| {
+		let span = e.span();
+		e.with_span(Span::new(span.start + offset, span.end + offset))
+	})?;
+	let Value::Number(number) = output.value else {
+		let span = Span::new(offset, line.len());
+		return Err(Error::invalid_definition("Expected a number with a unit").with_span(span));
+	};
+	define_unit(context, name, plural, &aliases, number).map_err(|e| e.with_span(names_span))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_load_units() {
+		let mut context = Context::new("en-US");
+		let units = load_units(
+			"# distances\n\
+			\n\
+			rod/rods, perch = 5.5 yd\n\
+			acre_length = 40 rod\n\
+			mAh = milliampere * hour",
+			&mut context,
+		)
+		.unwrap();
+		assert_eq!(units.len(), 3);
+		let mut eval = |input| {
+			eval_with_context(input, &EvalOptions::new(), &mut context)
+				.unwrap()
+				.value
+				.to_string()
+		};
+		assert_eq!(eval("2 perch to ft"), "33 feet");
+		assert_eq!(eval("1 acre_length to rods"), "40 rods");
+		assert_eq!(eval("1 mAh to mA s"), "3600 second * milliamperes");
+
+		let mut context = Context::new("en-US");
+		let error = load_units("thingy = 2 m\n\nwidget = 3 blarg", &mut context).unwrap_err();
+		let DefinitionError::Line { line, error } = error else {
+			panic!("{error}");
+		};
+		assert_eq!(line, 3);
+		assert_eq!(error.span(), Span::new(11, 16));
+		assert_eq!(context.custom_units.len(), 1);
+
+		let error = load_units("# units\nkm = 1000 m", &mut context).unwrap_err();
+		assert_eq!(
+			error.to_string(),
+			"Line 2: \"km\" can't be used as a unit name"
+		);
+		assert!(load_units("gadget 3 m", &mut context).is_err());
+		assert!(load_units_from_file("/nonexistent/units.txt", &mut context).is_err());
+	}
+}
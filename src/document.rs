@@ -0,0 +1,242 @@
+use crate::currency::{self, load_rates};
+use crate::units::{UnitType, add};
+use crate::{
+	Context, Error, EvalOptions, FunctionIdentifier, Number, Operator, Span, Token, Value,
+};
+use crate::{eval_with_context, parser::AstNode};
+use fastnum::dec128 as d;
+use std::fmt;
+
+/// The result of one line in [`eval_document`]
+#[derive(Clone, Debug)]
+pub enum LineResult {
+	/// A line with nothing but whitespace
+	Blank,
+	/// A line starting with `#` or `//`, with the text after it
+	Comment(String),
+	/// A line that was evaluated
+	Value(Value),
+	/// A line that couldn't be evaluated. The span is relative to the line.
+	Error(Error),
+}
+impl fmt::Display for LineResult {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			LineResult::Blank => Ok(()),
+			LineResult::Comment(comment) => write!(f, "{comment}"),
+			LineResult::Value(value) => write!(f, "{value}"),
+			LineResult::Error(error) => write!(f, "Error: {error}"),
+		}
+	}
+}
+
+/// Words that refer to the sum of the lines above
+const SUM_WORDS: [&str; 2] = ["sum", "total"];
+
+/// Evaluates a document with one expression per line, like a notepad
+/// calculator.
+///
+/// Every line gets a [`LineResult`], and a line that fails doesn't stop the
+/// lines after it. Lines can refer to earlier results:
+/// - by the name of a variable, like `rent = 1200 EUR`
+/// - with `line3` for the result of the third line
+/// - with `prev` for the result of the closest line above
+/// - with `sum` or `total` for the lines above added together, up to the
+///   previous blank line. Lines that use `sum` or `total`, and lines that
+///   aren't a single number, aren't included.
+///
+/// Example:
+/// ```rust
+/// use cpc::eval_document;
+///
+/// let results = eval_document("# trip\n12 km\n800 m\nsum to km");
+/// assert_eq!(results[3].to_string(), "12.8 kilometers");
+/// ```
+pub fn eval_document(input: &str) -> Vec<LineResult> {
+	let mut context = Context::from_globals();
+	let results = eval_document_with_context(input, &EvalOptions::new(), &mut context);
+	if let Some(exchange_rates) = &context.exchange_rates {
+		currency::cache_rates(exchange_rates);
+	}
+	results
+}
+
+/// Like [`eval_document`], but with [`EvalOptions`] and a [`Context`] instead
+/// of global state
+pub fn eval_document_with_context(
+	input: &str,
+	options: &EvalOptions,
+	context: &mut Context,
+) -> Vec<LineResult> {
+	let mut results = Vec::new();
+	// results since the last blank line, for sum and total
+	let mut block: Vec<Number> = Vec::new();
+	// names the document assigns itself, which shouldn't be overwritten
+	let mut assigned: Vec<String> = Vec::new();
+
+	for (index, line) in input.lines().enumerate() {
+		let trimmed = line.trim();
+		if trimmed.is_empty() {
+			block.clear();
+			results.push(LineResult::Blank);
+			continue;
+		}
+		if let Some(comment) = trimmed.strip_prefix('#').or(trimmed.strip_prefix("//")) {
+			results.push(LineResult::Comment(comment.trim().to_string()));
+			continue;
+		}
+
+		let sum = sum_block(&block, options, context);
+		for word in SUM_WORDS {
+			if assigned.iter().any(|name| name == word) {
+				continue;
+			}
+			match &sum {
+				Ok(sum) => context.variables.insert(word.to_string(), sum.clone()),
+				Err(_) => context.variables.remove(word),
+			};
+		}
+
+		let output = match eval_with_context(line, options, context) {
+			Ok(output) => output,
+			Err(error) => {
+				let error = match (&error, &sum) {
+					(Error::UnknownWord { word, span }, Err(sum_error))
+						if SUM_WORDS.contains(&word.as_str()) =>
+					{
+						sum_error.clone().with_span(*span)
+					}
+					_ => error,
+				};
+				results.push(LineResult::Error(error));
+				continue;
+			}
+		};
+
+		if let Some(name) = assigned_name(&output.ast)
+			&& !assigned.contains(name)
+		{
+			assigned.push(name.clone());
+		}
+		if let Value::Number(number) = &output.value {
+			// the running total, or the sum() function like `sum([1, 2, 3])`
+			let uses_sum = output.tokens.iter().any(|t| match &t.token {
+				Token::Identifier(name) => {
+					SUM_WORDS.contains(&name.as_str()) && !assigned.contains(name)
+				}
+				Token::FunctionIdentifier(FunctionIdentifier::Sum) => true,
+				_ => false,
+			});
+			if !uses_sum {
+				block.push(number.clone());
+			}
+			context
+				.variables
+				.insert(format!("line{}", index + 1), number.clone());
+			context.variables.insert("prev".to_string(), number.clone());
+		}
+		results.push(LineResult::Value(output.value));
+	}
+	results
+}
+
+/// The name of the variable an assignment like `rent = 1200 EUR` assigns to
+fn assigned_name(ast: &AstNode) -> Option<&String> {
+	match (&ast.token, ast.children.first().map(|c| &c.token)) {
+		(Token::Operator(Operator::Assign), Some(Token::Identifier(name))) => Some(name),
+		_ => None,
+	}
+}
+
+/// Add up the numbers in a block, converting units as needed
+fn sum_block(
+	block: &[Number],
+	options: &EvalOptions,
+	context: &mut Context,
+) -> Result<Number, Error> {
+	let mut currencies = Vec::new();
+	for number in block {
+		for (unit, _) in &number.unit {
+			if unit.category() == UnitType::Currency && !currencies.iter().any(|(c, _)| c == unit) {
+				currencies.push((unit.clone(), Span::default()));
+			}
+		}
+	}
+	load_rates(&currencies, context, options.currency_policy)?;
+
+	let mut numbers = block.iter().cloned();
+	let Some(first) = numbers.next() else {
+		return Ok(Number::new_unitless(d!(0)));
+	};
+	numbers.try_fold(first, |sum, number| add(sum, number, context))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::units::Unit;
+
+	fn eval_lines(input: &str) -> Vec<String> {
+		let mut context = Context::new("en-US");
+		eval_document_with_context(input, &EvalOptions::new(), &mut context)
+			.iter()
+			.map(|result| result.to_string())
+			.collect()
+	}
+
+	#[test]
+	fn test_document() {
+		let document = "\
+			# groceries\n\
+			12 km\n\
+			\n\
+			3 m\n\
+			50 cm\n\
+			sum\n\
+			line4 * 2\n\
+			prev + 1 m\n\
+			x = 1 kg\n\
+			sum to cm\n\
+			// done";
+		assert_eq!(
+			eval_lines(document),
+			vec![
+				"groceries",
+				"12 kilometers",
+				"",
+				"3 meters",
+				"50 centimeters",
+				"350 centimeters",
+				"6 meters",
+				"7 meters",
+				"1 kilogram",
+				"Error: Cannot convert centimeters to kilograms",
+				"done",
+			],
+		);
+	}
+
+	#[test]
+	fn test_document_errors() {
+		let results = eval_lines("1 +* 2\nfoo\n2 m\nprev * 3\ntotal");
+		assert_eq!(results[0], "Error: Unexpected *");
+		assert_eq!(results[1], "Error: Unknown word: foo");
+		assert_eq!(results[3], "6 meters");
+		assert_eq!(results[4], "8 meters");
+
+		let results = eval_lines("1 m\nsum([1, 2, 3])\n[2, 3] m\nsum");
+		assert_eq!(results[1], "6");
+		assert_eq!(results[3], "1 meter");
+
+		let mut context = Context::new("en-US");
+		let results = eval_document_with_context(
+			"total = 5 kg\n2 kg\ntotal + 1 kg",
+			&EvalOptions::new(),
+			&mut context,
+		);
+		let LineResult::Value(Value::Number(number)) = &results[2] else {
+			panic!("{:?}", results[2]);
+		};
+		assert_eq!(number, &Number::with_basic_unit(d!(6), Unit::Kilogram));
+	}
+}
@@ -0,0 +1,232 @@
+use crate::evaluator::MAX_RANGE_LENGTH;
+use crate::units::Unit;
+use crate::{Number, Token};
+use fastnum::dec128 as d;
+use std::fmt;
+
+/// A byte range in the input string, like `3..7`.
+///
+/// The span is empty (`0..0`) if the position is unknown, for example when an
+/// error comes from calling a [`units`](crate::units) function directly.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+	/// Byte offset of the first character
+	pub start: usize,
+	/// Byte offset after the last character
+	pub end: usize,
+}
+impl Span {
+	pub const fn new(start: usize, end: usize) -> Span {
+		Span { start, end }
+	}
+	pub fn is_empty(&self) -> bool {
+		self.start == self.end
+	}
+}
+
+/// An error that occurred while lexing, parsing or evaluating an expression.
+///
+/// Every variant carries the [`Span`] of the input it refers to.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+	/// The input contains nothing to evaluate
+	EmptyInput { span: Span },
+	/// A character that can't be part of any token, like `@`
+	InvalidCharacter { character: String, span: Span },
+	/// A number that can't be read, like `1.2.3`
+	InvalidNumber { number: String, span: Span },
+	/// A date that doesn't exist, like `2026-02-30`
+	InvalidDate { date: String, span: Span },
+	/// A word that isn't a known unit, function, constant etc.
+	UnknownWord { word: String, span: Span },
+	/// A word like `kr` or `¥` that has no clear meaning in the current region
+	AmbiguousWord {
+		word: String,
+		region: String,
+		span: Span,
+	},
+	/// A token in a position where it isn't allowed, like the `)` in `3 + )`
+	UnexpectedToken { token: Token, span: Span },
+	/// The input ended where more was expected, like in `3 *`
+	UnexpectedEnd { span: Span },
+	/// Something other than a unit on the right side of a conversion, like `3 m to 4`
+	ExpectedUnit { span: Span },
+	/// Units that can't be converted into each other, like `1 m to kg`
+	IncompatibleUnits {
+		from: Vec<(Unit, isize)>,
+		to: Vec<(Unit, isize)>,
+		span: Span,
+	},
+	/// An operation that doesn't accept the unit it was given, like `sqrt(4 m)`
+	UnsupportedUnit {
+		operation: String,
+		unit: Vec<(Unit, isize)>,
+		span: Span,
+	},
+	/// An exchange rate that is missing or couldn't be fetched
+	CurrencyUnavailable {
+		currency: Unit,
+		reason: String,
+		span: Span,
+	},
+	/// A value outside of what an operation accepts, like `ln(-1)`
+	DomainError { message: String, span: Span },
+	/// A unit definition that can't be used, like one that redefines `m`
+	InvalidDefinition { message: String, span: Span },
+	/// A user-defined function called with the wrong number of arguments
+	ArityMismatch {
+		function: String,
+		expected: usize,
+		found: usize,
+		span: Span,
+	},
+	/// A user-defined function that calls itself too deeply, like `f(x) = f(x)`
+	RecursionLimit { function: String, span: Span },
+	/// Lists of different lengths in one expression, like `[1, 2] + [1, 2, 3]`
+	ListLengthMismatch {
+		expected: usize,
+		found: usize,
+		span: Span,
+	},
+	/// A range with too many elements, like `1..1e9`
+	RangeTooLarge { span: Span },
+}
+impl Error {
+	/// The part of the input this error refers to
+	pub fn span(&self) -> Span {
+		match self {
+			Error::EmptyInput { span }
+			| Error::InvalidCharacter { span, .. }
+			| Error::InvalidNumber { span, .. }
+			| Error::InvalidDate { span, .. }
+			| Error::UnknownWord { span, .. }
+			| Error::AmbiguousWord { span, .. }
+			| Error::UnexpectedToken { span, .. }
+			| Error::UnexpectedEnd { span }
+			| Error::ExpectedUnit { span }
+			| Error::IncompatibleUnits { span, .. }
+			| Error::UnsupportedUnit { span, .. }
+			| Error::CurrencyUnavailable { span, .. }
+			| Error::DomainError { span, .. }
+			| Error::InvalidDefinition { span, .. }
+			| Error::ArityMismatch { span, .. }
+			| Error::RecursionLimit { span, .. }
+			| Error::ListLengthMismatch { span, .. }
+			| Error::RangeTooLarge { span } => *span,
+		}
+	}
+	/// Replace the span of this error
+	pub fn with_span(mut self, new_span: Span) -> Error {
+		match &mut self {
+			Error::EmptyInput { span }
+			| Error::InvalidCharacter { span, .. }
+			| Error::InvalidNumber { span, .. }
+			| Error::InvalidDate { span, .. }
+			| Error::UnknownWord { span, .. }
+			| Error::AmbiguousWord { span, .. }
+			| Error::UnexpectedToken { span, .. }
+			| Error::UnexpectedEnd { span }
+			| Error::ExpectedUnit { span }
+			| Error::IncompatibleUnits { span, .. }
+			| Error::UnsupportedUnit { span, .. }
+			| Error::CurrencyUnavailable { span, .. }
+			| Error::DomainError { span, .. }
+			| Error::InvalidDefinition { span, .. }
+			| Error::ArityMismatch { span, .. }
+			| Error::RecursionLimit { span, .. }
+			| Error::ListLengthMismatch { span, .. }
+			| Error::RangeTooLarge { span } => *span = new_span,
+		}
+		self
+	}
+	pub(crate) fn incompatible_units(from: &[(Unit, isize)], to: &[(Unit, isize)]) -> Error {
+		Error::IncompatibleUnits {
+			from: from.to_vec(),
+			to: to.to_vec(),
+			span: Span::default(),
+		}
+	}
+	pub(crate) fn unsupported_unit(operation: &str, unit: &[(Unit, isize)]) -> Error {
+		Error::UnsupportedUnit {
+			operation: operation.to_string(),
+			unit: unit.to_vec(),
+			span: Span::default(),
+		}
+	}
+	pub(crate) fn domain(message: impl Into<String>) -> Error {
+		Error::DomainError {
+			message: message.into(),
+			span: Span::default(),
+		}
+	}
+	pub(crate) fn invalid_definition(message: impl Into<String>) -> Error {
+		Error::InvalidDefinition {
+			message: message.into(),
+			span: Span::default(),
+		}
+	}
+}
+
+fn unit_name(unit: &[(Unit, isize)]) -> String {
+	match unit.is_empty() {
+		true => "no unit".to_string(),
+		false => Number::with_unit(d!(0), unit.to_vec()).plural(),
+	}
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Error::EmptyInput { .. } => write!(f, "Input was empty"),
+			Error::InvalidCharacter { character, .. } => {
+				write!(f, "Invalid character: {character}")
+			}
+			Error::InvalidNumber { number, .. } => write!(f, "Invalid number: {number}"),
+			Error::InvalidDate { date, .. } => write!(f, "Invalid date: {date}"),
+			Error::UnknownWord { word, .. } => write!(f, "Unknown word: {word}"),
+			Error::AmbiguousWord { word, region, .. } => {
+				write!(f, "\"{word}\" is ambiguous in your region \"{region}\"")
+			}
+			Error::UnexpectedToken { token, .. } => write!(f, "Unexpected {token}"),
+			Error::UnexpectedEnd { .. } => write!(f, "Unexpected end of input"),
+			Error::ExpectedUnit { .. } => write!(f, "Expected a unit to convert to"),
+			Error::IncompatibleUnits { from, to, .. } => {
+				write!(f, "Cannot convert {} to {}", unit_name(from), unit_name(to))
+			}
+			Error::UnsupportedUnit {
+				operation, unit, ..
+			} => write!(f, "Unsupported unit for {operation}: {}", unit_name(unit)),
+			Error::CurrencyUnavailable {
+				currency, reason, ..
+			} => write!(f, "{} is unavailable: {reason}", currency.singular()),
+			Error::DomainError { message, .. } | Error::InvalidDefinition { message, .. } => {
+				write!(f, "{message}")
+			}
+			Error::ArityMismatch {
+				function,
+				expected,
+				found,
+				..
+			} => {
+				let s = if *expected == 1 { "" } else { "s" };
+				write!(
+					f,
+					"{function}() takes {expected} argument{s} but got {found}"
+				)
+			}
+			Error::RecursionLimit { function, .. } => {
+				write!(f, "Recursion limit reached in {function}()")
+			}
+			Error::ListLengthMismatch {
+				expected, found, ..
+			} => {
+				write!(f, "Lists have different lengths ({expected} and {found})")
+			}
+			Error::RangeTooLarge { .. } => {
+				write!(f, "Range has more than {MAX_RANGE_LENGTH} elements")
+			}
+		}
+	}
+}
+
+impl std::error::Error for Error {}
@@ -3,22 +3,275 @@ use crate::FunctionIdentifier::*;
 use crate::Operator::*;
 use crate::TextOperator::*;
 use crate::UnaryOperator::*;
+use crate::currency::{CurrencyPolicy, prepare_rates};
+use crate::datetime::{DateValue, add_duration, duration_between};
 use crate::lookup::{lookup_factorial, lookup_named_number};
 use crate::parser::AstNode;
+use crate::rational::Rational;
 use crate::units::Unit;
 use crate::units::UnitType;
 use crate::units::multiply_any;
 use crate::units::to_ideal_unit;
-use crate::units::{add, convert, divide, modulo, multiply, pow, subtract};
-use crate::{Number, Token};
-use fastnum::decimal::Context;
+use crate::units::{add, convert, divide, integer_divide, modulo, multiply, pow, subtract};
+use crate::{
+	Context, Error, FunctionIdentifier, Number, Operator, Span, Token, UserFunction, Value,
+};
+use fastnum::decimal::Context as DecimalContext;
 use fastnum::{D128, dec128 as d, decimal::RoundingMode};
+use std::collections::HashMap;
+use std::ops::Deref;
 
-/// Evaluate an [`AstNode`] into a [`Number`]
-pub fn evaluate(ast: &mut AstNode) -> Result<Number, String> {
-	resolve_ambiguities(ast, None);
-	let answer = evaluate_node(&ast)?;
-	Ok(answer)
+/// How deeply user-defined functions can call each other before evaluation is aborted
+const MAX_CALL_DEPTH: usize = 16;
+
+/// The most elements a range like `1..10` can have
+pub(crate) const MAX_RANGE_LENGTH: usize = 10_000;
+
+/// The names an expression is evaluated with: the variables and functions of
+/// the [`Context`], and the parameters of the user-defined function being
+/// called, if any.
+///
+/// A function body only sees its own parameters and the variables of the
+/// context, not the parameters of the function that called it.
+struct Scope<'a> {
+	context: &'a Context,
+	/// The arguments of the current function call, by parameter name
+	frame: Option<&'a HashMap<String, Number>>,
+	/// How many user-defined function calls deep the evaluation is
+	call_depth: usize,
+}
+impl<'a> Scope<'a> {
+	fn new(context: &'a Context) -> Scope<'a> {
+		Scope {
+			context,
+			frame: None,
+			call_depth: 0,
+		}
+	}
+	/// Look up a parameter of the current function call, or else a variable
+	fn variable(&self, name: &str) -> Option<&Number> {
+		self.frame
+			.and_then(|frame| frame.get(name))
+			.or_else(|| self.context.variable(name))
+	}
+}
+impl Deref for Scope<'_> {
+	type Target = Context;
+	fn deref(&self) -> &Context {
+		self.context
+	}
+}
+
+/// Evaluate an [`AstNode`] into a [`Value`]
+pub fn evaluate(
+	ast: &mut AstNode,
+	context: &mut Context,
+	currency_policy: CurrencyPolicy,
+) -> Result<Value, Error> {
+	resolve_ambiguities(ast, None, context);
+	prepare_rates(ast, context, currency_policy)?;
+	if let Token::Operator(Assign) = &ast.token {
+		return evaluate_assignment(ast, context);
+	}
+	let context = &Scope::new(context);
+	if is_date(ast) {
+		return Ok(Value::Date(evaluate_date(ast, context)?));
+	}
+	if let Some(numbers) = evaluate_broadcast(ast, context)? {
+		return Ok(Value::List(numbers));
+	}
+	// a conversion to an output format like `to dms`
+	if let Token::TextOperator(To) = &ast.token
+		&& let [left, right] = ast.children.as_slice()
+		&& let Token::Format(format) = &right.token
+	{
+		let number = evaluate_node(left, context)?;
+		let number = format
+			.prepare(number, context)
+			.map_err(|e| e.with_span(ast.span))?;
+		return Ok(Value::Formatted(number, format.clone()));
+	}
+	let answer = evaluate_node(ast, context)?;
+	Ok(Value::Number(answer))
+}
+
+/// Evaluate an assignment like `x = 5 km` and store the variable in the
+/// [`Context`], or store a function definition like `f(x) = x^2`
+fn evaluate_assignment(ast: &AstNode, context: &mut Context) -> Result<Value, Error> {
+	let (Some(name_node), Some(value_node)) = (ast.children.first(), ast.children.get(1)) else {
+		return Err(unexpected(&ast.token).with_span(ast.span));
+	};
+	let Token::Identifier(name) = &name_node.token else {
+		return Err(unexpected(&name_node.token).with_span(name_node.span));
+	};
+	if !name_node.children.is_empty() {
+		let mut parameters = Vec::new();
+		for parameter in &name_node.children {
+			match &parameter.token {
+				Token::Identifier(name) if parameters.contains(name) => {
+					return Err(Error::InvalidDefinition {
+						message: format!("The parameter \"{name}\" is used more than once"),
+						span: parameter.span,
+					});
+				}
+				Token::Identifier(name) => parameters.push(name.clone()),
+				token => return Err(unexpected(token).with_span(parameter.span)),
+			}
+		}
+		let signature = format!("{name}({})", parameters.join(", "));
+		let function = UserFunction {
+			parameters,
+			body: value_node.clone(),
+		};
+		context.functions.insert(name.clone(), function);
+		return Ok(Value::Function(signature));
+	}
+	let value = evaluate_node(value_node, &Scope::new(context))?;
+	context.variables.insert(name.clone(), value.clone());
+	Ok(Value::Number(value))
+}
+
+/// Call a user-defined function, with the arguments bound to its parameters
+fn call_function(
+	name: &str,
+	arguments: &[AstNode],
+	span: Span,
+	context: &Scope,
+) -> Result<Number, Error> {
+	let Some(function) = context.functions.get(name) else {
+		return Err(Error::UnknownWord {
+			word: name.to_string(),
+			span,
+		});
+	};
+	if arguments.len() != function.parameters.len() {
+		return Err(Error::ArityMismatch {
+			function: name.to_string(),
+			expected: function.parameters.len(),
+			found: arguments.len(),
+			span,
+		});
+	}
+	if context.call_depth >= MAX_CALL_DEPTH {
+		return Err(Error::RecursionLimit {
+			function: name.to_string(),
+			span,
+		});
+	}
+	let mut frame = HashMap::new();
+	for (parameter, argument) in function.parameters.iter().zip(arguments) {
+		frame.insert(parameter.clone(), evaluate_node(argument, context)?);
+	}
+	let scope = Scope {
+		context: context.context,
+		frame: Some(&frame),
+		call_depth: context.call_depth + 1,
+	};
+	// the body was parsed from a different input, so its spans don't apply here
+	evaluate_node(&function.body, &scope).map_err(|e| e.with_span(span))
+}
+
+/// Whether a node evaluates to a date, like `today + 90 days`. The difference
+/// of two dates is a duration, not a date.
+fn is_date(ast: &AstNode) -> bool {
+	match (&ast.token, ast.children.as_slice()) {
+		(Token::Date(_) | Token::Now | Token::Today | Token::Time(_), _) => true,
+		(Token::Paren | Token::TimeZone(_), [child]) => is_date(child),
+		(Token::TextOperator(To), [left, right]) => {
+			is_date(left) && matches!(right.token, Token::TimeZone(_)) && right.children.is_empty()
+		}
+		(Token::Operator(Plus), [left, right]) => is_date(left) || is_date(right),
+		(Token::Operator(Minus), [left, right]) => is_date(left) && !is_date(right),
+		_ => false,
+	}
+}
+
+/// Evaluate a node that [`is_date`] into a [`DateValue`]
+fn evaluate_date(ast: &AstNode, context: &Scope) -> Result<DateValue, Error> {
+	evaluate_date_inner(ast, context).map_err(|e| match e.span().is_empty() {
+		true => e.with_span(ast.span),
+		false => e,
+	})
+}
+
+fn evaluate_date_inner(ast: &AstNode, context: &Scope) -> Result<DateValue, Error> {
+	match (&ast.token, ast.children.as_slice()) {
+		(Token::Date(date), _) => Ok(date.clone()),
+		(Token::Now, _) => {
+			// to the second, since fractions of a second aren't useful here
+			let now = context.clock.now().datetime();
+			Ok(DateValue::DateTime(
+				now.round(jiff::Unit::Second).unwrap_or(now),
+			))
+		}
+		(Token::Today, _) => Ok(DateValue::Date(context.clock.now().date())),
+		(Token::Time(time), _) => Ok(DateValue::DateTime(
+			context.clock.now().date().to_datetime(*time),
+		)),
+		(Token::TimeZone(zone), [child]) => {
+			let now = context.clock.now().with_time_zone(zone.clone());
+			let date = match &child.token {
+				// `now PST` is the current time there
+				Token::Now => {
+					return Ok(DateValue::Zoned(
+						now.round(jiff::Unit::Second).unwrap_or(now),
+					));
+				}
+				// a time like `3pm PST` is on the current date in that time zone
+				Token::Time(time) => DateValue::DateTime(now.date().to_datetime(*time)),
+				_ => evaluate_date(child, context)?,
+			};
+			Ok(DateValue::Zoned(date.to_zoned(zone, zone)?))
+		}
+		// a conversion like `3pm PST to CET`. Dates without a time zone are in
+		// the time zone of the clock.
+		(Token::TextOperator(To), [left, right]) => match &right.token {
+			Token::TimeZone(zone) => {
+				let date = evaluate_date(left, context)?;
+				let local = context.clock.now().time_zone().clone();
+				Ok(DateValue::Zoned(date.to_zoned(&local, zone)?))
+			}
+			_ => Err(unexpected(&right.token)),
+		},
+		(Token::Paren, [child]) => evaluate_date(child, context),
+		(Token::Operator(Plus), [left, right]) if is_date(left) && is_date(right) => {
+			Err(Error::domain("Dates can't be added together"))
+		}
+		(Token::Operator(Plus), [left, right]) if is_date(left) => {
+			add_duration_node(evaluate_date(left, context)?, right, false, context)
+		}
+		(Token::Operator(Plus), [left, right]) => {
+			add_duration_node(evaluate_date(right, context)?, left, false, context)
+		}
+		(Token::Operator(Minus), [left, right]) => {
+			add_duration_node(evaluate_date(left, context)?, right, true, context)
+		}
+		_ => Err(unexpected(&ast.token)),
+	}
+}
+
+/// Add the duration of a node to a date, or subtract it. A sum like
+/// `1 month 3 days` is added one part at a time, so the month is a calendar
+/// month.
+fn add_duration_node(
+	date: DateValue,
+	ast: &AstNode,
+	subtract: bool,
+	context: &Scope,
+) -> Result<DateValue, Error> {
+	match (&ast.token, ast.children.as_slice()) {
+		(Token::Paren, [child]) => add_duration_node(date, child, subtract, context),
+		(Token::Operator(operator @ (Plus | Minus)), [left, right])
+			if !is_date(left) && !is_date(right) =>
+		{
+			let date = add_duration_node(date, left, subtract, context)?;
+			add_duration_node(date, right, subtract ^ (*operator == Minus), context)
+		}
+		_ => {
+			let duration = evaluate_node(ast, context)?;
+			add_duration(date, &duration, subtract, context).map_err(|e| e.with_span(ast.span))
+		}
+	}
 }
 
 /// Returns the factorial of a [`struct@d128`] up to `1000!` without doing any math
@@ -32,69 +285,181 @@ pub fn factorial(input: D128) -> D128 {
 
 /// Returns the sine of a [`struct@d128`]
 pub fn sin(input: D128) -> D128 {
-	let result = input.sin();
-	// D128::PI is a finite-precision approximation of pi, so sin(pi) lands a few
-	// ulp away from zero rather than exactly zero. Snap sub-precision residue to 0.
-	if result.abs() < d!(1E-30) {
-		D128::ZERO
-	} else {
-		result
-	}
+	snap_to_zero(input.sin())
 }
 
 /// Returns the cosine of a [`struct@d128`]
 pub fn cos(input: D128) -> D128 {
-	input.cos()
+	snap_to_zero(input.cos())
 }
 
 /// Returns the tangent of a [`struct@d128`]
 pub fn tan(input: D128) -> D128 {
-	input.tan()
+	snap_to_zero(input.tan())
 }
 
-/// Turn `old` into `new` without updating the signal
-fn replace_without_updating_signals(old: D128, new: D128) -> D128 {
-	let new_without_signal = D128::parse_str(&new.to_string(), Context::default());
-	old - old + new_without_signal
+/// Returns the arctangent of a [`struct@d128`]
+pub fn atan(input: D128) -> D128 {
+	// fastnum's atan fails for |x| > 1, so use atan(x) = ±π/2 - atan(1/x) there
+	let result = if input.abs() > d!(1) {
+		let half_pi = D128::PI / d!(2);
+		let quarter_turn = if input < D128::ZERO {
+			-half_pi
+		} else {
+			half_pi
+		};
+		quarter_turn - (d!(1) / input).atan()
+	} else {
+		input.atan()
+	};
+	snap_to_zero(result)
 }
 
-fn find_concrete_category(ast: &AstNode) -> Option<UnitType> {
-	if let Token::Unit(units) = &ast.token {
-		if let Some((u, _)) = units.iter().find(|(u, _)| !matches!(u, Unit::Ambiguity(_))) {
-			return Some(u.category());
-		}
+/// Returns the angle between the positive x axis and the point `(x, y)`
+pub fn atan2(y: D128, x: D128) -> D128 {
+	let half_pi = D128::PI / d!(2);
+	match x {
+		x if x > D128::ZERO => atan(y / x),
+		x if x < D128::ZERO && y < D128::ZERO => atan(y / x) - D128::PI,
+		x if x < D128::ZERO => atan(y / x) + D128::PI,
+		_ if y > D128::ZERO => half_pi,
+		_ if y < D128::ZERO => -half_pi,
+		_ => D128::ZERO,
 	}
-	ast.children.iter().find_map(find_concrete_category)
 }
 
-fn resolve_ambiguities(ast: &mut AstNode, hint: Option<UnitType>) {
-	let mut child_hints = vec![hint; ast.children.len()];
+/// D128::PI is a finite-precision approximation of pi, so sin(pi) lands a few
+/// ulp away from zero rather than exactly zero. Snap sub-precision residue to 0.
+fn snap_to_zero(result: D128) -> D128 {
+	if result.abs() < d!(1E-30) {
+		D128::ZERO
+	} else {
+		result
+	}
+}
 
-	if let Token::TextOperator(To) = &ast.token {
-		if ast.children.len() == 2 {
-			let left_cat = find_concrete_category(&ast.children[0]);
-			let right_cat = find_concrete_category(&ast.children[1]);
-			child_hints[0] = right_cat.or(hint); // left side hinted by right
-			child_hints[1] = left_cat.or(hint); // right side hinted by left
-		}
+/// Returns the logarithm of a [`struct@d128`] with a base, exact if the result is an integer
+pub fn log(input: D128, base: D128) -> D128 {
+	let result = input.log(base);
+	let rounded = result.with_rounding_mode(RoundingMode::HalfUp).round(0);
+	if let Ok(exponent) = i32::try_from(rounded)
+		&& base.powi(exponent) == input
+	{
+		return replace_without_updating_signals(input, rounded);
 	}
+	result
+}
 
+/// Turn `old` into `new` without updating the signal
+#[allow(clippy::eq_op)]
+fn replace_without_updating_signals(old: D128, new: D128) -> D128 {
+	let new_without_signal = D128::parse_str(&new.to_string(), DecimalContext::default());
+	old - old + new_without_signal
+}
+
+fn find_concrete_category(ast: &AstNode, context: &Context) -> Option<UnitType> {
+	let units = match &ast.token {
+		// inverse trig functions return angles
+		Token::FunctionIdentifier(Asin | Acos | Atan | Atan2) => return Some(UnitType::Angle),
+		Token::Format(format) => return format.category(),
+		Token::Unit(units) => Some(units),
+		Token::Identifier(name) => context.variable(name).map(|number| &number.unit),
+		_ => None,
+	};
+	if let Some(units) = units
+		&& let Some((u, _)) = units.iter().find(|(u, _)| !matches!(u, Unit::Ambiguity(_)))
+	{
+		return Some(u.category());
+	}
+	ast.children
+		.iter()
+		.find_map(|child| find_concrete_category(child, context))
+}
+
+fn resolve_ambiguities(ast: &mut AstNode, hint: Option<UnitType>, context: &Context) {
+	let child_hints = child_hints(ast, hint, context, true);
 	for (child, h) in ast.children.iter_mut().zip(child_hints) {
-		resolve_ambiguities(child, h);
+		resolve_ambiguities(child, h, context);
 	}
 
 	if let Token::Unit(units) = &mut ast.token {
 		for (unit, _) in units.iter_mut() {
 			if let Unit::Ambiguity(amb) = unit {
-				*unit = hint
-					.and_then(|cat| amb.candidates.iter().find(|c| c.category() == cat).copied())
-					.unwrap_or(*amb.fallback);
+				*unit = amb.resolve(|candidate| Some(candidate.category()) == hint);
 			}
 		}
 	}
 }
 
-fn evaluate_unit(ast: &AstNode) -> Result<Vec<(Unit, isize)>, String> {
+/// The unit types the children of a node are expected to have, which decide
+/// what ambiguous units in them mean. Calls of user-defined functions are only
+/// looked into if `follow_calls` is set, so recursive functions don't recurse
+/// here.
+fn child_hints(
+	ast: &AstNode,
+	hint: Option<UnitType>,
+	context: &Context,
+	follow_calls: bool,
+) -> Vec<Option<UnitType>> {
+	let mut child_hints = vec![hint; ast.children.len()];
+	match &ast.token {
+		Token::TextOperator(To) | Token::Operator(Plus | Minus) if ast.children.len() == 2 => {
+			let left_cat = find_concrete_category(&ast.children[0], context);
+			let right_cat = find_concrete_category(&ast.children[1], context);
+			child_hints[0] = right_cat.or(hint); // left side hinted by right
+			child_hints[1] = left_cat.or(hint); // right side hinted by left
+		}
+		// an angle times or divided by a degree, like `asin(0.5) / 1 degree`
+		Token::Operator(Multiply | Divide) if ast.children.len() == 2 => {
+			for (i, other) in [(0, 1), (1, 0)] {
+				if find_concrete_category(&ast.children[other], context) == Some(UnitType::Angle) {
+					child_hints[i] = Some(UnitType::Angle);
+				}
+			}
+		}
+		// trig functions take angles, so `sin(30 degrees)` isn't a temperature
+		Token::FunctionIdentifier(Sin | Cos | Tan | Sec | Csc | Cot) => {
+			child_hints = vec![Some(UnitType::Angle); ast.children.len()];
+		}
+		// arguments of user-defined functions, like `30 degrees` in `f(30 degrees)`
+		// when `f(x) = sin(x)`
+		Token::Identifier(name) if follow_calls && !ast.children.is_empty() => {
+			if let Some(function) = context.functions.get(name) {
+				for (child_hint, parameter) in child_hints.iter_mut().zip(&function.parameters) {
+					if let Some(parameter_hint) =
+						parameter_hint(&function.body, parameter, None, context)
+					{
+						*child_hint = Some(parameter_hint);
+					}
+				}
+			}
+		}
+		_ => {}
+	}
+	child_hints
+}
+
+/// The unit type a parameter is expected to have in the body of a function,
+/// like an angle for `x` in `sin(x)`
+fn parameter_hint(
+	ast: &AstNode,
+	parameter: &str,
+	hint: Option<UnitType>,
+	context: &Context,
+) -> Option<UnitType> {
+	if let Token::Identifier(name) = &ast.token
+		&& ast.children.is_empty()
+		&& name == parameter
+	{
+		return hint;
+	}
+	ast.children
+		.iter()
+		.zip(child_hints(ast, hint, context, false))
+		.find_map(|(child, h)| parameter_hint(child, parameter, h, context))
+}
+
+fn evaluate_unit(ast: &AstNode) -> Result<Vec<(Unit, isize)>, Error> {
 	match &ast.token {
 		Token::Unit(unit) => Ok(unit.to_vec()),
 		Token::Operator(Divide) | Token::TextOperator(Per) => {
@@ -110,24 +475,700 @@ fn evaluate_unit(ast: &AstNode) -> Result<Vec<(Unit, isize)>, String> {
 			let right = evaluate_unit(&ast.children[1])?;
 			Ok([left.as_slice(), right.as_slice()].concat())
 		}
-		_ => Err("Expected unit expression".to_string()),
+		_ => Err(Error::ExpectedUnit { span: ast.span }),
+	}
+}
+
+fn unexpected(token: &Token) -> Error {
+	Error::UnexpectedToken {
+		token: token.clone(),
+		span: Span::default(),
+	}
+}
+
+/// Make sure a built-in function gets a number of arguments it accepts
+fn check_arity(function: &FunctionIdentifier, found: usize) -> Result<(), Error> {
+	let (min, max) = match function {
+		Min | Max | Sum | Avg | Median | Stddev | Variance | Count => (1, usize::MAX),
+		Log => (1, 2),
+		Hypot | Atan2 => (2, 2),
+		Clamp => (3, 3),
+		_ => (1, 1),
+	};
+	if (min..=max).contains(&found) {
+		return Ok(());
+	}
+	Err(Error::ArityMismatch {
+		function: Token::FunctionIdentifier(function.clone()).to_string(),
+		expected: if found < min { min } else { max },
+		found,
+		span: Span::default(),
+	})
+}
+
+/// Evaluate the arguments of a function, where lists like `[1, 2]` are expanded
+/// into their elements. Each argument comes with its span.
+fn evaluate_arguments(children: &[AstNode], context: &Scope) -> Result<Vec<(Number, Span)>, Error> {
+	let mut arguments = Vec::new();
+	for child in children {
+		if let Token::List = child.token {
+			for element in &child.children {
+				arguments.push((evaluate_node(element, context)?, element.span));
+			}
+		} else if let Some(numbers) = evaluate_broadcast(child, context)? {
+			arguments.extend(numbers.into_iter().map(|number| (number, child.span)));
+		} else {
+			arguments.push((evaluate_node(child, context)?, child.span));
+		}
+	}
+	Ok(arguments)
+}
+
+/// Evaluate an expression with lists or ranges in it, like `[1, 2, 5] km to mi`,
+/// once for each element. Scalars are broadcast over the lists, and lists in
+/// the same expression must have the same length. Returns `None` if there are
+/// no lists.
+fn evaluate_broadcast(ast: &AstNode, context: &Scope) -> Result<Option<Vec<Number>>, Error> {
+	let mut paths = Vec::new();
+	find_lists(ast, &mut Vec::new(), &mut paths);
+	if paths.is_empty() {
+		return Ok(None);
+	}
+	let mut lists: Vec<Vec<Number>> = Vec::new();
+	for path in &paths {
+		let node = path.iter().fold(ast, |node, index| &node.children[*index]);
+		let elements = evaluate_list(node, context)?;
+		if let Some(first) = lists.first()
+			&& first.len() != elements.len()
+		{
+			return Err(Error::ListLengthMismatch {
+				expected: first.len(),
+				found: elements.len(),
+				span: node.span,
+			});
+		}
+		lists.push(elements);
+	}
+	let mut results = Vec::new();
+	for index in 0..lists[0].len() {
+		let mut element_ast = ast.clone();
+		for (path, elements) in paths.iter().zip(&lists) {
+			let node = path
+				.iter()
+				.fold(&mut element_ast, |node, index| &mut node.children[*index]);
+			*node = number_node(elements[index].clone(), node.span);
+		}
+		results.push(evaluate_node(&element_ast, context)?);
+	}
+	Ok(Some(results))
+}
+
+/// Find the paths to the lists and ranges an expression is evaluated over.
+/// Functions like `sum` take lists themselves, so lists inside them are skipped.
+fn find_lists(ast: &AstNode, path: &mut Vec<usize>, paths: &mut Vec<Vec<usize>>) {
+	match &ast.token {
+		Token::List | Token::Operator(Range) => {
+			paths.push(path.clone());
+			return;
+		}
+		Token::FunctionIdentifier(Min | Max | Sum | Avg | Median | Stddev | Variance | Count) => {
+			return;
+		}
+		_ => {}
+	}
+	for (index, child) in ast.children.iter().enumerate() {
+		path.push(index);
+		find_lists(child, path, paths);
+		path.pop();
+	}
+}
+
+/// Evaluate the elements of a list like `[1, 2]` or a range like `1..10`
+fn evaluate_list(ast: &AstNode, context: &Scope) -> Result<Vec<Number>, Error> {
+	match &ast.token {
+		Token::Operator(Range) => {
+			evaluate_range(&ast.children, context).map_err(|e| match e.span().is_empty() {
+				true => e.with_span(ast.span),
+				false => e,
+			})
+		}
+		_ => ast
+			.children
+			.iter()
+			.map(|element| evaluate_node(element, context))
+			.collect(),
+	}
+}
+
+/// Evaluate the elements of a range like `1..10 step 2`. The parts without a
+/// unit get the unit of the others, so `1..3 km` goes from 1 km to 3 km.
+fn evaluate_range(children: &[AstNode], context: &Scope) -> Result<Vec<Number>, Error> {
+	let mut parts = Vec::new();
+	for child in children {
+		parts.push(evaluate_node(child, context)?);
+	}
+	let unit = parts
+		.iter()
+		.find(|part| part.has_unit())
+		.map(|part| part.unit.clone())
+		.unwrap_or_default();
+	let mut values = Vec::new();
+	for (part, child) in parts.into_iter().zip(children) {
+		let part = match part.is_unitless() {
+			true => Number::with_unit(part.value, unit.clone()),
+			false => part,
+		};
+		values.push(
+			convert(part, unit.clone(), context)
+				.map_err(|e| e.with_span(child.span))?
+				.value,
+		);
+	}
+	let (start, end) = (values[0], values[1]);
+	let step = values.get(2).map_or(d!(1), |step| step.abs());
+	if step.is_zero() {
+		return Err(Error::domain("The step of a range can't be zero"));
+	}
+	let length = match i64::try_from(((end - start).abs() / step).floor()) {
+		Ok(steps) if (steps as usize) < MAX_RANGE_LENGTH => steps as usize + 1,
+		_ => {
+			return Err(Error::RangeTooLarge {
+				span: Span::default(),
+			});
+		}
+	};
+	let step = if end < start { -step } else { step };
+	Ok((0..length)
+		.map(|index| Number::with_unit(start + step * D128::from(index), unit.clone()))
+		.collect())
+}
+
+/// A node that evaluates to a number, to put in place of a list element
+fn number_node(number: Number, span: Span) -> AstNode {
+	let value_node = AstNode::new(Token::Number(number.value), span);
+	match number.is_unitless() {
+		true => value_node,
+		false => AstNode {
+			children: vec![value_node],
+			token: Token::Unit(number.unit),
+			span,
+		},
 	}
 }
 
+fn no_values(function: &FunctionIdentifier) -> Error {
+	Error::domain(format!(
+		"{}() needs at least one value",
+		Token::FunctionIdentifier(function.clone())
+	))
+}
+
+/// Evaluate an aggregate function like `avg(3.2 km, 5 mi)` or `sum([1, 2])`.
+/// The values are added with [`add`], so they end up in the smallest of their
+/// units.
+fn evaluate_aggregate(
+	function: &FunctionIdentifier,
+	children: &[AstNode],
+	context: &Scope,
+) -> Result<Number, Error> {
+	let arguments = evaluate_arguments(children, context)?;
+	let count = D128::from(arguments.len());
+	if let Count = function {
+		return Ok(Number::new_unitless(count));
+	}
+	let mut sum: Option<Number> = None;
+	for (argument, span) in &arguments {
+		sum = Some(match sum {
+			Some(sum) => add(sum, argument.clone(), context).map_err(|e| e.with_span(*span))?,
+			None => argument.clone(),
+		});
+	}
+	let Some(sum) = sum else {
+		return Err(no_values(function));
+	};
+	let unit = sum.unit.clone();
+	let mut values = Vec::new();
+	for (argument, _) in arguments {
+		values.push(convert(argument, unit.clone(), context)?.value);
+	}
+	match function {
+		Sum => Ok(sum),
+		Avg => Ok(Number::with_unit(sum.value / count, unit)),
+		Median => {
+			values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+			let middle = values.len() / 2;
+			let median = match values.len() % 2 {
+				0 => (values[middle - 1] + values[middle]) / d!(2),
+				_ => values[middle],
+			};
+			Ok(Number::with_unit(median, unit))
+		}
+		_ => {
+			// the sample variance, which divides by n - 1
+			if values.len() < 2 {
+				return Err(Error::domain(format!(
+					"{}() needs at least two values",
+					Token::FunctionIdentifier(function.clone())
+				)));
+			}
+			let mean = sum.value / count;
+			let squares = values.iter().fold(D128::ZERO, |acc, value| {
+				acc + (*value - mean) * (*value - mean)
+			});
+			let variance = squares / (count - d!(1));
+			match function {
+				Variance => {
+					let unit = unit
+						.iter()
+						.map(|(u, exponent)| (u.clone(), exponent * 2))
+						.collect();
+					Ok(Number::with_unit(variance, unit))
+				}
+				_ => Ok(Number::with_unit(variance.sqrt(), unit)),
+			}
+		}
+	}
+}
+
+/// Evaluate a function like `max(3 km, 2 mi)`, where the arguments are
+/// converted to the unit of the first one to be compared
+fn evaluate_multi_argument_function(
+	function: &FunctionIdentifier,
+	children: &[AstNode],
+	context: &Scope,
+) -> Result<Number, Error> {
+	let mut arguments = Vec::new();
+	let mut values = Vec::new();
+	for (argument, span) in evaluate_arguments(children, context)? {
+		let unit = arguments
+			.first()
+			.map_or(&argument.unit, |first: &Number| &first.unit);
+		let value = convert(argument.clone(), unit.clone(), context)
+			.map_err(|e| e.with_span(span))?
+			.value;
+		arguments.push(argument);
+		values.push(value);
+	}
+	let Some(unit) = arguments.first().map(|first| first.unit.clone()) else {
+		return Err(no_values(function));
+	};
+	let extreme = |is_better: fn(&D128, &D128) -> bool| {
+		let mut best = 0;
+		for (i, value) in values.iter().enumerate() {
+			if is_better(value, &values[best]) {
+				best = i;
+			}
+		}
+		Ok(arguments[best].clone())
+	};
+	match function {
+		Min => extreme(|a, b| a < b),
+		Max => extreme(|a, b| a > b),
+		Clamp => {
+			let (value, low, high) = (values[0], values[1], values[2]);
+			if low > high {
+				return Err(Error::domain(
+					"The lower bound of clamp() is above the upper bound",
+				));
+			}
+			let result = if value < low {
+				low
+			} else if value > high {
+				high
+			} else {
+				value
+			};
+			Ok(Number::with_unit(result, unit))
+		}
+		Hypot => {
+			let result = values[0].hypot(values[1]);
+			// like sqrt, avoid the OP_INEXACT signal if the result is exact
+			let result_with_old_signals = replace_without_updating_signals(values[0], result);
+			let squares = values[0] * values[0] + values[1] * values[1];
+			if !squares.is_op_inexact()
+				&& result_with_old_signals * result_with_old_signals == squares
+			{
+				return Ok(Number::with_unit(result_with_old_signals, unit));
+			}
+			Ok(Number::with_unit(result, unit))
+		}
+		Atan2 => Ok(Number::with_basic_unit(
+			atan2(values[0], values[1]),
+			Unit::Radian,
+		)),
+		_ => Err(unexpected(&Token::FunctionIdentifier(function.clone()))),
+	}
+}
+
+/// Evaluate a trig function like `sin` of a unitless number in radians or an
+/// angle. Angles that are whole multiples of 30° or 45° give exact results.
+fn evaluate_trig(
+	function: &FunctionIdentifier,
+	argument: Number,
+	context: &Scope,
+) -> Result<Number, Error> {
+	let undefined = |argument: &Number| {
+		Error::domain(format!(
+			"{}() is undefined at {argument}",
+			Token::FunctionIdentifier(function.clone())
+		))
+	};
+	let too_large = || {
+		Error::domain(format!(
+			"{}() can't be calculated for an angle this large",
+			Token::FunctionIdentifier(function.clone())
+		))
+	};
+	let radians = if argument.is_unitless() {
+		reduce_radians(argument.value).ok_or_else(too_large)?
+	} else if let [(unit, 1)] = argument.unit.as_slice()
+		&& unit.category() == UnitType::Angle
+	{
+		// the remainder of a turn is exact in degrees, unlike in radians
+		let degrees = convert(argument.clone(), vec![(Unit::Degree, 1)], context)?.value;
+		let degrees = reduce_degrees(degrees).ok_or_else(too_large)?;
+		if trig_undefined(function, degrees) {
+			return Err(undefined(&argument));
+		}
+		if let Some(result) = exact_trig(function, degrees) {
+			return Ok(Number::new_unitless(result));
+		}
+		degrees * D128::PI / d!(180)
+	} else {
+		return Err(unsupported_argument(function, &argument));
+	};
+	let result = match function {
+		Sin => sin(radians),
+		Cos => cos(radians),
+		Tan => tan(radians),
+		_ => {
+			let divisor = match function {
+				Sec => cos(radians),
+				Csc => sin(radians),
+				_ => tan(radians),
+			};
+			if divisor.is_zero() {
+				return Err(undefined(&argument));
+			}
+			d!(1) / divisor
+		}
+	};
+	Ok(Number::new_unitless(result))
+}
+
+/// An angle in radians reduced to less than a turn, which fastnum's trig
+/// functions need for large angles. Returns None if the angle is too large for
+/// the remainder to have any precision left.
+fn reduce_radians(radians: D128) -> Option<D128> {
+	if radians.abs() < D128::TAU {
+		return Some(radians);
+	}
+	let turns = (radians / D128::TAU).floor();
+	let reduced = radians - turns * D128::TAU;
+	let precise = turns.abs() < d!(1e30) && reduced >= D128::ZERO && reduced < D128::TAU;
+	precise.then_some(reduced)
+}
+
+/// An angle in degrees reduced to less than a turn, keeping its sign. Returns
+/// None if the angle is too large for the remainder to be exact.
+fn reduce_degrees(degrees: D128) -> Option<D128> {
+	if degrees.abs() < d!(360) {
+		return Some(degrees);
+	}
+	let turns = (degrees / d!(360)).trunc();
+	let reduced = degrees - turns * d!(360);
+	let precise = turns.abs() < d!(1e30) && reduced.abs() < d!(360);
+	precise.then_some(reduced)
+}
+
+/// The number of whole steps in an angle, if it's a whole number
+fn whole_steps(degrees: D128, step: D128) -> Option<i64> {
+	let steps = degrees / step;
+	if steps.is_op_inexact() || steps.floor() != steps {
+		return None;
+	}
+	i64::try_from(steps).ok()
+}
+
+/// Whether a trig function is undefined at an angle in degrees, like `tan(90°)`
+fn trig_undefined(function: &FunctionIdentifier, degrees: D128) -> bool {
+	let Some(quarters) = whole_steps(degrees, d!(90)) else {
+		return false;
+	};
+	match function {
+		Tan | Sec => quarters % 2 != 0,
+		Csc | Cot => quarters % 2 == 0,
+		_ => false,
+	}
+}
+
+/// The exact result of a trig function of an angle in degrees, if it's
+/// rational
+fn exact_trig(function: &FunctionIdentifier, degrees: D128) -> Option<D128> {
+	// sine of each multiple of 30°, where None means the result is irrational
+	const SINES: [Option<D128>; 12] = [
+		Some(d!(0)),
+		Some(d!(0.5)),
+		None,
+		Some(d!(1)),
+		None,
+		Some(d!(0.5)),
+		Some(d!(0)),
+		Some(d!(-0.5)),
+		None,
+		Some(d!(-1)),
+		None,
+		Some(d!(-0.5)),
+	];
+	// tangent of each multiple of 45°, where None means it's undefined
+	const TANGENTS: [Option<D128>; 8] = [
+		Some(d!(0)),
+		Some(d!(1)),
+		None,
+		Some(d!(-1)),
+		Some(d!(0)),
+		Some(d!(1)),
+		None,
+		Some(d!(-1)),
+	];
+	match function {
+		Sin | Cos | Sec | Csc => {
+			let steps = whole_steps(degrees, d!(30))?;
+			// cos(x) = sin(x + 90°)
+			let steps = match function {
+				Cos | Sec => steps + 3,
+				_ => steps,
+			};
+			let sine = SINES[steps.rem_euclid(12) as usize]?;
+			match function {
+				Sec | Csc => Some(d!(1) / sine),
+				_ => Some(sine),
+			}
+		}
+		Tan => TANGENTS[whole_steps(degrees, d!(45))?.rem_euclid(8) as usize],
+		// cot(x) = -tan(x + 90°), subtracted from zero so cot(90°) isn't -0
+		_ => TANGENTS[(whole_steps(degrees, d!(45))? + 2).rem_euclid(8) as usize]
+			.map(|t| D128::ZERO - t),
+	}
+}
+
+/// Evaluate `asin`, `acos` or `atan`, which return an angle in degrees so that
+/// results that are whole multiples of 30° or 45° are exact
+fn evaluate_inverse_trig(function: &FunctionIdentifier, argument: Number) -> Result<Number, Error> {
+	if !argument.is_unitless() {
+		return Err(unsupported_argument(function, &argument));
+	}
+	let x = argument.value;
+	if let Some(degrees) = exact_inverse_trig(function, x) {
+		return Ok(Number::with_basic_unit(degrees, Unit::Degree));
+	}
+	let result = match function {
+		Asin | Acos if x.abs() > d!(1) => {
+			return Err(Error::domain(format!(
+				"{}() is only defined from -1 to 1",
+				Token::FunctionIdentifier(function.clone())
+			)));
+		}
+		Asin => x.asin(),
+		Acos => x.acos(),
+		_ => atan(x),
+	};
+	let degrees = snap_to_zero(result) * d!(180) / D128::PI;
+	Ok(Number::with_basic_unit(degrees, Unit::Degree))
+}
+
+/// The exact result in degrees of `asin`, `acos` or `atan`, if it's a whole
+/// multiple of 30° or 45°
+fn exact_inverse_trig(function: &FunctionIdentifier, x: D128) -> Option<D128> {
+	let degrees = match function {
+		Asin | Acos => match x {
+			x if x == d!(-1) => d!(-90),
+			x if x == d!(-0.5) => d!(-30),
+			x if x == d!(0) => d!(0),
+			x if x == d!(0.5) => d!(30),
+			x if x == d!(1) => d!(90),
+			_ => return None,
+		},
+		_ => match x {
+			x if x == d!(-1) => d!(-45),
+			x if x == d!(0) => d!(0),
+			x if x == d!(1) => d!(45),
+			_ => return None,
+		},
+	};
+	match function {
+		// acos(x) = 90° - asin(x)
+		Acos => Some(d!(90) - degrees),
+		_ => Some(degrees),
+	}
+}
+
+/// Evaluate a hyperbolic function like `sinh`, or its inverse like `asinh`
+fn evaluate_hyperbolic(function: &FunctionIdentifier, argument: Number) -> Result<Number, Error> {
+	if !argument.is_unitless() {
+		return Err(unsupported_argument(function, &argument));
+	}
+	let x = argument.value;
+	let result = match function {
+		Acosh if x < d!(1) => return Err(Error::domain("acosh() is only defined from 1 and up")),
+		Atanh if x.abs() >= d!(1) => {
+			return Err(Error::domain("atanh() is only defined between -1 and 1"));
+		}
+		// fastnum panics on acosh(1)
+		Acosh if x == d!(1) => D128::ZERO,
+		Sinh => x.sinh(),
+		Cosh => x.cosh(),
+		Tanh => x.tanh(),
+		Asinh => x.asinh(),
+		Acosh => x.acosh(),
+		_ => x.atanh(),
+	};
+	Ok(Number::new_unitless(snap_to_zero(result)))
+}
+
+fn unsupported_argument(function: &FunctionIdentifier, argument: &Number) -> Error {
+	let name = format!("{}()", Token::FunctionIdentifier(function.clone()));
+	Error::unsupported_unit(&name, &argument.unit)
+}
+
+/// Convert a whole number to an [`i128`], or return `None` if it doesn't fit
+pub(crate) fn to_i128(value: D128) -> Option<i128> {
+	let max = D128::try_from(i128::MAX).ok()?;
+	match value.is_integral() && value.abs() <= max {
+		true => i128::try_from(value).ok(),
+		false => None,
+	}
+}
+
+/// The integer value of an operand of a bitwise operation like `and` or `<<`,
+/// which can't have a unit or a fractional part
+fn integer_operand(number: &Number, operation: &str) -> Result<i128, Error> {
+	if number.has_unit() {
+		return Err(Error::unsupported_unit(operation, &number.unit));
+	}
+	if !number.value.is_integral() {
+		return Err(Error::domain(format!(
+			"{operation} only works on whole numbers"
+		)));
+	}
+	to_i128(number.value)
+		.ok_or_else(|| Error::domain(format!("{} is too large for {operation}", number.value)))
+}
+
+/// The result of a bitwise operation
+fn integer_result(result: Option<i128>, operation: &str) -> Result<Number, Error> {
+	result
+		.and_then(|result| D128::try_from(result).ok())
+		.map(Number::new_unitless)
+		.ok_or_else(|| Error::domain(format!("The result of {operation} is too large")))
+}
+
+/// Evaluate a bitwise [`Operator`] like [`And`] or [`ShiftLeft`]
+fn evaluate_bitwise(operator: &Operator, left: Number, right: Number) -> Result<Number, Error> {
+	let operation = format!("`{}`", Token::Operator(operator.clone()));
+	let left = integer_operand(&left, &operation)?;
+	let right = integer_operand(&right, &operation)?;
+	let result = match operator {
+		And => Some(left & right),
+		Or => Some(left | right),
+		Xor => Some(left ^ right),
+		ShiftLeft | ShiftRight => {
+			let Ok(shift) = u32::try_from(right) else {
+				return Err(Error::domain("Can't shift by a negative amount"));
+			};
+			match operator {
+				// shifting out the sign bit would overflow
+				ShiftLeft if shift < i128::BITS - 1 => left.checked_mul(1 << shift),
+				ShiftLeft => (left == 0).then_some(0),
+				_ => Some(left >> shift.min(i128::BITS - 1)),
+			}
+		}
+		_ => return Err(unexpected(&Token::Operator(operator.clone()))),
+	};
+	integer_result(result, &operation)
+}
+
 /// Evaluate an [`AstNode`] into a [`Number`]
-fn evaluate_node(ast_node: &AstNode) -> Result<Number, String> {
+///
+/// Errors that don't have a [`Span`] yet get the span of the node they occurred in.
+fn evaluate_node(ast_node: &AstNode, context: &Scope) -> Result<Number, Error> {
+	evaluate_node_inner(ast_node, context).map_err(|e| match e.span().is_empty() {
+		true => e.with_span(ast_node.span),
+		false => e,
+	})
+}
+
+fn evaluate_node_inner(ast_node: &AstNode, context: &Scope) -> Result<Number, Error> {
 	let token = &ast_node.token;
 	let children = &ast_node.children;
 	match token {
 		Token::Number(number) => Ok(Number::new_unitless(*number)),
+		Token::Identifier(name) if !children.is_empty() => {
+			call_function(name, children, ast_node.span, context)
+		}
+		Token::Identifier(name) => match context.variable(name) {
+			Some(number) => Ok(number.clone()),
+			None => Err(Error::UnknownWord {
+				word: name.clone(),
+				span: Span::default(),
+			}),
+		},
 		Token::Constant(constant) => match constant {
 			Pi => Ok(Number::new_unitless(D128::PI)),
 			E => Ok(Number::new_unitless(D128::E)),
 		},
+		Token::Date(_) | Token::Now | Token::Today | Token::Time(_) => Err(Error::domain(
+			"Dates can only be added to or subtracted from durations and other dates",
+		)),
+		Token::TimeZone(_) => Err(Error::domain(
+			"Time zones can only be used after dates and times, or after `to`",
+		)),
 		Token::FunctionIdentifier(function) => {
-			let child_node = children.get(0).ok_or("Function has no child[0]")?;
-			let child_answer = evaluate_node(child_node)?;
+			check_arity(function, children.len())?;
 			match function {
+				Hypot | Min | Max | Clamp | Atan2 => {
+					return evaluate_multi_argument_function(function, children, context);
+				}
+				Sum | Avg | Median | Stddev | Variance | Count => {
+					return evaluate_aggregate(function, children, context);
+				}
+				Log if children.len() == 2 => {
+					let value = evaluate_node(&children[0], context)?;
+					let base = evaluate_node(&children[1], context)?;
+					if !value.is_unitless() {
+						return Err(Error::unsupported_unit("log()", &value.unit));
+					} else if !base.is_unitless() {
+						return Err(Error::unsupported_unit("log()", &base.unit));
+					}
+					if value.value <= D128::ZERO {
+						return Err(Error::domain("log() is only defined for numbers above 0")
+							.with_span(children[0].span));
+					}
+					if base.value <= D128::ZERO || base.value == d!(1) {
+						return Err(Error::domain("The base of log() must be above 0 and not 1")
+							.with_span(children[1].span));
+					}
+					return Ok(Number::new_unitless(log(value.value, base.value)));
+				}
+				_ => {}
+			}
+			let child_node = children.first().ok_or_else(|| unexpected(token))?;
+			let child_answer = evaluate_node(child_node, context)?;
+			// rounding a fraction is exact, like `round(7/3)`
+			if let Some(rational) = child_answer.rational
+				&& let Some(result) = match function {
+					Round => rational.round(),
+					Ceil => Some(rational.ceil()),
+					Floor => Some(rational.floor()),
+					_ => None,
+				} {
+				let result = Rational::from_integer(result);
+				return Ok(Number::from_rational(result, child_answer.unit));
+			}
+			match function {
+				Sqrt if child_answer.is_unitless() && child_answer.value < D128::ZERO => Err(
+					Error::domain("sqrt() is only defined for numbers from 0 and up"),
+				),
 				Sqrt => {
 					if child_answer.is_unitless() {
 						let mut result = child_answer.value.sqrt();
@@ -140,12 +1181,16 @@ fn evaluate_node(ast_node: &AstNode) -> Result<Number, String> {
 						}
 						Ok(Number::with_unit(result, child_answer.unit))
 					} else {
-						Err("sqrt() only accepts unitless numbers".to_string())
+						Err(Error::unsupported_unit("sqrt()", &child_answer.unit))
 					}
 				}
 				Cbrt => {
 					if child_answer.is_unitless() {
-						let mut result = child_answer.value.cbrt();
+						// fastnum's cbrt fails for negative numbers
+						let mut result = match child_answer.value < D128::ZERO {
+							true => -child_answer.value.abs().cbrt(),
+							false => child_answer.value.cbrt(),
+						};
 						let result_with_old_signals =
 							replace_without_updating_signals(child_answer.value, result);
 						let result_squared = result_with_old_signals
@@ -157,15 +1202,21 @@ fn evaluate_node(ast_node: &AstNode) -> Result<Number, String> {
 						}
 						Ok(Number::with_unit(result, child_answer.unit))
 					} else {
-						Err("cbrt() only accepts unitless numbers".to_string())
+						Err(Error::unsupported_unit("cbrt()", &child_answer.unit))
 					}
 				}
+				Log | Ln if child_answer.is_unitless() && child_answer.value <= D128::ZERO => {
+					Err(Error::domain(format!(
+						"{}() is only defined for numbers above 0",
+						Token::FunctionIdentifier(function.clone())
+					)))
+				}
 				Log => {
 					if child_answer.is_unitless() {
 						let result = child_answer.value.log10();
 						Ok(Number::with_unit(result, child_answer.unit))
 					} else {
-						Err("log() only accepts unitless numbers".to_string())
+						Err(Error::unsupported_unit("log()", &child_answer.unit))
 					}
 				}
 				Ln => {
@@ -173,7 +1224,7 @@ fn evaluate_node(ast_node: &AstNode) -> Result<Number, String> {
 						let result = child_answer.value.ln();
 						Ok(Number::with_unit(result, child_answer.unit))
 					} else {
-						Err("ln() only accepts unitless numbers".to_string())
+						Err(Error::unsupported_unit("ln()", &child_answer.unit))
 					}
 				}
 				Exp => {
@@ -181,7 +1232,7 @@ fn evaluate_node(ast_node: &AstNode) -> Result<Number, String> {
 						let result = child_answer.value.exp();
 						Ok(Number::with_unit(result, child_answer.unit))
 					} else {
-						Err("exp() only accepts unitless numbers".to_string())
+						Err(Error::unsupported_unit("exp()", &child_answer.unit))
 					}
 				}
 				Round => {
@@ -203,116 +1254,137 @@ fn evaluate_node(ast_node: &AstNode) -> Result<Number, String> {
 					let result = replace_without_updating_signals(child_answer.value, result);
 					Ok(Number::with_unit(result, child_answer.unit))
 				}
-				Abs => {
-					let result = child_answer.value.abs();
-					Ok(Number::with_unit(result, child_answer.unit))
-				}
-				Sin => {
-					let result = sin(child_answer.value);
-					Ok(Number::with_unit(result, child_answer.unit))
-				}
-				Cos => {
-					let result = cos(child_answer.value);
-					Ok(Number::with_unit(result, child_answer.unit))
+				Abs => Ok(child_answer.map(
+					|value| value.abs(),
+					Rational::checked_abs,
+					child_answer.unit.clone(),
+				)),
+				Sin | Cos | Tan | Sec | Csc | Cot => evaluate_trig(function, child_answer, context),
+				Asin | Acos | Atan => evaluate_inverse_trig(function, child_answer),
+				Sinh | Cosh | Tanh | Asinh | Acosh | Atanh => {
+					evaluate_hyperbolic(function, child_answer)
 				}
-				Tan => {
-					let result = tan(child_answer.value);
-					Ok(Number::with_unit(result, child_answer.unit))
+				Popcount => {
+					let integer = integer_operand(&child_answer, "popcount()")?;
+					if integer < 0 {
+						return Err(Error::domain(
+							"popcount() only works on numbers that aren't negative",
+						));
+					}
+					Ok(Number::new_unitless(D128::from(integer.count_ones())))
 				}
+				Hypot | Min | Max | Clamp | Atan2 | Sum | Avg | Median | Stddev | Variance
+				| Count => Err(unexpected(token)),
 			}
 		}
 		Token::Unit(unit) => {
-			let child_answer = match children.get(0) {
-				Some(node) => evaluate_node(node)?,
+			let child_answer = match children.first() {
+				Some(node) => evaluate_node(node, context)?,
 				None => Number::new_unitless(d!(1)),
 			};
-			Ok(Number::with_unit(child_answer.value, unit.clone()))
+			Ok(Number {
+				unit: unit.clone(),
+				..child_answer
+			})
 		}
 		Token::Negative => {
-			let child_node = children.get(0).ok_or("Negative has no child[0]")?;
-			let child_answer = evaluate_node(child_node)?;
-			Ok(Number::with_unit(-child_answer.value, child_answer.unit))
+			let child_node = children.first().ok_or_else(|| unexpected(token))?;
+			let child_answer = evaluate_node(child_node, context)?;
+			Ok(child_answer.map(
+				|value| -value,
+				Rational::checked_neg,
+				child_answer.unit.clone(),
+			))
 		}
 		Token::Paren => {
-			let child_node = children.get(0).ok_or("Paren has no child[0]")?;
-			evaluate_node(child_node)
+			let child_node = children.first().ok_or_else(|| unexpected(token))?;
+			evaluate_node(child_node, context)
 		}
 		Token::UnaryOperator(operator) => {
-			let child_node = children
-				.get(0)
-				.ok_or(format!("Token {:?} has no child[0]", token))?;
-			let child_answer = evaluate_node(child_node)?;
+			let child_node = children.first().ok_or_else(|| unexpected(token))?;
+			let child_answer = evaluate_node(child_node, context)?;
 			match operator {
-				Percent => Ok(Number::with_unit(
-					child_answer.value / d!(100),
-					child_answer.unit,
+				Percent => Ok(child_answer.map(
+					|value| value / d!(100),
+					|value| value.checked_div(Rational::from_integer(100)),
+					child_answer.unit.clone(),
 				)),
 				Factorial => {
 					let result = factorial(child_answer.value);
 					if result.is_nan() {
-						return Err(
-							"Can only perform factorial on integers from 0 to 1000".to_string()
-						);
+						return Err(Error::domain(
+							"Can only perform factorial on integers from 0 to 1000",
+						));
 					}
 					Ok(Number::with_unit(result, child_answer.unit))
 				}
+				Not => {
+					let integer = integer_operand(&child_answer, "`not`")?;
+					integer_result(Some(!integer), "`not`")
+				}
 			}
 		}
 		Token::NamedNumber(named_number) => {
-			let child_node = children
-				.get(0)
-				.ok_or(format!("Token {:?} has no child[0]", token))?;
+			let child_node = children.first().ok_or_else(|| unexpected(token))?;
 			let named_number_value = lookup_named_number(named_number);
 			if let Token::NamedNumber(child_nn) = &child_node.token {
 				let child_nn_value = lookup_named_number(child_nn);
 				if child_nn_value > named_number_value {
-					return Err(format!("Unexpected smaller token {:?}", token));
+					return Err(unexpected(token));
 				}
 			}
-			let child_answer = evaluate_node(child_node)?;
-			let result = child_answer.value * named_number_value;
-			Ok(Number::with_unit(result, child_answer.unit))
+			let child_answer = evaluate_node(child_node, context)?;
+			Ok(child_answer.map(
+				|value| value * named_number_value,
+				|value| value.checked_mul(Rational::from_decimal(named_number_value)?),
+				child_answer.unit.clone(),
+			))
 		}
 		Token::TextOperator(operator) => {
-			let left_child = children
-				.get(0)
-				.ok_or(format!("Token {:?} has no child[0]", token))?;
-			let right_child = children
-				.get(1)
-				.ok_or(format!("Token {:?} has no child[1]", token))?;
+			let left_child = children.first().ok_or_else(|| unexpected(token))?;
+			let right_child = children.get(1).ok_or_else(|| unexpected(token))?;
 
 			match operator {
 				To => {
-					let left = evaluate_node(left_child)?;
-					let right = evaluate_unit(right_child)
-						.map_err(|_| "Right side of To operator needs to be a unit".to_string())?;
-					let result = convert(left, right)?;
+					let left = evaluate_node(left_child, context)?;
+					let right = evaluate_unit(right_child)?;
+					let result = convert(left, right, context)?;
 					Ok(result)
 				}
 				Of => {
-					let left = evaluate_node(left_child)?;
-					let right = evaluate_node(right_child)?;
+					let left = evaluate_node(left_child, context)?;
+					let right = evaluate_node(right_child, context)?;
 					if left.is_unitless() {
-						Ok(Number::with_unit(left.value * right.value, right.unit))
+						Ok(left.combine(
+							&right,
+							|a, b| a * b,
+							Rational::checked_mul,
+							right.unit.clone(),
+						))
 					} else {
-						Err("Left side of the Of operator must be NoUnit".to_string())
+						Err(Error::unsupported_unit("the left side of `of`", &left.unit))
 					}
 				}
 				Per => {
-					let mut node = AstNode::new(Token::Operator(Divide));
+					let mut node = AstNode::new(Token::Operator(Divide), ast_node.span);
 					node.children = children.to_vec();
-					Ok(evaluate_node(&node)?)
+					Ok(evaluate_node(&node, context)?)
 				}
+				// only used in ranges
+				Step => Err(unexpected(token)),
 			}
 		}
 		Token::Operator(operator) => {
-			let left_child = children
-				.get(0)
-				.ok_or(format!("Token {:?} has no child[0]", token))?;
-			let right_child = children
-				.get(1)
-				.ok_or(format!("Token {:?} has no child[1]", token))?;
-			let left = evaluate_node(left_child)?;
+			let left_child = children.first().ok_or_else(|| unexpected(token))?;
+			let right_child = children.get(1).ok_or_else(|| unexpected(token))?;
+			// the duration between two dates, like `2027-01-01 - today`
+			if *operator == Minus && is_date(left_child) && is_date(right_child) {
+				let end = evaluate_date(left_child, context)?;
+				let start = evaluate_date(right_child, context)?;
+				let local = context.clock.now().time_zone().clone();
+				return duration_between(&start, &end, &local);
+			}
+			let left = evaluate_node(left_child, context)?;
 			if matches!(operator, Multiply | Divide)
 				&& let Ok(right) = evaluate_unit(right_child)
 			{
@@ -336,37 +1408,56 @@ fn evaluate_node(ast_node: &AstNode) -> Result<Number, String> {
 				return Ok(raw_result);
 			}
 
-			let right = evaluate_node(right_child)?;
+			let right = evaluate_node(right_child, context)?;
 			match operator {
-				Plus => Ok(add(left, right)?),
-				Minus => Ok(subtract(left, right)?),
+				Plus => Ok(add(left, right, context)?),
+				Minus => Ok(subtract(left, right, context)?),
 				Multiply => Ok(to_ideal_unit(multiply(left, right)?)),
 				Divide => Ok(to_ideal_unit(divide(left, right)?)),
-				Modulo => Ok(modulo(left, right)?),
+				Modulo => Ok(modulo(left, right, context)?),
+				IntegerDivide => Ok(integer_divide(left, right, context)?),
 				Caret => Ok(pow(left, right)?),
-				_ => Err(format!("Unexpected operator {:?}", operator)),
+				And | Or | Xor | ShiftLeft | ShiftRight => evaluate_bitwise(operator, left, right),
+				_ => Err(unexpected(token)),
 			}
 		}
-		_ => Err(format!("Unexpected token {:?}", token)),
+		_ => Err(unexpected(token)),
 	}
 }
 
 #[cfg(test)]
 mod tests {
-	use crate::{Settings, eval};
-	use serial_test::serial;
+	use crate::datetime::Clock;
+	use crate::rational::Rational;
+	use crate::{Context, Error, EvalOptions, Number, Span, eval_with_context};
 	use std::str::FromStr;
 
+	fn eval_in(input: &str, context: &mut Context) -> Result<Number, Error> {
+		let options = EvalOptions::new().allow_trailing_operators(true);
+		eval_with_context(input, &options, context)
+			.map(|output| output.value.into_number().unwrap())
+	}
+
+	fn eval(input: &str) -> Result<Number, Error> {
+		eval_in(input, &mut Context::new("nb-NO"))
+	}
+
 	#[track_caller]
 	fn eval_test(input: &str, expected: &str) {
-		let result = eval(input, true, false).unwrap();
-		assert_eq!(result.to_string(), expected);
+		let options = EvalOptions::new().allow_trailing_operators(true);
+		let output = eval_with_context(input, &options, &mut Context::new("nb-NO")).unwrap();
+		assert_eq!(output.value.to_string(), expected);
 	}
 
 	#[track_caller]
 	fn results_eq(a: &str, b: &str) {
-		let result_a = crate::eval(a, true, false).unwrap();
-		let result_b = crate::eval(b, true, false).unwrap();
+		results_eq_in(a, b, &mut Context::new("nb-NO"));
+	}
+
+	#[track_caller]
+	fn results_eq_in(a: &str, b: &str, context: &mut Context) {
+		let result_a = eval_in(a, context).unwrap();
+		let result_b = eval_in(b, context).unwrap();
 		assert_eq!(result_a, result_b, "{a} != {b}");
 		assert_eq!(
 			result_a.value.op_signals(),
@@ -392,9 +1483,13 @@ mod tests {
 	fn test_functions() {
 		eval_test("cbrt(125)", "5");
 		eval_test("cbrt(2)", "≈ 1.25992104989487316476721060727822835057");
+		eval_test("cbrt(-8)", "-2");
+		eval_test("cbrt(-2)", "≈ -1.25992104989487316476721060727822835057");
 
 		eval_test("sqrt(25)", "5");
 		eval_test("sqrt(2)", "≈ 1.41421356237309504880168872420969807857");
+		eval_test("sqrt(0)", "0");
+		assert!(matches!(eval("sqrt(-1)"), Err(Error::DomainError { .. })));
 
 		eval_test("log(100)", "2");
 		eval_test("log(2)", "≈ 0.301029995663981195213738894724493026768");
@@ -427,18 +1522,541 @@ mod tests {
 		eval_test("tan(2)", "≈ -2.18503986326151899164330610231368254343");
 	}
 
+	#[test]
+	fn test_angles() {
+		eval_test("sin(30 deg)", "0.5");
+		eval_test("cos(60°)", "0.5");
+		eval_test("sin(-90 degrees)", "-1");
+		eval_test("cos(0.25 turn)", "0");
+		eval_test("tan(225 deg)", "1");
+		eval_test("sin(100 grad)", "1");
+		eval_test("sin(45 deg)", "≈ 0.70710678118654752440084436210484903929");
+		eval_test("sin(pi rad)", "0");
+		eval_test(
+			"90 deg to rad",
+			"≈ 1.5707963267948966192313216916397514421 radians",
+		);
+		eval_test("1 turn to deg", "360 degrees");
+		eval_test("30 arcmin to deg", "0.5 degrees");
+		eval_test("30 deg + 0.5 turn", "210 degrees");
+		results_eq_in("20 degrees", "20 celsius", &mut Context::new("nb-NO"));
+		results_eq_in("20 degrees", "20 fahrenheit", &mut Context::new("en-US"));
+		results_eq("100 degrees to rad", "100 deg to rad");
+		results_eq("10 degrees + 1 rad", "10 deg + 1 rad");
+		eval_test(
+			"sin(1e20 deg)",
+			"≈ -0.98480775301220805936674302458952301366",
+		);
+		eval_test("sin(-1e20)", "≈ 0.64525128526578084405686531833888044498");
+		assert!(eval("sin(1e40)").is_err());
+		eval_test(
+			"sin(1e29 deg)",
+			"≈ -0.98480775301220805936674302458952301366",
+		);
+		assert!(matches!(
+			eval("sin(1e40 deg)"),
+			Err(Error::DomainError { .. })
+		));
+		assert!(matches!(
+			eval("cos(-1e40 turns)"),
+			Err(Error::DomainError { .. })
+		));
+
+		// `deg` and `degree` are angles wherever an angle is expected
+		let mut context = Context::new("en-US");
+		let options = EvalOptions::new();
+		eval_with_context("half_cos(a) = cos(a) / 2", &options, &mut context).unwrap();
+		let mut eval_us = |input| eval_in(input, &mut context).unwrap().to_string();
+		assert_eq!(eval_us("sin(30 deg)"), "0.5");
+		assert_eq!(eval_us("asin(0.5) / 1 deg"), "30");
+		assert_eq!(eval_us("asin(0.5) / 1 degree"), "30");
+		assert_eq!(eval_us("90 deg + 0.5 turns"), "270 degrees");
+		assert_eq!(eval_us("half_cos(120 degrees)"), "-0.25");
+		assert_eq!(eval_us("45 deg"), "45 fahrenheit");
+		assert_eq!(eval_us("45 degrees"), "45 fahrenheit");
+		eval_test("1 deg to C", "1 celsius");
+		eval_test("30 deg + 1 C", "31 celsius");
+
+		eval_test(
+			"45°30'15\" to deg",
+			"≈ 45.504166666666666666666666666666666667 degrees",
+		);
+		eval_test(
+			"45°30'15\"",
+			"≈ 45.504166666666666666666666666666666667 degrees",
+		);
+		eval_test("-10° 7′ 30″", "-10.125 degrees");
+		eval_test("45.5042 deg to dms", "45° 30′ 15″");
+		eval_test("-10° 7′ 30″ to dms", "-10° 7′ 30″");
+		eval_test("0.5 rad to dms", "≈ 28° 38′ 52″");
+		eval_test("sin(29°60')", "0.5");
+		eval_test("5'11\"", "71 inches");
+		assert!(eval("2 m to dms").is_err());
+
+		assert!(eval("tan(90 deg)").is_err());
+		assert!(eval("sin(3 m)").is_err());
+	}
+
+	#[test]
+	fn test_inverse_and_hyperbolic_functions() {
+		eval_test("sec(60 deg)", "2");
+		eval_test("csc(-30 deg)", "-2");
+		eval_test("cot(135 deg)", "-1");
+		eval_test("cot(90 deg)", "0");
+		eval_test("sec(0)", "1");
+		eval_test("cot(1)", "≈ 0.64209261593433070300641998659426562023");
+		eval_test("30 sec to min", "0.5 minutes");
+
+		eval_test("asin(0.5)", "30 degrees");
+		eval_test(
+			"acos(-1) to rad",
+			"≈ 3.14159265358979323846264338327950288421 radians",
+		);
+		eval_test(
+			"asin(0.3) to deg",
+			"≈ 17.4576031237220922902460457924449418217 degrees",
+		);
+		eval_test("atan(1) * 4", "180 degrees");
+		eval_test("atan(-1)", "-45 degrees");
+		eval_test("atan2(-1, -1) to deg", "≈ -135 degrees");
+		eval_test(
+			"atan2(2 m, 0 m)",
+			"≈ 1.5707963267948966192313216916397514421 radians",
+		);
+		eval_test(
+			"arctan(2)",
+			"≈ 63.434948822922010648427806279546705329 degrees",
+		);
+		eval_test(
+			"asin(0.3)",
+			"≈ 17.4576031237220922902460457924449418217 degrees",
+		);
+		eval_test("acos(1)", "0 degrees");
+
+		eval_test("sinh(0)", "0");
+		eval_test("cosh(0)", "1");
+		eval_test("tanh(1)", "≈ 0.76159415595576488811945828260479359041");
+		eval_test("asinh(sinh(2))", "≈ 2");
+		eval_test("acosh(1)", "0");
+		eval_test("atanh(0.5)", "≈ 0.54930614433405484569762261846126285232");
+
+		for input in [
+			"asin(2)",
+			"acos(-1.5)",
+			"acosh(0.5)",
+			"atanh(1)",
+			"sec(90 deg)",
+			"csc(0)",
+			"cot(180 deg)",
+			"asin(1 m)",
+			"sinh(30 deg)",
+		] {
+			assert!(eval(input).is_err(), "{input}");
+		}
+	}
+
+	#[test]
+	fn test_locale_separators() {
+		let mut de = Context::new("de-DE");
+		results_eq_in("1,5 kg", "1.5 kg", &mut de);
+		results_eq_in("1.000,5 m + 0,5 m", "1001 m", &mut de);
+		results_eq_in("max(1, 5)", "5", &mut de);
+		results_eq("1 000 000 m to km", "1000 km");
+		results_eq("2,5 * 2", "5");
+
+		let options = EvalOptions::new().locale_independent(true);
+		let output = eval_with_context("1,000.5 + 1", &options, &mut de).unwrap();
+		assert_eq!(output.value.to_string(), "1001.5");
+		assert!(!de.locale_independent);
+		let options = EvalOptions::new().locale("en-US");
+		let output = eval_with_context("1,5", &options, &mut de);
+		assert!(output.is_err());
+	}
+
+	#[test]
+	fn test_multi_argument_functions() {
+		eval_test("max(3 km, 2 mi)", "2 miles");
+		eval_test("min(3 km, 2 mi, 2500 m)", "2500 meters");
+		eval_test("max(1 000, 2)", "1000");
+		eval_test("min(5 min, 2 h)", "5 minutes");
+		eval_test("2 max(1, 3)", "6");
+
+		eval_test("hypot(3 m, 4 m)", "5 meters");
+		eval_test("hypot(3 m, 400 cm)", "5 meters");
+		eval_test(
+			"atan2(1, 1)",
+			"≈ 0.78539816339744830961566084581987572105 radians",
+		);
+
+		eval_test("log(8, 2)", "3");
+		eval_test("log(0.01, 10)", "-2");
+		eval_test("log(10, 3)", "≈ 2.09590327428938460429656752202140125061");
+
+		eval_test("clamp(5, 100, 200)", "100");
+		eval_test("clamp(5 m, 1 m, 300 cm)", "3 meters");
+		eval_test("clamp(2 m, 1 m, 300 cm)", "2 meters");
+
+		assert!(matches!(
+			eval("max(1 m, 1 kg)").unwrap_err(),
+			Error::IncompatibleUnits { .. }
+		));
+		assert!(matches!(
+			eval("clamp(1, 3, 2)").unwrap_err(),
+			Error::DomainError { .. }
+		));
+		assert_eq!(
+			eval("sqrt(1, 2)").unwrap_err(),
+			Error::ArityMismatch {
+				function: "sqrt".to_string(),
+				expected: 1,
+				found: 2,
+				span: Span::new(0, 10),
+			},
+		);
+		assert!(eval("log(1, 2, 3)").is_err());
+		for input in ["sum()", "avg()", "max()", "sqrt()"] {
+			let error = eval(input).unwrap_err();
+			assert!(
+				matches!(error, Error::ArityMismatch { found: 0, .. }),
+				"{input}"
+			);
+			assert_eq!(error.span(), Span::new(0, input.len()), "{input}");
+		}
+		for (input, span) in [
+			("log(8, 1)", Span::new(7, 8)),
+			("log(8, 0)", Span::new(7, 8)),
+			("log(0, 2)", Span::new(4, 5)),
+			("log(-8, 2)", Span::new(4, 6)),
+		] {
+			let error = eval(input).unwrap_err();
+			assert!(matches!(error, Error::DomainError { .. }), "{input}");
+			assert_eq!(error.span(), span, "{input}");
+		}
+	}
+
+	#[test]
+	fn test_aggregate_functions() {
+		eval_test("sum([1, 2, 3])", "6");
+		eval_test("sum(1 km, 300 m)", "1300 meters");
+		eval_test(
+			"avg(3.2 km, 5 mi, 4100 m) to km",
+			"≈ 5.1155733333333333333333333333333333333 kilometers",
+		);
+		eval_test("mean([2 h, 30 min])", "75 minutes");
+		eval_test("median(5, 1, 3)", "3");
+		eval_test("median([5 m, 1 m], 3 m, 20 cm)", "200 centimeters");
+		eval_test("variance(1 m, 3 m)", "2 meters^2");
+		eval_test(
+			"stddev([2, 4, 4, 4, 5, 5, 7, 9])",
+			"≈ 2.13808993529939507747642784703802817244",
+		);
+		eval_test("count([1, 2, 3], 4)", "4");
+		eval_test("count([])", "0");
+		eval_test("max([3 km, 2 mi], 1 m)", "2 miles");
+		eval_test("min([4, 2])", "2");
+		eval_test("[1 m, 2 + 3]", "[1 meter, 5]");
+
+		assert!(eval("sum([])").is_err());
+		assert!(eval("avg(1 kg, 2 m)").is_err());
+		assert!(eval("stddev(5)").is_err());
+		assert!(eval("max([])").is_err());
+	}
+
+	#[test]
+	fn test_bases() {
+		eval_test("255 to hex", "0xFF");
+		eval_test("0x1F4 bytes to KiB", "0.48828125 kibibytes");
+		eval_test("0xff_ff", "65535");
+		eval_test("0b1010 to oct", "0o12");
+		eval_test("0o755 to binary", "0b111101101");
+		eval_test("255 to base 36", "73₃₆");
+		eval_test("500 bytes in hex", "0x1F4 bytes");
+		eval_test("-10 to hex", "-0xA");
+		eval_test("base = 3", "3");
+
+		assert!(eval("2.5 to hex").is_err());
+		assert!(eval("10^40 to bin").is_err());
+		assert!(eval("255 to base 37").is_err());
+		assert!(matches!(
+			eval("1 to base 2.5"),
+			Err(Error::DomainError { .. })
+		));
+		assert!(eval("0b102").is_err());
+	}
+
+	#[test]
+	fn test_bitwise_operators() {
+		eval_test("0xF0 and 0x3C to hex", "0x30");
+		eval_test("0xF0 or 0x0F to bin", "0b11111111");
+		eval_test("6 xor 3", "5");
+		eval_test("not 5", "-6");
+		eval_test("2 * not 3", "-8");
+		eval_test("1 << 10", "1024");
+		eval_test("-16 >> 2", "-4");
+		eval_test("1 >> 200", "0");
+		eval_test("popcount(0xFF)", "8");
+		// shifts bind looser than +, and `and` tighter than `xor` and `or`
+		eval_test("1 << 2 + 1", "8");
+		eval_test("1 or 2 and 3", "3");
+		eval_test("1 xor 3 or 4", "6");
+
+		eval_test("7 div 2", "3");
+		eval_test("-7 div 2", "-3");
+		eval_test("10 km div 3 m", "3333");
+		eval_test("10 m div 3", "3 meters");
+
+		assert!(eval("1.5 and 1").is_err());
+		assert!(eval("3 m and 1").is_err());
+		assert!(eval("not 1 kg").is_err());
+		assert!(eval("1 << 200").is_err());
+		assert!(eval("1 << -1").is_err());
+		assert!(eval("popcount(-1)").is_err());
+		assert!(eval("5 div 0").is_err());
+		assert!(eval("5 m div 2 kg").is_err());
+	}
+
+	#[test]
+	fn test_scientific_notation() {
+		eval_test("1.5e3", "1500");
+		eval_test("2E-4 m to mm", "0.2 millimeters");
+		eval_test("1e+2 + 1", "101");
+		eval_test("6.02×10^23 / 6.02e23", "1");
+		eval_test("6.02 * 10^-5", "0.0000602");
+		eval_test("3×10⁻² kg to g", "30 grams");
+		// the whole number is the divisor
+		eval_test("3 / 1.5×10^2", "0.02");
+		// but not with *, and not after ^
+		eval_test("3 / 1.5*10^2", "200");
+		eval_test("2^3*10^2", "800");
+		eval_test("2³ + 10⁻¹", "8.1");
+		// e on its own is still Euler's number
+		results_eq("2e", "2 * e");
+		results_eq("e", "1 * e");
+
+		assert!(eval("2⁻").is_err());
+	}
+
+	#[test]
+	fn test_rational_arithmetic() {
+		eval_test("1/3 * 3", "1");
+		eval_test("1/3 + 1/6", "0.5");
+		eval_test("(2/3)^-2", "2.25");
+		eval_test("3.6 km/h to m/s", "1 meter / second");
+		eval_test("(1/3) ft to in", "4 inches");
+		eval_test("round(7/2)", "4");
+		eval_test("floor(-1/3)", "-1");
+		eval_test("(10/3) mod (1/3)", "0");
+		eval_test("(20/3) div (1/3)", "20");
+		// transcendental functions fall back to decimals
+		eval_test("sqrt(2)^2 / 2", "≈ 1");
+
+		let number = eval("1 km/h to m/s").unwrap();
+		assert_eq!(number.rational, Rational::new(5, 18));
+		assert_eq!(eval("sin(1)").unwrap().rational, None);
+	}
+
+	#[test]
+	fn test_mixed_units() {
+		eval_test("5000 s to h min s", "1 hour 23 minutes 20 seconds");
+		eval_test("2.3 lb to lb oz", "2 pounds 4.8 ounces");
+		eval_test("1.5 h in min h", "1 hour 30 minutes");
+		eval_test("3600 s to h min s", "1 hour");
+		eval_test("0 s to h min s", "0 seconds");
+		eval_test("-66 in to ft in", "-5 feet 6 inches");
+		eval_test("6 ft to in ft", "6 feet");
+		eval_test(
+			"1.68 m to ft in",
+			"≈ 5 feet 6.1417322834645669291338582677165354331 inches",
+		);
+		eval_test("5000 s to hh:mm:ss", "01:23:20");
+		eval_test("1 day + 5.25 s to hh:mm:ss", "24:00:05.25");
+		eval_test("-90.5 min to hh:mm:ss", "-01:30:30");
+		eval_test("1e30 s to hh:mm:ss", "277777777777777777777777777:46:40");
+
+		assert!(eval("3 kg to ft in").is_err());
+		assert!(eval("5 m to hh:mm:ss").is_err());
+	}
+
+	#[test]
+	fn test_compound_input() {
+		eval_test("1h 30min", "90 minutes");
+		eval_test("5 lb 3 oz to lb oz", "5 pounds 3 ounces");
+		eval_test("1 m 80 cm", "180 centimeters");
+		eval_test("2 km 3 m 40 cm to m", "2003.4 meters");
+		eval_test("1h 30min * 2", "3 hours");
+		eval_test("-1h 30min to min", "-90 minutes");
+		results_eq("2h 15min 30s", "2h + 15min + 30s");
+
+		// units that aren't in decreasing size, or of different types, aren't added
+		assert!(eval("1 cm 2 m").is_err());
+		assert!(eval("1 kg 3 m").is_err());
+	}
+
+	#[test]
+	fn test_dates() {
+		let now = jiff::civil::date(2026, 10, 16)
+			.at(9, 30, 0, 0)
+			.to_zoned(jiff::tz::TimeZone::UTC)
+			.unwrap();
+		let mut context = Context::new("en-US").with_clock(Clock::Fixed(now));
+		let mut eval_date = |input: &str| {
+			eval_with_context(input, &EvalOptions::new(), &mut context).map(|o| o.value.to_string())
+		};
+		assert_eq!(eval_date("today").unwrap(), "2026-10-16");
+		assert_eq!(eval_date("now + 90 min").unwrap(), "2026-10-16 11:00");
+		assert_eq!(eval_date("today + 90 days").unwrap(), "2027-01-14");
+		assert_eq!(eval_date("90 days + today").unwrap(), "2027-01-14");
+		assert_eq!(
+			eval_date("2027-01-01 - today to weeks").unwrap(),
+			"11 weeks"
+		);
+		assert_eq!(eval_date("2026-01-31 + 1 month").unwrap(), "2026-02-28");
+		assert_eq!(eval_date("2024-02-29 + 1 year").unwrap(), "2025-02-28");
+		assert_eq!(
+			eval_date("Oct 16, 2026 + 1 month 3 days").unwrap(),
+			"2026-11-19"
+		);
+		assert_eq!(eval_date("today - 36 h").unwrap(), "2026-10-14 12:00");
+		assert_eq!(
+			eval_date("16 Oct 2026 14:30 - 2026-10-16").unwrap(),
+			"870 minutes"
+		);
+		assert_eq!(
+			eval_date("2026-10-16T14:30:15 - 2026-10-16 14:30").unwrap(),
+			"15 seconds"
+		);
+
+		let error = eval_date("2026-02-30 + 1 day").unwrap_err();
+		assert_eq!(error.to_string(), "Invalid date: 2026-02-30");
+		assert_eq!(error.span(), Span::new(0, 10));
+		assert!(eval_date("today + 1.5 months").is_err());
+		assert!(eval_date("today + 3 kg").is_err());
+		assert!(eval_date("today + today").is_err());
+		assert_eq!(eval_date("today * 2").unwrap_err().span(), Span::new(0, 5));
+	}
+
+	#[test]
+	fn test_time_zones() {
+		let now = jiff::civil::date(2026, 10, 16)
+			.at(9, 30, 0, 0)
+			.to_zoned(jiff::tz::TimeZone::UTC)
+			.unwrap();
+		let eval_zoned = |input: &str, locale: &str| {
+			let mut context = Context::new(locale).with_clock(Clock::Fixed(now.clone()));
+			eval_with_context(input, &EvalOptions::new(), &mut context).map(|o| o.value.to_string())
+		};
+		let eval_date = |input: &str| eval_zoned(input, "en-US");
+		assert_eq!(eval_date("3pm PST to CET").unwrap(), "2026-10-17 00:00 CET");
+		assert_eq!(
+			eval_date("3pm PST to Berlin").unwrap(),
+			"2026-10-17 01:00 CEST"
+		);
+		assert_eq!(eval_date("now in Tokyo").unwrap(), "2026-10-16 18:30 JST");
+		assert_eq!(eval_date("now Tokyo").unwrap(), "2026-10-16 18:30 JST");
+		assert_eq!(eval_date("14:30 + 2h").unwrap(), "2026-10-16 16:30");
+		assert_eq!(
+			eval_date("2026-01-15 9:00 am new york to Europe/Oslo").unwrap(),
+			"2026-01-15 15:00 CET"
+		);
+		// the clocks go forward on 2026-03-08 in the US
+		assert_eq!(
+			eval_date("2026-03-07 12:00 Los Angeles + 1 day").unwrap(),
+			"2026-03-08 12:00 PDT"
+		);
+		assert_eq!(
+			eval_date("2026-03-07 12:00 Los Angeles + 24 h").unwrap(),
+			"2026-03-08 13:00 PDT"
+		);
+		// abbreviations are a fixed offset, even when the clocks have changed
+		assert_eq!(
+			eval_date("2026-07-01 12:00 EST").unwrap(),
+			"2026-07-01 12:00 EST"
+		);
+		assert_eq!(
+			eval_date("2026-07-01 12:00 EST to New York").unwrap(),
+			"2026-07-01 13:00 EDT"
+		);
+		assert_eq!(
+			eval_date("2026-07-01 12:00 EDT to UTC").unwrap(),
+			"2026-07-01 16:00 UTC"
+		);
+		assert_eq!(
+			eval_date("2026-10-16 12:00 CEST - 2026-10-16 12:00 UTC").unwrap(),
+			"-2 hours"
+		);
+		assert_eq!(
+			eval_zoned("12:00 IST to UTC", "en-IN").unwrap(),
+			"2026-10-16 06:30 UTC"
+		);
+		assert_eq!(
+			eval_zoned("12:00 IST to UTC", "en-IE").unwrap(),
+			"2026-10-16 11:00 UTC"
+		);
+
+		assert!(eval_date("3 m to CET").is_err());
+		assert!(eval_date("now to Atlantis").is_err());
+	}
+
+	#[test]
+	fn test_fraction_output() {
+		eval_test("0.3125 in to fraction", "5/16 inches");
+		eval_test("1.5 cups to fraction", "1 1/2 cups");
+		eval_test("-7/4 to fraction", "-1 3/4");
+		eval_test("1 km/h to m/s to fraction", "5/18 meters / second");
+		eval_test("0.3 in to nearest 1/16", "≈ 5/16 inches");
+		eval_test("0.5 in to nearest 1/16", "1/2 inches");
+		eval_test("pi to fraction", "≈ 3 16/113");
+		eval_test("sqrt(2) in nearest 1/8", "≈ 1 3/8");
+		eval_test("nearest = 3", "3");
+
+		assert!(eval("2 to nearest 1/0").is_err());
+		assert!(eval("1e40 to fraction").is_err());
+	}
+
+	#[test]
+	fn test_lists_and_ranges() {
+		eval_test(
+			"[1, 2, 5] km to m",
+			"[1000 meters, 2000 meters, 5000 meters]",
+		);
+		eval_test("[1, 2] * 3 m", "[3 meters, 6 meters]");
+		eval_test("10 - [1, 2]", "[9, 8]");
+		eval_test("[1, 2] + [10, 20]", "[11, 22]");
+		eval_test("sqrt([4, 9])", "[2, 3]");
+		eval_test("[20, 30] °C to F", "[68 fahrenheit, 86 fahrenheit]");
+		eval_test(
+			"1..10 step 2 ft to cm",
+			"[30.48 centimeters, 91.44 centimeters, 152.4 centimeters, 213.36 centimeters, 274.32 centimeters]",
+		);
+		eval_test("10..1 step 3", "[10, 7, 4, 1]");
+		eval_test("1.5..3", "[1.5, 2.5]");
+		eval_test("1..3 km", "[1 kilometer, 2 kilometers, 3 kilometers]");
+		eval_test("1 .. 3", "[1, 2, 3]");
+		eval_test("sum(1..100)", "5050");
+
+		assert!(eval("[1, 2] + [1, 2, 3]").is_err());
+		assert!(eval("1..2 step 0").is_err());
+		assert!(eval("1..100000").is_err());
+		assert!(eval("1..1e9").is_err());
+	}
+
 	#[test]
 	fn test_currency() {
-		use crate::currency::{CurrencyRate, set_currency_cache};
+		use crate::currency::{CurrencyRate, ExchangeRates};
 		use serde_json::Number;
 
-		set_currency_cache(vec![CurrencyRate {
+		let exchange_rates = ExchangeRates::from_rates(vec![CurrencyRate {
 			date: "2000-01-01".to_string(),
 			base: "EUR".to_string(),
 			quote: "NOK".to_string(),
 			rate: Number::from_str("11.2839").unwrap(),
 		}])
 		.unwrap();
+		let mut context = Context::new("nb-NO").with_exchange_rates(exchange_rates);
+		let mut eval_test = |input, expected| {
+			let result = eval_in(input, &mut context).unwrap();
+			assert_eq!(result.to_string(), expected, "{input}");
+		};
 
 		eval_test("1 EUR to NOK", "11.2839 NOK");
 		eval_test("11.2839 NOK to EUR", "≈ 0.99999952902 EUR");
@@ -448,15 +2066,14 @@ mod tests {
 			"1 EUR/gallon to NOK/liter",
 			"≈ 2.98089102160411090430525272544562882356 NOK / liter",
 		);
+		eval_test("12 EUR + 112.839 NOK", "≈ 21.9999952902 EUR");
+		eval_test("sum([12 EUR, 112.839 NOK, 1 EUR])", "≈ 22.9999952902 EUR");
 	}
 
 	#[test]
-	#[serial]
 	fn test_ambiguous_evals() {
-		Settings::write().locale = "en-GB".to_string();
-		results_eq("1pound", "1gbp");
-		Settings::write().locale = "nb-NO".to_string();
-		results_eq("1pound", "1lbs");
+		results_eq_in("1pound", "1gbp", &mut Context::new("en-GB"));
+		results_eq_in("1pound", "1lbs", &mut Context::new("nb-NO"));
 	}
 
 	#[test]
@@ -464,8 +2081,8 @@ mod tests {
 		results_eq("100kg*sqm / 2s^2", "50j");
 		results_eq("3.6km/1h", "3.6 kph");
 		results_eq("0.3048 m/s to ft/s", "1 ft/s");
-		eval_test("1.609344 km/1h to mph", "≈ 1 mile per hour");
-		eval_test("1.852 kph to knots", "≈ 1 knot");
+		eval_test("1.609344 km/1h to mph", "1 mile per hour");
+		eval_test("1.852 kph to knots", "1 knot");
 		results_eq("120 seconds to minutes", "2 minutes");
 		results_eq("100 cm to m", "1 m");
 		results_eq("1 km2 to m2", "1000000 m2");
@@ -490,4 +2107,154 @@ mod tests {
 		results_eq("8 megabytes per second * 1 minute", "480mb");
 		results_eq("8 megaFLOP per second * 1 minute", "480megaFLOP");
 	}
+
+	#[test]
+	fn test_errors() {
+		let err = |input| eval(input).unwrap_err();
+
+		assert_eq!(
+			err("1 m to kg").to_string(),
+			"Cannot convert meters to kilograms"
+		);
+		assert_eq!(
+			err("sqrt(4 m)").to_string(),
+			"Unsupported unit for sqrt(): meters"
+		);
+		assert_eq!(
+			err("1001!").to_string(),
+			"Can only perform factorial on integers from 0 to 1000",
+		);
+		assert_eq!(err("3 + )").to_string(), "Unexpected )");
+		assert_eq!(
+			err("3 + foo"),
+			Error::UnknownWord {
+				word: "foo".to_string(),
+				span: Span::new(4, 7),
+			},
+		);
+		assert_eq!(err("2 @ 3").span(), Span::new(2, 3));
+		assert!(matches!(err("1 m to 4"), Error::ExpectedUnit { .. }));
+		assert_eq!(
+			err("ln(-1)"),
+			Error::DomainError {
+				message: "ln() is only defined for numbers above 0".to_string(),
+				span: Span::new(0, 6),
+			},
+		);
+		assert!(matches!(err("log(0)"), Error::DomainError { .. }));
+	}
+
+	#[test]
+	fn test_error_spans() {
+		let span = |input| eval(input).unwrap_err().span();
+
+		assert_eq!(span("2 + 1 m to kg"), Span::new(0, 7));
+		assert_eq!(span("1 m to kg"), Span::new(0, 9));
+		assert_eq!(span("2 + sqrt(4 m)"), Span::new(4, 13));
+		assert_eq!(span("(1 km + 1 kg) * 3"), Span::new(1, 12));
+		assert_eq!(span("1 m to 4"), Span::new(7, 8));
+		assert_eq!(span("3 + )"), Span::new(4, 5));
+		assert_eq!(span("2 + sqrt"), Span::new(8, 8));
+	}
+
+	#[test]
+	fn test_variables() {
+		let mut context = Context::new("nb-NO");
+		let mut eval_test = |input, expected| {
+			let result = eval_in(input, &mut context).unwrap();
+			assert_eq!(result.to_string(), expected, "{input}");
+		};
+		eval_test("rate = 45 EUR/h", "45 EUR / hour");
+		eval_test("rate * 37.5 h", "1687.5 EUR");
+		eval_test("ans / 5", "337.5 EUR");
+		eval_test("_ * 2", "675 EUR");
+		eval_test("Ans + 1 EUR", "676 EUR");
+		eval_test("ANS", "676 EUR");
+		eval_test("rate to EUR/day", "≈ 1080 EUR / day");
+		eval_test("width_2 = 3 m", "3 meters");
+		eval_test("2width_2 + 1 m", "7 meters");
+		eval_test("(1 + 1) width_2 to cm", "600 centimeters");
+
+		let mut context = Context::new("nb-NO");
+		assert_eq!(
+			eval_in("x + 1", &mut context).unwrap_err(),
+			Error::UnknownWord {
+				word: "x".to_string(),
+				span: Span::new(0, 1),
+			},
+		);
+		assert!(eval_in("ans", &mut context).is_err());
+		assert!(eval_in("1 + x = 2", &mut context).is_err());
+	}
+
+	#[test]
+	fn test_user_functions() {
+		let mut context = Context::new("nb-NO");
+		let mut eval_test = |input, expected| {
+			let options = EvalOptions::new();
+			let output = eval_with_context(input, &options, &mut context).unwrap();
+			assert_eq!(output.value.to_string(), expected, "{input}");
+		};
+		eval_test("f(x) = x^2 + 3x", "f(x)");
+		eval_test("f(2)", "10");
+		eval_test("f(-3)", "0");
+		eval_test("2f(1) + 1", "9");
+		eval_test("bmi(m, h) = m / h^2", "bmi(m, h)");
+		eval_test("bmi(81 kg, 1.8 m)", "25 kilograms / square meter");
+		eval_test("1 h to min", "60 minutes");
+		eval_test("g(a, b) = f(a) - b", "g(a, b)");
+		eval_test("g(3, 4)", "14");
+		eval_test("area(r) = pi r^2", "area(r)");
+		eval_test(
+			"round(area(2 m) to square cm)",
+			"≈ 125664 square centimeters",
+		);
+		// a function body sees global variables, not the parameters of its caller
+		eval_test("shifted(x) = x + offset", "shifted(x)");
+		eval_test("outer(offset) = shifted(1)", "outer(offset)");
+		eval_test("offset = 10", "10");
+		eval_test("outer(2)", "11");
+
+		assert_eq!(
+			eval_in("f(1, 2)", &mut context).unwrap_err(),
+			Error::ArityMismatch {
+				function: "f".to_string(),
+				expected: 1,
+				found: 2,
+				span: Span::new(0, 7),
+			},
+		);
+		assert!(matches!(
+			eval_in("unknown(1)", &mut context).unwrap_err(),
+			Error::UnknownWord { .. }
+		));
+		for (input, span) in [
+			("pi(x) = x + 100", Span::new(0, 2)),
+			("km(x) = x + 100", Span::new(0, 2)),
+			("sqrt(x) = x", Span::new(0, 4)),
+			("to(x) = x", Span::new(0, 2)),
+			("g(x, kg) = x", Span::new(5, 7)),
+			("h(a, a) = a", Span::new(5, 6)),
+			("f(e) = e", Span::new(2, 3)),
+		] {
+			let error = eval_in(input, &mut context).unwrap_err();
+			assert!(matches!(error, Error::InvalidDefinition { .. }), "{input}");
+			assert_eq!(error.span(), span, "{input}");
+		}
+		let options = EvalOptions::new();
+		let output = eval_with_context("pi(3)", &options, &mut context).unwrap();
+		assert_eq!(
+			output.value.to_string(),
+			"≈ 9.4247779607693797153879301498385086526"
+		);
+		assert!(eval_in("km(3)", &mut context).is_err());
+		eval_with_context("loop(x) = loop(x) + 1", &options, &mut context).unwrap();
+		assert_eq!(
+			eval_in("2 * loop(1)", &mut context).unwrap_err(),
+			Error::RecursionLimit {
+				function: "loop".to_string(),
+				span: Span::new(4, 11),
+			},
+		);
+	}
 }
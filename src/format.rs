@@ -0,0 +1,791 @@
+use crate::evaluator::to_i128;
+use crate::rational::Rational;
+use crate::units::{Unit, UnitType, convert, split_into_units};
+use crate::{Context, Error, Number, OutputFormat, Value};
+use fastnum::{D128, dec128 as d, decimal::RoundingMode};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// How a number is written, used by [`FormatOptions`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Notation {
+	/// Plain for numbers of a normal size and scientific for very large or
+	/// small ones
+	#[default]
+	Auto,
+	/// Never with an exponent, like `0.00000015`
+	Plain,
+	/// With one digit before the decimal point, like `1.5e-7`
+	Scientific,
+	/// With an exponent that is a multiple of 3, like `150e-9`
+	Engineering,
+}
+
+/// How digits are rounded away, used by [`FormatOptions`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Rounding {
+	/// To the nearest, with halves away from zero
+	#[default]
+	HalfUp,
+	/// To the nearest, with halves to the even neighbour
+	HalfEven,
+	/// Towards zero
+	Down,
+	/// Away from zero
+	Up,
+	/// Towards negative infinity
+	Floor,
+	/// Towards positive infinity
+	Ceiling,
+}
+
+/// The characters a locale uses in numbers, like `,` as the decimal separator
+/// and `.` between thousands in `de-DE`
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Separators {
+	/// The decimal separator, `.` or `,`
+	pub decimal: char,
+	/// The separator between groups of thousands, like `,`, `.`, `'` or a
+	/// no-break space
+	pub grouping: char,
+}
+impl Separators {
+	/// `.` as the decimal separator and `,` between thousands, like in
+	/// `en-US`. Used when reading numbers in a locale-independent way.
+	pub const INVARIANT: Separators = Separators {
+		decimal: '.',
+		grouping: ',',
+	};
+	/// The separators of a locale like `de-DE` or `fr`
+	pub fn for_locale(locale: &str) -> Separators {
+		let mut parts = locale.split(['-', '_']);
+		let language = parts.next().unwrap_or_default().to_ascii_lowercase();
+		let region = parts.next_back().unwrap_or_default().to_ascii_uppercase();
+		let (decimal, grouping) = match (language.as_str(), region.as_str()) {
+			("de" | "it" | "rm", "CH" | "LI") => ('.', '\''),
+			("es", "MX" | "US") => ('.', ','),
+			("pt", "PT") | ("fr", _) => (',', '\u{a0}'),
+			(
+				"de" | "es" | "it" | "nl" | "pt" | "da" | "id" | "tr" | "el" | "ro" | "hr" | "sl"
+				| "sr" | "vi" | "ca",
+				_,
+			) => (',', '.'),
+			(
+				"nb" | "nn" | "no" | "sv" | "fi" | "ru" | "pl" | "cs" | "sk" | "uk" | "hu" | "bg"
+				| "lt" | "lv" | "et",
+				_,
+			) => (',', '\u{a0}'),
+			_ => ('.', ','),
+		};
+		Separators { decimal, grouping }
+	}
+	/// Whether a character in the input is this grouping separator. Any kind of
+	/// space counts when the separator is a space.
+	pub(crate) fn is_grouping(&self, c: char) -> bool {
+		match self.grouping.is_whitespace() {
+			true => matches!(c, ' ' | '\u{a0}' | '\u{202f}'),
+			false => c == self.grouping || self.grouping == '\'' && c == '’',
+		}
+	}
+}
+impl Default for Separators {
+	fn default() -> Self {
+		Separators::INVARIANT
+	}
+}
+
+/// Options for showing a number with [`Number::format`].
+///
+/// Example:
+/// ```rust
+/// use cpc::{eval, EvalOptions};
+/// use cpc::format::{FormatOptions, Notation};
+///
+/// let output = eval("1 km/h to m/s", &EvalOptions::new()).unwrap();
+/// let options = FormatOptions::new().max_digits(4);
+/// assert_eq!(output.value.format(&options), "≈ 0.2778 meters / second");
+/// let options = options.notation(Notation::Scientific);
+/// assert_eq!(output.value.format(&options), "≈ 2.778e-1 meters / second");
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct FormatOptions {
+	notation: Notation,
+	max_digits: Option<u32>,
+	decimals: Option<u32>,
+	rounding: Rounding,
+	separators: Option<Separators>,
+}
+impl FormatOptions {
+	pub fn new() -> FormatOptions {
+		FormatOptions::default()
+	}
+	/// How to write the number, like [`Notation::Scientific`]
+	pub fn notation(mut self, notation: Notation) -> FormatOptions {
+		self.notation = notation;
+		self
+	}
+	/// Round to at most this many significant digits. 0 means no limit.
+	pub fn max_digits(mut self, significant_digits: u32) -> FormatOptions {
+		self.max_digits = Some(significant_digits).filter(|&digits| digits > 0);
+		self
+	}
+	/// Show exactly this many digits after the decimal point. With an
+	/// exponent, this is the number of decimals before it.
+	pub fn decimals(mut self, decimals: u32) -> FormatOptions {
+		self.decimals = Some(decimals);
+		self
+	}
+	/// How to round digits away
+	pub fn rounding(mut self, rounding: Rounding) -> FormatOptions {
+		self.rounding = rounding;
+		self
+	}
+	/// Use these separators, and group thousands. By default, `.` is the
+	/// decimal separator and thousands aren't grouped.
+	pub fn separators(mut self, separators: Separators) -> FormatOptions {
+		self.separators = Some(separators);
+		self
+	}
+	/// Use the separators of a locale like `de-DE`, see [`Separators::for_locale`]
+	pub fn locale(self, locale: &str) -> FormatOptions {
+		self.separators(Separators::for_locale(locale))
+	}
+	/// Whether separators have been set
+	pub fn has_separators(&self) -> bool {
+		self.separators.is_some()
+	}
+}
+
+impl Number {
+	/// The unit, in plural unless the value is 1
+	pub(crate) fn unit_word(&self) -> String {
+		match self.value == d!(1) {
+			true => self.singular(),
+			false => self.plural(),
+		}
+	}
+	/// Show the number with its unit, like `≈ 2.778e-1 meters / second`
+	pub fn format(&self, options: &FormatOptions) -> String {
+		let (value, rounded) = format_value(self.value, options);
+		let approx_str = match self.value.is_op_inexact() || rounded {
+			true => "≈ ",
+			false => "",
+		};
+		match self.unit_word().as_str() {
+			"" => format!("{approx_str}{value}"),
+			word => format!("{approx_str}{value} {word}"),
+		}
+	}
+}
+
+impl Value {
+	/// Show the value, with numbers shown using [`Number::format`]
+	pub fn format(&self, options: &FormatOptions) -> String {
+		match self {
+			Value::Number(number) => number.format(options),
+			Value::Function(signature) => signature.clone(),
+			Value::Formatted(number, format) => format.format(number, options),
+			Value::List(numbers) => {
+				let numbers: Vec<String> = numbers.iter().map(|n| n.format(options)).collect();
+				format!("[{}]", numbers.join(", "))
+			}
+			Value::Date(date) => date.to_string(),
+		}
+	}
+}
+
+/// The digits of a decimal number, which is `digits × 10^exponent`
+struct Digits {
+	negative: bool,
+	digits: Vec<u8>,
+	exponent: i32,
+}
+impl Digits {
+	fn new(value: D128) -> Digits {
+		let mut digits = Digits {
+			negative: value.is_negative(),
+			digits: value
+				.digits()
+				.to_string()
+				.bytes()
+				.map(|b| b - b'0')
+				.collect(),
+			exponent: -i32::from(value.fractional_digits_count()),
+		};
+		digits.trim();
+		digits
+	}
+	/// Remove trailing zeros, and make zero `0 × 10^0`
+	fn trim(&mut self) {
+		while self.digits.len() > 1 && self.digits.last() == Some(&0) {
+			self.digits.pop();
+			self.exponent += 1;
+		}
+		if self.digits.iter().all(|digit| *digit == 0) {
+			self.digits = vec![0];
+			self.exponent = 0;
+		}
+	}
+	/// The exponent in scientific notation
+	fn magnitude(&self) -> i32 {
+		self.digits.len() as i32 - 1 + self.exponent
+	}
+	/// Round to `keep` digits from the left. A negative `keep` rounds at a
+	/// position above the first digit. Returns whether anything but zeros was
+	/// rounded away.
+	fn round(&mut self, keep: i32, rounding: Rounding) -> bool {
+		if keep < 0 {
+			let zeros = -keep as usize;
+			self.digits.splice(0..0, std::iter::repeat_n(0, zeros));
+		}
+		let keep = keep.max(0) as usize;
+		if self.digits.len() <= keep {
+			return false;
+		}
+		let dropped = &self.digits[keep..];
+		let first = dropped[0];
+		let rest_nonzero = dropped[1..].iter().any(|digit| *digit != 0);
+		let nonzero = first != 0 || rest_nonzero;
+		let last_kept_odd = keep > 0 && self.digits[keep - 1] % 2 == 1;
+		let round_up = match rounding {
+			Rounding::HalfUp => first >= 5,
+			Rounding::HalfEven => first > 5 || first == 5 && (rest_nonzero || last_kept_odd),
+			Rounding::Down => false,
+			Rounding::Up => nonzero,
+			Rounding::Floor => nonzero && self.negative,
+			Rounding::Ceiling => nonzero && !self.negative,
+		};
+		self.exponent += (self.digits.len() - keep) as i32;
+		self.digits.truncate(keep);
+		if round_up {
+			let mut index = self.digits.len();
+			loop {
+				if index == 0 {
+					self.digits.insert(0, 1);
+					break;
+				}
+				index -= 1;
+				if self.digits[index] == 9 {
+					self.digits[index] = 0;
+				} else {
+					self.digits[index] += 1;
+					break;
+				}
+			}
+		}
+		self.trim();
+		nonzero
+	}
+	/// Write the digits times `10^-shift` without an exponent, with at least
+	/// `decimals` digits after the decimal point
+	fn plain(&self, shift: i32, decimals: usize) -> String {
+		let digits: String = self.digits.iter().map(|d| char::from(b'0' + d)).collect();
+		let exponent = self.exponent - shift;
+		let (integer, mut fraction) = if exponent >= 0 {
+			(digits + &"0".repeat(exponent as usize), String::new())
+		} else {
+			let fraction_len = -exponent as usize;
+			match digits.len() > fraction_len {
+				true => {
+					let (integer, fraction) = digits.split_at(digits.len() - fraction_len);
+					(integer.to_string(), fraction.to_string())
+				}
+				false => (
+					"0".to_string(),
+					"0".repeat(fraction_len - digits.len()) + &digits,
+				),
+			}
+		};
+		if fraction.len() < decimals {
+			fraction += &"0".repeat(decimals - fraction.len());
+		}
+		match fraction.is_empty() {
+			true => integer,
+			false => format!("{integer}.{fraction}"),
+		}
+	}
+}
+
+/// The exponent a number is shown with in a [`Notation`], given its magnitude
+fn notation_exponent(notation: Notation, magnitude: i32) -> i32 {
+	match notation {
+		Notation::Auto if (-7..21).contains(&magnitude) => 0,
+		Notation::Auto | Notation::Scientific => magnitude,
+		Notation::Plain => 0,
+		Notation::Engineering => magnitude.div_euclid(3) * 3,
+	}
+}
+
+/// Replace the decimal point in a number like `1234.5` with the decimal
+/// separator, and group the thousands
+fn with_separators(number: &str, separators: Separators) -> String {
+	let (integer, fraction) = match number.split_once('.') {
+		Some((integer, fraction)) => (integer, Some(fraction)),
+		None => (number, None),
+	};
+	let mut grouped = String::new();
+	for (index, digit) in integer.chars().enumerate() {
+		if index > 0 && (integer.len() - index) % 3 == 0 {
+			grouped.push(separators.grouping);
+		}
+		grouped.push(digit);
+	}
+	match fraction {
+		Some(fraction) => format!("{grouped}{}{fraction}", separators.decimal),
+		None => grouped,
+	}
+}
+
+/// Write a value with [`FormatOptions`]. Returns the string, and whether
+/// rounding changed the value.
+fn format_value(value: D128, options: &FormatOptions) -> (String, bool) {
+	if value.is_nan() || value.is_infinite() {
+		return (value.to_string(), false);
+	}
+	let mut digits = Digits::new(value);
+	let mut rounded = false;
+	if let Some(max_digits) = options.max_digits.filter(|&digits| digits > 0) {
+		rounded |= digits.round(max_digits as i32, options.rounding);
+	}
+	let mut exponent = notation_exponent(options.notation, digits.magnitude());
+	if let Some(decimals) = options.decimals {
+		let keep = digits.digits.len() as i32 + digits.exponent - exponent + decimals as i32;
+		rounded |= digits.round(keep, options.rounding);
+		// rounding up can add a digit, like 9.99 to 10.0
+		exponent = notation_exponent(options.notation, digits.magnitude());
+	}
+	let sign = match digits.negative && digits.digits != [0] {
+		true => "-",
+		false => "",
+	};
+	let decimals = options.decimals.unwrap_or(0) as usize;
+	let mut mantissa = digits.plain(exponent, decimals);
+	if let Some(separators) = options.separators {
+		mantissa = with_separators(&mantissa, separators);
+	}
+	let output = match options.notation {
+		Notation::Scientific | Notation::Engineering => format!("{sign}{mantissa}e{exponent}"),
+		Notation::Auto if exponent != 0 => format!("{sign}{mantissa}e{exponent}"),
+		Notation::Auto | Notation::Plain => format!("{sign}{mantissa}"),
+	};
+	(output, rounded)
+}
+
+impl OutputFormat {
+	/// The unit category a number needs to be shown in this format, if any
+	pub(crate) fn category(&self) -> Option<UnitType> {
+		match self {
+			OutputFormat::Dms => Some(UnitType::Angle),
+			OutputFormat::Base(_) | OutputFormat::Fraction(_) => None,
+			OutputFormat::Mixed(units) => units.first().map(Unit::category),
+			OutputFormat::Clock => Some(UnitType::Time),
+		}
+	}
+	/// Convert a number to what this format shows, or fail if the number can't
+	/// be shown in it
+	pub(crate) fn prepare(&self, number: Number, context: &Context) -> Result<Number, Error> {
+		match self {
+			OutputFormat::Dms => convert(number, vec![(Unit::Degree, 1)], context),
+			OutputFormat::Base(radix) => {
+				let name = base_name(*radix);
+				if number.value != number.value.floor() {
+					let message = format!("Only whole numbers can be shown in {name}");
+					return Err(Error::domain(message));
+				}
+				if to_i128(number.value).is_none() {
+					let message = format!("{} is too large to show in {name}", number.value);
+					return Err(Error::domain(message));
+				}
+				Ok(number)
+			}
+			OutputFormat::Fraction(denominator) => match fraction(&number, *denominator) {
+				Some(_) => Ok(number),
+				None => {
+					let message = format!("{} can't be shown as a fraction", number.value);
+					Err(Error::domain(message))
+				}
+			},
+			OutputFormat::Mixed(units) => {
+				let smallest = units.iter().min_by(|a, b| a.weight().cmp(&b.weight()));
+				let unit = smallest
+					.map(|unit| vec![(unit.clone(), 1)])
+					.unwrap_or_default();
+				convert(number, unit, context)
+			}
+			OutputFormat::Clock => convert(number, vec![(Unit::Second, 1)], context),
+		}
+	}
+	/// Show a number that has been converted with [`OutputFormat::prepare`].
+	/// The options apply to the parts of mixed units and the seconds of
+	/// `hh:mm:ss`.
+	pub fn format(&self, number: &Number, options: &FormatOptions) -> String {
+		let mut inexact = number.value.is_op_inexact();
+		let output = match self {
+			OutputFormat::Dms => format_dms(number.value),
+			OutputFormat::Base(radix) => {
+				let integer = to_i128(number.value).unwrap_or_default();
+				with_unit_word(format_base(integer, *radix), number)
+			}
+			OutputFormat::Fraction(denominator) => {
+				let (fraction, exact) =
+					fraction(number, *denominator).unwrap_or((Rational::from_integer(0), false));
+				inexact = !exact;
+				with_unit_word(format_fraction(fraction), number)
+			}
+			OutputFormat::Mixed(units) => {
+				let parts = split_into_units(number.clone(), units, &Context::default());
+				let parts = parts.unwrap_or_default();
+				inexact |= parts.iter().any(|part| part.value.is_op_inexact());
+				let (output, rounded) = format_mixed(&parts, options);
+				inexact |= rounded;
+				output
+			}
+			OutputFormat::Clock => {
+				let units = [Unit::Hour, Unit::Minute, Unit::Second];
+				let parts = split_into_units(number.clone(), &units, &Context::default());
+				let parts = parts.unwrap_or_default();
+				inexact |= parts.iter().any(|part| part.value.is_op_inexact());
+				let (output, rounded) = format_clock(&parts, options);
+				inexact |= rounded;
+				output
+			}
+		};
+		match inexact {
+			true => format!("≈ {output}"),
+			false => output,
+		}
+	}
+}
+
+/// A formatted value followed by the unit of a number, like `0xFF bytes`
+fn with_unit_word(value: String, number: &Number) -> String {
+	match number.unit_word().as_str() {
+		"" => value,
+		word => format!("{value} {word}"),
+	}
+}
+
+/// The sign of a number split into parts, which all have the same sign
+fn parts_sign(parts: &[Number]) -> &'static str {
+	match parts.iter().any(|part| part.value < D128::ZERO) {
+		true => "-",
+		false => "",
+	}
+}
+
+/// Show a number split into several units like `5 feet 6 inches`, leaving out
+/// the units that are 0. Returns the string, and whether rounding changed a
+/// part.
+fn format_mixed(parts: &[Number], options: &FormatOptions) -> (String, bool) {
+	let mut shown: Vec<&Number> = parts.iter().filter(|part| !part.value.is_zero()).collect();
+	if shown.is_empty() {
+		shown.extend(parts.last());
+	}
+	let mut rounded = false;
+	let parts_strings: Vec<String> = shown
+		.iter()
+		.map(|part| {
+			let part = Number::with_unit(part.value.abs(), part.unit.clone());
+			let (value, part_rounded) = format_value(part.value, options);
+			rounded |= part_rounded;
+			with_unit_word(value, &part)
+		})
+		.collect();
+	let output = format!("{}{}", parts_sign(parts), parts_strings.join(" "));
+	(output, rounded)
+}
+
+/// Show a duration split into hours, minutes and seconds like `01:23:20`.
+/// Hours are never in scientific notation, however many there are, and only
+/// the seconds are rounded with the options. Returns the string, and whether
+/// rounding changed the seconds.
+fn format_clock(parts: &[Number], options: &FormatOptions) -> (String, bool) {
+	let plain = FormatOptions::new().notation(Notation::Plain);
+	let seconds_options = options.clone().notation(Notation::Plain);
+	let mut rounded = false;
+	let parts_strings: Vec<String> = parts
+		.iter()
+		.enumerate()
+		.map(|(i, part)| {
+			let options = match i == parts.len() - 1 {
+				true => &seconds_options,
+				false => &plain,
+			};
+			let (value, part_rounded) = format_value(part.value.abs(), options);
+			rounded |= part_rounded;
+			match value.chars().take_while(char::is_ascii_digit).count() {
+				1 => format!("0{value}"),
+				_ => value,
+			}
+		})
+		.collect();
+	let output = format!("{}{}", parts_sign(parts), parts_strings.join(":"));
+	(output, rounded)
+}
+
+/// Show an angle in degrees as degrees, minutes and seconds like `45° 30′ 15″`,
+/// rounded to the nearest second
+fn format_dms(degrees: D128) -> String {
+	let sign = if degrees < D128::ZERO { "-" } else { "" };
+	let seconds = (degrees.abs() * d!(3600))
+		.with_rounding_mode(RoundingMode::HalfUp)
+		.round(0);
+	let minutes = (seconds / d!(60)).floor();
+	let whole_degrees = (minutes / d!(60)).floor();
+	let minutes = minutes - whole_degrees * d!(60);
+	let seconds = seconds - (whole_degrees * d!(60) + minutes) * d!(60);
+	format!(
+		"{sign}{}° {}′ {}″",
+		whole_degrees.reduce(),
+		minutes.reduce(),
+		seconds.reduce()
+	)
+}
+
+impl fmt::Display for OutputFormat {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			OutputFormat::Dms => write!(f, "dms"),
+			OutputFormat::Base(16) => write!(f, "hex"),
+			OutputFormat::Base(2) => write!(f, "bin"),
+			OutputFormat::Base(8) => write!(f, "oct"),
+			OutputFormat::Base(radix) => write!(f, "base {radix}"),
+			OutputFormat::Fraction(None) => write!(f, "fraction"),
+			OutputFormat::Fraction(Some(denominator)) => write!(f, "nearest 1/{denominator}"),
+			OutputFormat::Mixed(units) => {
+				let names: Vec<String> = units
+					.iter()
+					.map(|unit| Number::with_unit(d!(1), vec![(unit.clone(), 1)]).singular())
+					.collect();
+				write!(f, "{}", names.join(" "))
+			}
+			OutputFormat::Clock => write!(f, "hh:mm:ss"),
+		}
+	}
+}
+
+/// Numbers that aren't rational are shown as the closest fraction with a
+/// denominator up to this
+const MAX_APPROXIMATE_DENOMINATOR: i128 = 10_000;
+
+/// The fraction a number is shown as, rounded to a denominator if there is
+/// one, and whether it's exact
+fn fraction(number: &Number, denominator: Option<u32>) -> Option<(Rational, bool)> {
+	match (number.rational, denominator) {
+		(Some(rational), None) => Some((rational, true)),
+		(Some(rational), Some(denominator)) => {
+			let rounded = rational.round_to(i128::from(denominator))?;
+			Some((rounded, rounded == rational))
+		}
+		(None, None) => {
+			let fraction = Rational::approximate(number.value, MAX_APPROXIMATE_DENOMINATOR)?;
+			Some((fraction, false))
+		}
+		(None, Some(denominator)) => {
+			let fraction = Rational::round_decimal_to(number.value, i128::from(denominator))?;
+			Some((fraction, false))
+		}
+	}
+}
+
+/// Show a fraction as a mixed number like `1 5/16` if it's larger than 1
+fn format_fraction(fraction: Rational) -> String {
+	let (numerator, denominator) = (fraction.numerator(), fraction.denominator());
+	let whole = numerator / denominator;
+	let remainder = numerator % denominator;
+	match (whole, remainder) {
+		(_, 0) => whole.to_string(),
+		(0, _) => format!("{remainder}/{denominator}"),
+		_ => format!("{whole} {}/{denominator}", remainder.abs()),
+	}
+}
+
+/// The name of a base, like `hexadecimal` or `base 36`
+fn base_name(radix: u32) -> String {
+	match radix {
+		2 => "binary".to_string(),
+		8 => "octal".to_string(),
+		16 => "hexadecimal".to_string(),
+		_ => format!("base {radix}"),
+	}
+}
+
+/// Show an integer in a base from 2 to 36, with a prefix like `0xFF` for the
+/// bases that have one and a subscript like `73₃₆` otherwise
+fn format_base(integer: i128, radix: u32) -> String {
+	let sign = if integer < 0 { "-" } else { "" };
+	let mut magnitude = integer.unsigned_abs();
+	let mut digits = Vec::new();
+	loop {
+		let digit = char::from_digit((magnitude % radix as u128) as u32, radix).unwrap_or('?');
+		digits.push(digit.to_ascii_uppercase());
+		magnitude /= radix as u128;
+		if magnitude == 0 {
+			break;
+		}
+	}
+	let digits: String = digits.iter().rev().collect();
+	match radix {
+		2 => format!("{sign}0b{digits}"),
+		8 => format!("{sign}0o{digits}"),
+		16 => format!("{sign}0x{digits}"),
+		_ => {
+			const SUBSCRIPTS: [char; 10] = ['₀', '₁', '₂', '₃', '₄', '₅', '₆', '₇', '₈', '₉'];
+			let subscript: String = radix
+				.to_string()
+				.chars()
+				.filter_map(|c| c.to_digit(10).map(|digit| SUBSCRIPTS[digit as usize]))
+				.collect();
+			format!("{sign}{digits}{subscript}")
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_format_dms() {
+		assert_eq!(format_dms(d!(45.5042)), "45° 30′ 15″");
+		assert_eq!(format_dms(d!(-12.25)), "-12° 15′ 0″");
+		assert_eq!(format_dms(d!(29.99999)), "30° 0′ 0″");
+		assert_eq!(format_dms(d!(0)), "0° 0′ 0″");
+	}
+
+	#[test]
+	fn test_format_options() {
+		let format = |value: D128, options: FormatOptions| {
+			let (output, rounded) = format_value(value, &options);
+			format!("{}{output}", if rounded { "≈ " } else { "" })
+		};
+		let default = FormatOptions::new;
+		assert_eq!(format(d!(1500), default()), "1500");
+		assert_eq!(format(d!(0.0000602), default()), "0.0000602");
+		assert_eq!(format(d!(6.02e23), default()), "6.02e23");
+		assert_eq!(format(d!(-1.5e-9), default()), "-1.5e-9");
+		assert_eq!(
+			format(d!(6.02e23), default().notation(Notation::Plain)),
+			"602000000000000000000000"
+		);
+		assert_eq!(
+			format(d!(1500), default().notation(Notation::Scientific)),
+			"1.5e3"
+		);
+		assert_eq!(
+			format(d!(0.00015), default().notation(Notation::Engineering)),
+			"150e-6"
+		);
+		assert_eq!(
+			format(d!(1500), default().notation(Notation::Engineering)),
+			"1.5e3"
+		);
+
+		assert_eq!(format(d!(2.71828), default().max_digits(3)), "≈ 2.72");
+		assert_eq!(format(d!(123456), default().max_digits(2)), "≈ 120000");
+		assert_eq!(format(d!(2.5), default().max_digits(1)), "≈ 3");
+		assert_eq!(
+			format(
+				d!(2.5),
+				default().max_digits(1).rounding(Rounding::HalfEven)
+			),
+			"≈ 2"
+		);
+		assert_eq!(
+			format(d!(-2.1), default().max_digits(1).rounding(Rounding::Floor)),
+			"≈ -3"
+		);
+		assert_eq!(
+			format(d!(2.9), default().max_digits(1).rounding(Rounding::Down)),
+			"≈ 2"
+		);
+		assert_eq!(format(d!(0.25), default().max_digits(5)), "0.25");
+		assert_eq!(format(d!(2.71828), default().max_digits(0)), "2.71828");
+
+		assert_eq!(format(d!(3.14159), default().decimals(2)), "≈ 3.14");
+		assert_eq!(format(d!(2), default().decimals(2)), "2.00");
+		assert_eq!(format(d!(0.004), default().decimals(2)), "≈ 0.00");
+		assert_eq!(
+			format(
+				d!(9.996),
+				default().decimals(2).notation(Notation::Scientific)
+			),
+			"≈ 1.00e1"
+		);
+		assert_eq!(
+			format(d!(-0.004), default().decimals(1).rounding(Rounding::Floor)),
+			"≈ -0.1"
+		);
+
+		assert_eq!(
+			format(d!(1234567.5), default().locale("en-US")),
+			"1,234,567.5"
+		);
+		assert_eq!(
+			format(d!(1234567.5), default().locale("de-DE")),
+			"1.234.567,5"
+		);
+		assert_eq!(
+			format(d!(1234.5), default().locale("nb-NO")),
+			"1\u{a0}234,5"
+		);
+		assert_eq!(format(d!(1234.5), default().locale("de-CH")), "1'234.5");
+		assert_eq!(format(d!(123), default().locale("de-DE")), "123");
+		assert_eq!(format(d!(1.5e30), default().locale("fr-FR")), "1,5e30");
+	}
+
+	#[test]
+	fn test_output_format_options() {
+		let format = |input: &str, options: FormatOptions| {
+			let output = crate::eval(input, &crate::EvalOptions::new()).unwrap();
+			output.value.format(&options)
+		};
+		let digits = FormatOptions::new().max_digits(4);
+		assert_eq!(
+			format("1.68 m to ft in", digits.clone()),
+			"≈ 5 feet 6.142 inches"
+		);
+		assert_eq!(format("66 in to ft in", digits.clone()), "5 feet 6 inches");
+		assert_eq!(
+			format("5000.125 s to hh:mm:ss", digits.clone()),
+			"≈ 01:23:20.13"
+		);
+		assert_eq!(
+			format("1e30 s to hh:mm:ss", digits),
+			"277777777777777777777777777:46:40"
+		);
+		let german = FormatOptions::new().locale("de-DE");
+		assert_eq!(
+			format("2.3 lb to lb oz", german.clone()),
+			"2 pounds 4,8 ounces"
+		);
+		assert_eq!(format("5.25 s to hh:mm:ss", german), "00:00:05,25");
+		let scientific = FormatOptions::new().notation(Notation::Scientific);
+		assert_eq!(format("66 in to ft in", scientific), "5e0 feet 6e0 inches");
+	}
+
+	#[test]
+	fn test_separators() {
+		assert_eq!(Separators::for_locale("en-US"), Separators::INVARIANT);
+		assert_eq!(Separators::for_locale("").decimal, '.');
+		assert_eq!(Separators::for_locale("de").grouping, '.');
+		assert_eq!(Separators::for_locale("pt_BR").decimal, ',');
+		assert_eq!(Separators::for_locale("es-MX").decimal, '.');
+		assert!(Separators::for_locale("sv-SE").is_grouping('\u{202f}'));
+		assert!(Separators::for_locale("de-CH").is_grouping('’'));
+	}
+
+	#[test]
+	fn test_format_base() {
+		assert_eq!(format_base(255, 16), "0xFF");
+		assert_eq!(format_base(10, 2), "0b1010");
+		assert_eq!(format_base(493, 8), "0o755");
+		assert_eq!(format_base(0, 16), "0x0");
+		assert_eq!(format_base(-255, 16), "-0xFF");
+		assert_eq!(format_base(255, 36), "73₃₆");
+		assert_eq!(format_base(i128::MAX, 2).len(), 129);
+	}
+}
@@ -4,14 +4,18 @@ use crate::LexerKeyword::*;
 use crate::NamedNumber::*;
 use crate::Operator::*;
 use crate::TextOperator::*;
-use crate::Token;
 use crate::UnaryOperator::*;
 use crate::currency::currency_code_to_unit;
-use crate::get_region;
-use crate::units::Ambiguity;
+use crate::datetime::{parse_date, parse_time_of_day};
+use crate::evaluator::to_i128;
+use crate::format::Separators;
+use crate::time_zones::parse_time_zone;
 use crate::units::Unit::*;
+use crate::units::{Ambiguity, CustomUnit, Unit, UnitType, find_custom_unit};
+use crate::{Context, Error, OutputFormat, Span};
+use crate::{SpannedToken, Token};
 use fastnum::D128;
-use fastnum::decimal::Context;
+use fastnum::decimal::Context as DecimalContext;
 use std::iter::Peekable;
 use unicode_segmentation::{GraphemeIndices, UnicodeSegmentation};
 
@@ -22,7 +26,7 @@ fn is_word_char_str(input: &str) -> bool {
 		"a" | "b" | "c" | "d" | "e" | "f" | "g" | "h" | "i" | "j" | "k" | "l" | "m" | "n" | "o"
 		| "p" | "q" | "r" | "s" | "t" | "u" | "v" | "w" | "x" | "y" | "z" => true,
 		"Ω" | "Ω" | "µ" | "μ" | "ł" | "$" | "€" | "£" | "₹" | "₪" | "¥" | "₩" | "₱" | "฿" | "₺"
-		| "₴" | "₫" | "đ" | "Đ" | "č" | "°" => true,
+		| "₴" | "₫" | "đ" | "Đ" | "č" | "°" | "_" => true,
 		_ => false,
 	}
 }
@@ -34,13 +38,108 @@ fn is_numeric_str(input: &str) -> bool {
 	)
 }
 
+/// Whether a grouping separator is a thousands separator, which is the case if
+/// it's followed by exactly three digits, like in `1,000`
+fn is_thousands_separator(after_separator: &str) -> bool {
+	after_separator
+		.bytes()
+		.take_while(u8::is_ascii_digit)
+		.count()
+		== 3
+}
+
+/// The base of a number that starts with a prefix like `0x` for hexadecimal,
+/// `0b` for binary or `0o` for octal. The prefix only counts if a digit of
+/// that base follows it, so `0b` on its own is still 0 bits.
+fn radix_prefix(input: &str) -> Option<u32> {
+	let radix = match input.get(..2)?.to_ascii_lowercase().as_str() {
+		"0x" => 16,
+		"0b" => 2,
+		"0o" => 8,
+		_ => return None,
+	};
+	let first_digit = input[2..].chars().next()?;
+	first_digit.is_digit(radix).then_some(radix)
+}
+
+/// The digit of a superscript like `³`, or the sign of one like `⁻`
+fn superscript_digit(c: char) -> Option<char> {
+	let digit = match c {
+		'⁰' => '0',
+		'¹' => '1',
+		'²' => '2',
+		'³' => '3',
+		'⁴' => '4',
+		'⁵' => '5',
+		'⁶' => '6',
+		'⁷' => '7',
+		'⁸' => '8',
+		'⁹' => '9',
+		'⁺' => '+',
+		'⁻' => '-',
+		_ => return None,
+	};
+	Some(digit)
+}
+
+/// Read an integer with an optional sign from the start of `input`, where
+/// `digit` turns characters into `0`-`9`, `+` or `-`. Returns the integer and
+/// the rest of the input.
+fn read_signed_digits(input: &str, digit: fn(char) -> Option<char>) -> Option<(String, &str)> {
+	let mut integer = String::new();
+	let mut rest = input;
+	while let Some(c) = rest.chars().next()
+		&& let Some(digit) = digit(c)
+		&& (digit.is_ascii_digit() || integer.is_empty())
+	{
+		integer.push(digit);
+		rest = &rest[c.len_utf8()..];
+	}
+	match integer.ends_with(|c: char| c.is_ascii_digit()) {
+		true => Some((integer, rest)),
+		false => None,
+	}
+}
+
+/// The exponent of a number like `1.5e3` or `2E-4`, given the input after the
+/// digits. Returns the length of the exponent and its value.
+fn e_exponent(after: &str) -> Option<(usize, String)> {
+	let rest = after.strip_prefix(['e', 'E'])?;
+	let ascii = |c: char| (c.is_ascii_digit() || c == '+' || c == '-').then_some(c);
+	let (exponent, rest) = read_signed_digits(rest, ascii)?;
+	Some((after.len() - rest.len(), exponent))
+}
+
+/// The exponent of a number like `6.02×10^23`, `6.02 * 10^-5` or `6.02×10²³`,
+/// given the input after the digits. Returns the length of the `×10^n` and the
+/// exponent.
+fn times_ten_exponent(after: &str, allow_asterisk: bool) -> Option<(usize, String)> {
+	let rest = after.trim_start();
+	let rest = match rest.strip_prefix('×') {
+		Some(rest) => rest,
+		None if allow_asterisk => rest.strip_prefix('*')?,
+		None => return None,
+	};
+	let rest = rest.trim_start().strip_prefix("10")?;
+	let ascii = |c: char| (c.is_ascii_digit() || c == '+' || c == '-').then_some(c);
+	let (exponent, rest) = match rest.strip_prefix('^') {
+		Some(rest) => read_signed_digits(rest, ascii)?,
+		None => read_signed_digits(rest, superscript_digit)?,
+	};
+	// an exponent like ^2.5 or ^2! isn't an integer power of ten on its own
+	if rest.starts_with(|c: char| c.is_ascii_digit() || c == '.' || c == '!') {
+		return None;
+	}
+	Some((after.len() - rest.len(), exponent))
+}
+
 /// For example parse a hyphen with no whitespace before or after it
 fn read_immediate_grapheme(infix: &str, lexer: &mut Lexer) -> bool {
-	if let Some((_i, grapheme)) = lexer.graphemes.peek() {
-		if *grapheme == infix {
-			lexer.graphemes.next();
-			return true;
-		}
+	if let Some((_i, grapheme)) = lexer.graphemes.peek()
+		&& *grapheme == infix
+	{
+		lexer.graphemes.next();
+		return true;
 	}
 	false
 }
@@ -48,6 +147,7 @@ fn read_immediate_grapheme(infix: &str, lexer: &mut Lexer) -> bool {
 /// Read next characters as a word, otherwise return empty string.
 /// Returns an empty string if there's leading whitespace.
 fn read_immediate_word(lexer: &mut Lexer) -> String {
+	lexer.word_start = lexer.pos();
 	let graphemes = &mut lexer.graphemes;
 
 	let mut word = String::new();
@@ -73,6 +173,8 @@ fn read_word(lexer: &mut Lexer) -> String {
 			break;
 		}
 	}
+	lexer.word_start = lexer.pos();
+	let graphemes = &mut lexer.graphemes;
 	let mut word = "".to_string();
 	while let Some((_i, grapheme)) = graphemes.peek() {
 		if is_word_char_str(grapheme) {
@@ -81,7 +183,8 @@ fn read_word(lexer: &mut Lexer) -> String {
 			break;
 		}
 	}
-	if !word.is_empty() {
+	// exponents like ft2, but not the minutes after a degree sign like 45°30'
+	if !word.is_empty() && word != "°" {
 		match *graphemes.peek().map(|(_i, g)| g).unwrap_or(&"") {
 			"2" | "²" => {
 				word += "2";
@@ -97,7 +200,7 @@ fn read_word(lexer: &mut Lexer) -> String {
 	word
 }
 
-fn lex_token(lexer: &mut Lexer) -> Result<(), String> {
+fn lex_token(lexer: &mut Lexer) -> Result<(), Error> {
 	let (start_i, first_grapheme) = match lexer.graphemes.peek() {
 		Some(c) => *c,
 		None => return Ok(()),
@@ -107,37 +210,200 @@ fn lex_token(lexer: &mut Lexer) -> Result<(), String> {
 			lexer.graphemes.next();
 			return Ok(());
 		}
+		// a date like 2026-10-16, 16 Oct 2026 or Oct 16, 2026
+		grapheme
+			if (is_numeric_str(grapheme) || is_word_char_str(grapheme))
+				&& let Some((date, length)) = parse_date(&lexer.input[start_i..]) =>
+		{
+			let span = Span::new(start_i, start_i + length);
+			let date = date.map_err(|_e| Error::InvalidDate {
+				date: lexer.input[span.start..span.end].to_string(),
+				span,
+			})?;
+			lexer.skip_to(span.end);
+			lexer.push(Token::Date(date), span);
+			return Ok(());
+		}
+		// a time like 14:30 or 3pm
+		grapheme
+			if is_numeric_str(grapheme)
+				&& let Some((time, length)) = parse_time_of_day(&lexer.input[start_i..]) =>
+		{
+			let end = start_i + length;
+			lexer.skip_to(end);
+			lexer.push(Token::Time(time), Span::new(start_i, end));
+			return Ok(());
+		}
+		// a time zone like PST, Tokyo or Europe/Oslo
+		grapheme
+			if is_word_char_str(grapheme)
+				&& lexer.expects_time_zone()
+				&& let Some((zone, length)) =
+					parse_time_zone(&lexer.input[start_i..], lexer.region) =>
+		{
+			let end = start_i + length;
+			lexer.skip_to(end);
+			lexer.push(Token::TimeZone(zone), Span::new(start_i, end));
+			return Ok(());
+		}
+		"h" if lexer.input[start_i..]
+			.get(.."hh:mm:ss".len())
+			.is_some_and(|format| format.eq_ignore_ascii_case("hh:mm:ss")) =>
+		{
+			let end = start_i + "hh:mm:ss".len();
+			lexer.skip_to(end);
+			lexer.push(Token::Format(OutputFormat::Clock), Span::new(start_i, end));
+			return Ok(());
+		}
 		grapheme if is_word_char_str(grapheme) => {
 			lex_word(read_word(lexer).as_str(), lexer)?;
 			return Ok(());
 		}
+		"." if lexer.input[start_i..].starts_with("..") => {
+			lexer.graphemes.next();
+			lexer.graphemes.next();
+			lexer.push(Token::Operator(Range), Span::new(start_i, start_i + 2));
+			return Ok(());
+		}
+		"<" | ">" if lexer.input[start_i..].starts_with(&first_grapheme.repeat(2)) => {
+			let operator = match first_grapheme {
+				"<" => ShiftLeft,
+				_ => ShiftRight,
+			};
+			lexer.graphemes.next();
+			lexer.graphemes.next();
+			lexer.push(Token::Operator(operator), Span::new(start_i, start_i + 2));
+			return Ok(());
+		}
+		"0" if let Some(radix) = radix_prefix(&lexer.input[start_i..]) => {
+			lexer.graphemes.next();
+			lexer.graphemes.next();
+			let mut end_i = start_i + 2;
+			while let Some((_i, grapheme)) = lexer.graphemes.peek()
+				// decimal digits that aren't in the base make the number invalid, like 0b12
+				&& (grapheme.chars().all(|c| c.is_digit(radix.max(10))) || *grapheme == "_")
+			{
+				end_i += grapheme.len();
+				lexer.graphemes.next();
+			}
+			let number_string = &lexer.input[start_i..end_i];
+			let digits = number_string[2..].replace('_', "");
+			let number = i128::from_str_radix(&digits, radix)
+				.ok()
+				.and_then(|number| D128::try_from(number).ok());
+			let Some(number) = number else {
+				return Err(Error::InvalidNumber {
+					number: number_string.to_string(),
+					span: Span::new(start_i, end_i),
+				});
+			};
+			lexer.push(Token::Number(number), Span::new(start_i, end_i));
+			return Ok(());
+		}
 		grapheme if is_numeric_str(grapheme) => {
+			let separators = lexer.separators;
 			let mut end_i = start_i + grapheme.len();
+			let mut number_string = grapheme.to_string();
+			let mut has_decimal = grapheme == ".";
+			// digits since the start or the last thousands separator
+			let mut group_digits = usize::from(!has_decimal);
 			lexer.graphemes.next();
-			while let Some((_, grapheme)) = lexer.graphemes.peek() {
-				if is_numeric_str(grapheme) {
-					end_i += grapheme.len();
-					lexer.graphemes.next();
+			while let Some((i, grapheme)) = lexer.graphemes.peek() {
+				let (i, grapheme) = (*i, *grapheme);
+				let after = &lexer.input[i + grapheme.len()..];
+				let c = grapheme.chars().next().unwrap_or_default();
+				if grapheme == "." && lexer.input[i..].starts_with("..") {
+					// the end of a range like 1..10
+					break;
+				} else if c.is_ascii_digit() {
+					number_string.push(c);
+					group_digits += 1;
+				} else if separators.is_grouping(c)
+					&& !has_decimal && (1..=3).contains(&group_digits)
+					&& is_thousands_separator(after)
+				{
+					// thousands separator, like 1,000 or 1.000 in de-DE
+					group_digits = 0;
+				} else if grapheme == "."
+					|| c == separators.decimal
+						&& !has_decimal && after.starts_with(|c: char| c.is_ascii_digit())
+				{
+					// a decimal comma like 1,5 in de-DE. A point is a decimal point
+					// in every locale unless it's a thousands separator.
+					number_string.push('.');
+					has_decimal = true;
 				} else {
 					break;
 				}
+				end_i += grapheme.len();
+				lexer.graphemes.next();
 			}
-			let number_string = &lexer.input[start_i..end_i];
-			let token = match D128::from_str(&number_string, Context::default()) {
+			// scientific notation like 1.5e3, 6.02×10^23 or 6.02×10²³, except for
+			// `*10^n` after a division or anything after a ^, where the number
+			// isn't the whole operand
+			let last_token = lexer.tokens.last().map(|t| &t.token);
+			let after_caret = last_token == Some(&Token::Operator(Caret));
+			let after_divide = last_token == Some(&Token::Operator(Divide));
+			let after = &lexer.input[end_i..];
+			let exponent = e_exponent(after)
+				.or_else(|| times_ten_exponent(after, !after_divide).filter(|_| !after_caret));
+			if let Some((length, exponent)) = exponent {
+				end_i += length;
+				lexer.skip_to(end_i);
+				number_string = format!("{number_string}e{exponent}");
+			}
+			let token = match D128::from_str(&number_string, DecimalContext::default()) {
 				Ok(number) => Token::Number(number),
 				Err(_e) => {
-					return Err(format!("Error lexing d128 number: {}", number_string));
+					return Err(Error::InvalidNumber {
+						number: lexer.input[start_i..end_i].to_string(),
+						span: Span::new(start_i, end_i),
+					});
 				}
 			};
-			lexer.tokens.push(token);
+			lexer.push(token, Span::new(start_i, end_i));
+			return Ok(());
+		}
+		// superscript exponents like the ³ in 2³ or the ⁻² in 10⁻²
+		grapheme if grapheme.chars().all(|c| superscript_digit(c).is_some()) => {
+			let Some((exponent, rest)) =
+				read_signed_digits(&lexer.input[start_i..], superscript_digit)
+			else {
+				return Err(Error::InvalidCharacter {
+					character: first_grapheme.to_string(),
+					span: Span::new(start_i, start_i + first_grapheme.len()),
+				});
+			};
+			let end_i = lexer.input.len() - rest.len();
+			lexer.skip_to(end_i);
+			let digits_start = match exponent.starts_with(['+', '-']) {
+				true => start_i + first_grapheme.len(),
+				false => start_i,
+			};
+			lexer.push(Token::Operator(Caret), Span::new(start_i, start_i));
+			if exponent.starts_with('-') {
+				lexer.push(Token::Operator(Minus), Span::new(start_i, digits_start));
+			}
+			let digits = exponent.trim_start_matches(['+', '-']);
+			let number = D128::from_str(digits, DecimalContext::default()).map_err(|_e| {
+				Error::InvalidNumber {
+					number: lexer.input[digits_start..end_i].to_string(),
+					span: Span::new(digits_start, end_i),
+				}
+			})?;
+			lexer.push(Token::Number(number), Span::new(digits_start, end_i));
 			return Ok(());
 		}
 		"+" => Token::Operator(Plus),
 		"-" => Token::Operator(Minus),
-		"*" => Token::Operator(Multiply),
+		"*" | "×" => Token::Operator(Multiply),
 		"/" | "÷" => Token::Operator(Divide),
 		"%" => Token::LexerKeyword(PercentChar),
 		"^" => Token::Operator(Caret),
+		"=" => Token::Operator(Assign),
+		"," => Token::Operator(Comma),
+		"[" => Token::Operator(LeftBracket),
+		"]" => Token::Operator(RightBracket),
 		"!" => Token::UnaryOperator(Factorial),
 		"(" => {
 			lexer.left_paren_count += 1;
@@ -148,25 +414,31 @@ fn lex_token(lexer: &mut Lexer) -> Result<(), String> {
 			Token::Operator(RightParen)
 		}
 		"π" => Token::Constant(Pi),
-		"'" => Token::unit(Foot),
+		"'" | "′" => Token::unit(Foot),
 		"\"" | "“" | "”" | "″" => Token::LexerKeyword(DoubleQuotes),
-		grapheme => {
-			return Err(format!("Invalid character: {}", grapheme));
+		_ => {
+			return Err(Error::InvalidCharacter {
+				character: first_grapheme.to_string(),
+				span: Span::new(start_i, start_i + first_grapheme.len()),
+			});
 		}
 	};
 	lexer.graphemes.next();
-	lexer.tokens.push(token);
+	lexer.push(token, Span::new(start_i, start_i + first_grapheme.len()));
 	Ok(())
 }
 
-fn lex_word_if_non_empty(word: &str, lexer: &mut Lexer) -> Result<(), String> {
+fn lex_word_if_non_empty(word: &str, lexer: &mut Lexer) -> Result<(), Error> {
 	match word {
 		"" => Ok(()),
 		_ => lex_word(word, lexer),
 	}
 }
 
-fn lex_word(word: &str, lexer: &mut Lexer) -> Result<(), String> {
+fn lex_word(word: &str, lexer: &mut Lexer) -> Result<(), Error> {
+	let start = lexer.word_start;
+	// span of only the first word, for when a following word is lexed separately
+	let first_word = lexer.span_from(start);
 	let token = match word.to_ascii_lowercase().as_str() {
 		"to" | "as" | "into" => Token::TextOperator(To),
 		"of" => Token::TextOperator(Of),
@@ -196,6 +468,9 @@ fn lex_word(word: &str, lexer: &mut Lexer) -> Result<(), String> {
 		"centillion" => Token::NamedNumber(Centillion),
 		"googol" => Token::NamedNumber(Googol),
 
+		"now" => Token::Now,
+		"today" => Token::Today,
+
 		"pi" => Token::Constant(Pi),
 		"e" => Token::Constant(E),
 
@@ -204,16 +479,22 @@ fn lex_word(word: &str, lexer: &mut Lexer) -> Result<(), String> {
 		"times" => Token::Operator(Multiply),
 		"multiplied" => match read_word(lexer).as_str() {
 			"by" => Token::Operator(Multiply),
-			string => return Err(format!("Invalid string: {}", string)),
+			_ => return Err(lexer.unknown_word(start)),
 		},
 		"divided" => match read_word(lexer).as_str() {
 			"by" => Token::Operator(Divide),
-			string => return Err(format!("Invalid string: {}", string)),
+			_ => return Err(lexer.unknown_word(start)),
 		},
 		"mod" => Token::Operator(Modulo),
+		"div" => Token::Operator(IntegerDivide),
+		"and" => Token::Operator(And),
+		"or" => Token::Operator(Or),
+		"xor" => Token::Operator(Xor),
+		"not" => Token::UnaryOperator(Not),
 
 		"sqrt" => Token::FunctionIdentifier(Sqrt),
 		"cbrt" => Token::FunctionIdentifier(Cbrt),
+		"hypot" => Token::FunctionIdentifier(Hypot),
 
 		"log" => Token::FunctionIdentifier(Log),
 		"ln" => Token::FunctionIdentifier(Ln),
@@ -223,12 +504,49 @@ fn lex_word(word: &str, lexer: &mut Lexer) -> Result<(), String> {
 		"ceil" => Token::FunctionIdentifier(Ceil),
 		"floor" => Token::FunctionIdentifier(Floor),
 		"abs" | "fabs" => Token::FunctionIdentifier(Abs),
+		"popcount" | "popcnt" => Token::FunctionIdentifier(Popcount),
+
+		// "min" is minutes unless it's followed by "(", see `lex`
+		"max" => Token::FunctionIdentifier(Max),
+		"clamp" => Token::FunctionIdentifier(Clamp),
+
+		// "sum" is a variable unless it's followed by "(", see `lex`
+		"sum" => Token::FunctionIdentifier(Sum),
+		"avg" | "mean" | "average" => Token::FunctionIdentifier(Avg),
+		"median" => Token::FunctionIdentifier(Median),
+		"stddev" | "stdev" => Token::FunctionIdentifier(Stddev),
+		"variance" => Token::FunctionIdentifier(Variance),
+		"count" => Token::FunctionIdentifier(Count),
 
 		"sin" => Token::FunctionIdentifier(Sin),
 		"cos" => Token::FunctionIdentifier(Cos),
 		"tan" => Token::FunctionIdentifier(Tan),
+		// "sec(" is secant rather than seconds, see `lex`
+		"csc" | "cosec" => Token::FunctionIdentifier(Csc),
+		"cot" | "cotan" => Token::FunctionIdentifier(Cot),
+		"asin" | "arcsin" => Token::FunctionIdentifier(Asin),
+		"acos" | "arccos" => Token::FunctionIdentifier(Acos),
+		"atan" | "arctan" => Token::FunctionIdentifier(Atan),
+
+		"sinh" => Token::FunctionIdentifier(Sinh),
+		"cosh" => Token::FunctionIdentifier(Cosh),
+		"tanh" => Token::FunctionIdentifier(Tanh),
+		"asinh" | "arsinh" | "arcsinh" => Token::FunctionIdentifier(Asinh),
+		"acosh" | "arcosh" | "arccosh" => Token::FunctionIdentifier(Acosh),
+		"atanh" | "artanh" | "arctanh" => Token::FunctionIdentifier(Atanh),
+
+		"dms" => Token::Format(OutputFormat::Dms),
+		"hex" | "hexadecimal" => Token::Format(OutputFormat::Base(16)),
+		"bin" | "binary" => Token::Format(OutputFormat::Base(2)),
+		"oct" | "octal" => Token::Format(OutputFormat::Base(8)),
+		// "base" is a format like "base 36" if it's followed by a number, see `lex`
+		"base" => Token::LexerKeyword(Base),
+		"fraction" | "fractions" => Token::Format(OutputFormat::Fraction(None)),
+		// "nearest" is a format like "nearest 1/16" if it's followed by a fraction
+		"nearest" => Token::LexerKeyword(Nearest),
 
 		"per" => Token::TextOperator(Per),
+		"step" => Token::TextOperator(Step),
 		"hg" => Token::LexerKeyword(Hg), // can be hectogram or mercury
 
 		"ns" | "nanosec" | "nanosecs" | "nanosecond" | "nanoseconds" => Token::unit(Nanosecond),
@@ -269,14 +587,14 @@ fn lex_word(word: &str, lexer: &mut Lexer) -> Result<(), String> {
 		"nmi" => Token::unit(NauticalMile),
 		"nautical" => match read_word(lexer).as_str() {
 			"mile" | "miles" => Token::unit(NauticalMile),
-			string => return Err(format!("Invalid string: {}", string)),
+			_ => return Err(lexer.unknown_word(start)),
 		},
 		"ly" | "lightyear" | "lightyears" => Token::unit(LightYear),
 		"lightsec" | "lightsecs" | "lightsecond" | "lightseconds" => Token::unit(LightSecond),
 		"light" => match read_word(lexer).as_str() {
 			"yr" | "yrs" | "year" | "years" => Token::unit(LightYear),
 			"sec" | "secs" | "second" | "seconds" => Token::unit(LightSecond),
-			string => return Err(format!("Invalid string: {}", string)),
+			_ => return Err(lexer.unknown_word(start)),
 		},
 
 		"sqmm" | "mm2" | "millimeter2" | "millimeters2" | "millimetre2" | "millimetres2" => {
@@ -314,7 +632,7 @@ fn lex_word(word: &str, lexer: &mut Lexer) -> Result<(), String> {
 			"ft" | "foot" | "feet" => Token::unit(SquareFoot),
 			"yd" | "yard" | "yards" => Token::unit(SquareYard),
 			"mi" | "mile" | "miles" => Token::unit(SquareMile),
-			string => return Err(format!("Invalid string: {}", string)),
+			_ => return Err(lexer.unknown_word(start)),
 		},
 		"are" | "ares" => Token::unit(Are),
 		"decare" | "decares" => Token::unit(Decare),
@@ -356,7 +674,7 @@ fn lex_word(word: &str, lexer: &mut Lexer) -> Result<(), String> {
 			"ft" | "foot" | "feet" => Token::unit(CubicFoot),
 			"yd" | "yard" | "yards" => Token::unit(CubicYard),
 			"mi" | "mile" | "miles" => Token::unit(CubicMile),
-			string => return Err(format!("Invalid string: {}", string)),
+			_ => return Err(lexer.unknown_word(start)),
 		},
 		"ml" | "milliliter" | "milliliters" | "millilitre" | "millilitres" => {
 			Token::unit(Milliliter)
@@ -371,7 +689,7 @@ fn lex_word(word: &str, lexer: &mut Lexer) -> Result<(), String> {
 		"floz" => Token::unit(FluidOunce),
 		"fl" | "fluid" => match read_word(lexer).as_str() {
 			"oz" | "ounce" | "ounces" => Token::unit(FluidOunce),
-			string => return Err(format!("Invalid string: {}", string)),
+			_ => return Err(lexer.unknown_word(start)),
 		},
 		"cup" | "cups" => Token::unit(Cup),
 		"pt" | "pint" | "pints" => Token::unit(Pint),
@@ -380,13 +698,13 @@ fn lex_word(word: &str, lexer: &mut Lexer) -> Result<(), String> {
 		"bbl" => Token::unit(OilBarrel),
 		"oil" => match read_word(lexer).as_str() {
 			"barrel" | "barrels" => Token::unit(OilBarrel),
-			string => return Err(format!("Invalid string: {}", string)),
+			_ => return Err(lexer.unknown_word(start)),
 		},
 
 		"metric" => match read_word(lexer).as_str() {
 			"ton" | "tons" | "tonne" | "tonnes" => Token::unit(MetricTon),
 			"hp" | "hps" | "horsepower" | "horsepowers" => Token::unit(MetricHorsepower),
-			string => return Err(format!("Invalid string: {}", string)),
+			_ => return Err(lexer.unknown_word(start)),
 		},
 
 		"mg" | "milligram" | "milligrams" => Token::unit(Milligram),
@@ -400,8 +718,12 @@ fn lex_word(word: &str, lexer: &mut Lexer) -> Result<(), String> {
 			true => match lexer.read_immediate_word().as_str() {
 				"force" => Token::LexerKeyword(PoundForce),
 				other => {
-					lexer.tokens.push(Token::unit(Pound));
-					lexer.tokens.push(Token::Operator(Minus));
+					lexer.push(Token::unit(Pound), first_word);
+					let hyphen_start = first_word.end;
+					lexer.push(
+						Token::Operator(Minus),
+						Span::new(hyphen_start, hyphen_start + 1),
+					);
 					lex_word_if_non_empty(other, lexer)?;
 					return Ok(());
 				}
@@ -409,7 +731,7 @@ fn lex_word(word: &str, lexer: &mut Lexer) -> Result<(), String> {
 			false => Token::unit(Ambiguity(Ambiguity {
 				string: "pound",
 				candidates: &[Pound, GBP],
-				fallback: match get_region().as_str() {
+				fallback: match lexer.region {
 					"GB" => &GBP,
 					_ => &Pound,
 				},
@@ -419,12 +741,12 @@ fn lex_word(word: &str, lexer: &mut Lexer) -> Result<(), String> {
 		"st" | "ton" | "tons" => Token::unit(ShortTon),
 		"short" => match read_word(lexer).as_str() {
 			"ton" | "tons" | "tonne" | "tonnes" => Token::unit(ShortTon),
-			string => return Err(format!("Invalid string: {}", string)),
+			_ => return Err(lexer.unknown_word(start)),
 		},
 		"lt" => Token::unit(LongTon),
 		"long" => match read_word(lexer).as_str() {
 			"ton" | "tons" | "tonne" | "tonnes" => Token::unit(LongTon),
-			string => return Err(format!("Invalid string: {}", string)),
+			_ => return Err(lexer.unknown_word(start)),
 		},
 
 		"bit" | "bits" => Token::unit(Bit),
@@ -512,11 +834,11 @@ fn lex_word(word: &str, lexer: &mut Lexer) -> Result<(), String> {
 		"newton" => match read_immediate_grapheme("-", lexer) {
 			true => match lexer.read_immediate_word().as_str() {
 				"meter" | "meters" | "metre" | "metres" => Token::unit(NewtonMeter),
-				string => return Err(format!("Invalid string: {}", string)),
+				_ => return Err(lexer.unknown_word(start)),
 			},
 			false => match lexer.read_word().as_str() {
 				"meter" | "meters" | "metre" | "metres" => Token::unit(NewtonMeter),
-				string => return Err(format!("Invalid string: {}", string)),
+				_ => return Err(lexer.unknown_word(start)),
 			},
 		},
 		"kj" | "kilojoule" | "kilojoules" => Token::unit(Kilojoule),
@@ -529,9 +851,9 @@ fn lex_word(word: &str, lexer: &mut Lexer) -> Result<(), String> {
 		"british" => match read_word(lexer).as_str() {
 			"thermal" => match read_word(lexer).as_str() {
 				"unit" | "units" => Token::unit(BritishThermalUnit),
-				string => return Err(format!("Invalid string: {}", string)),
+				_ => return Err(lexer.unknown_word(start)),
 			},
-			string => return Err(format!("Invalid string: {}", string)),
+			_ => return Err(lexer.unknown_word(start)),
 		},
 		"wh" => Token::unit(WattHour),
 		"kwh" => Token::unit(KilowattHour),
@@ -553,7 +875,7 @@ fn lex_word(word: &str, lexer: &mut Lexer) -> Result<(), String> {
 		"watt" => match read_word(lexer).as_str() {
 			"hr" | "hrs" | "hour" | "hours" => Token::unit(WattHour),
 			other => {
-				lexer.tokens.push(Token::unit(Watt));
+				lexer.push(Token::unit(Watt), first_word);
 				lex_word_if_non_empty(other, lexer)?;
 				return Ok(());
 			}
@@ -561,7 +883,7 @@ fn lex_word(word: &str, lexer: &mut Lexer) -> Result<(), String> {
 		"kilowatt" => match read_word(lexer).as_str() {
 			"hr" | "hrs" | "hour" | "hours" => Token::unit(KilowattHour),
 			other => {
-				lexer.tokens.push(Token::unit(Kilowatt));
+				lexer.push(Token::unit(Kilowatt), first_word);
 				lex_word_if_non_empty(other, lexer)?;
 				return Ok(());
 			}
@@ -569,7 +891,7 @@ fn lex_word(word: &str, lexer: &mut Lexer) -> Result<(), String> {
 		"megawatt" => match read_word(lexer).as_str() {
 			"hr" | "hrs" | "hour" | "hours" => Token::unit(MegawattHour),
 			other => {
-				lexer.tokens.push(Token::unit(Megawatt));
+				lexer.push(Token::unit(Megawatt), first_word);
 				lex_word_if_non_empty(other, lexer)?;
 				return Ok(());
 			}
@@ -577,7 +899,7 @@ fn lex_word(word: &str, lexer: &mut Lexer) -> Result<(), String> {
 		"gigawatt" => match read_word(lexer).as_str() {
 			"hr" | "hrs" | "hour" | "hours" => Token::unit(GigawattHour),
 			other => {
-				lexer.tokens.push(Token::unit(Gigawatt));
+				lexer.push(Token::unit(Gigawatt), first_word);
 				lex_word_if_non_empty(other, lexer)?;
 				return Ok(());
 			}
@@ -585,7 +907,7 @@ fn lex_word(word: &str, lexer: &mut Lexer) -> Result<(), String> {
 		"terawatt" => match read_word(lexer).as_str() {
 			"hr" | "hrs" | "hour" | "hours" => Token::unit(TerawattHour),
 			other => {
-				lexer.tokens.push(Token::unit(Terawatt));
+				lexer.push(Token::unit(Terawatt), first_word);
 				lex_word_if_non_empty(other, lexer)?;
 				return Ok(());
 			}
@@ -593,7 +915,7 @@ fn lex_word(word: &str, lexer: &mut Lexer) -> Result<(), String> {
 		"petawatt" => match &*read_word(lexer) {
 			"hr" | "hrs" | "hour" | "hours" => Token::unit(PetawattHour),
 			other => {
-				lexer.tokens.push(Token::unit(Petawatt));
+				lexer.push(Token::unit(Petawatt), first_word);
 				lex_word_if_non_empty(other, lexer)?;
 				return Ok(());
 			}
@@ -637,6 +959,12 @@ fn lex_word(word: &str, lexer: &mut Lexer) -> Result<(), String> {
 		"rpm" => Token::unit(RevolutionsPerMinute),
 		"r" | "rev" | "revolution" | "revolutions" => Token::LexerKeyword(Revolution),
 
+		"rad" | "rads" | "radian" | "radians" => Token::unit(Radian),
+		"grad" | "grads" | "gradian" | "gradians" | "gon" | "gons" => Token::unit(Gradian),
+		"arcmin" | "arcmins" | "arcminute" | "arcminutes" => Token::unit(Arcminute),
+		"arcsec" | "arcsecs" | "arcsecond" | "arcseconds" => Token::unit(Arcsecond),
+		"turn" | "turns" => Token::unit(Turn),
+
 		"kph" | "kmh" => Token::unit(KilometersPerHour),
 		"mps" => Token::unit(MetersPerSecond),
 		"mph" => Token::unit(MilesPerHour),
@@ -650,11 +978,20 @@ fn lex_word(word: &str, lexer: &mut Lexer) -> Result<(), String> {
 				Token::unit(Fahrenheit)
 			}
 			other => {
-				let token = match get_region().as_str() {
-					"BS" | "BZ" | "KY" | "PR" | "PW" | "US" => Fahrenheit,
-					_ => Celsius,
-				};
-				lexer.tokens.push(Token::unit(token));
+				// an angle or a temperature, depending on the context
+				let token = Token::unit(Ambiguity(match lexer.region {
+					"BS" | "BZ" | "KY" | "PR" | "PW" | "US" => Ambiguity {
+						string: "degree",
+						candidates: &[Degree, Fahrenheit],
+						fallback: &Fahrenheit,
+					},
+					_ => Ambiguity {
+						string: "degree",
+						candidates: &[Degree, Celsius],
+						fallback: &Celsius,
+					},
+				}));
+				lexer.push(token, first_word);
 				lex_word_if_non_empty(other, lexer)?;
 				return Ok(());
 			}
@@ -665,35 +1002,35 @@ fn lex_word(word: &str, lexer: &mut Lexer) -> Result<(), String> {
 			Token::unit(Fahrenheit)
 		}
 
-		"AU$" => Token::unit(AUD),
-		"R$" => Token::unit(BRL),
-		"CA$" => Token::unit(CAD),
+		"au$" => Token::unit(AUD),
+		"r$" => Token::unit(BRL),
+		"ca$" => Token::unit(CAD),
 		"€" | "euro" | "euros" => Token::unit(EUR),
 		"£" => Token::unit(GBP),
-		"HK$" => Token::unit(HKD),
-		"₹" | "Rs" => Token::unit(INR),
+		"hk$" => Token::unit(HKD),
+		"₹" | "rs" => Token::unit(INR),
 		"₪" => Token::unit(ILS),
-		"CN¥" => Token::unit(CNY),
-		"JP¥" => Token::unit(JPY),
-		"¥" => match get_region().as_str() {
+		"cn¥" => Token::unit(CNY),
+		"jp¥" => Token::unit(JPY),
+		"¥" => match lexer.region {
 			"JP" => Token::unit(JPY),
 			"CN" => Token::unit(CNY),
-			region => return Err(format!("\"¥\" is ambiguous in your region \"{region}\"")),
+			region => return Err(lexer.ambiguous_word(start, region)),
 		},
-		"₩" => match get_region().as_str() {
+		"₩" => match lexer.region {
 			"KP" => Token::unit(KPW),
 			_ => Token::unit(KRW),
 		},
-		"MX$" => Token::unit(MXN),
-		"NZ$" => Token::unit(NZD),
+		"mx$" => Token::unit(MXN),
+		"nz$" => Token::unit(NZD),
 		"₱" => Token::unit(PHP),
 		"zl" | "zł" => Token::unit(PLN),
-		"S$" => Token::unit(SGD),
+		"s$" => Token::unit(SGD),
 		"฿" => Token::unit(THB),
 		"tl" | "₺" => Token::unit(TRY),
 		"₴" => Token::unit(UAH),
-		"US$" => Token::unit(USD),
-		"$" => match get_region().as_str() {
+		"us$" => Token::unit(USD),
+		"$" => match lexer.region {
 			"CA" => Token::unit(CAD),
 			"AU" | "TV" | "KI" => Token::unit(AUD),
 			"NZ" => Token::unit(NZD),
@@ -727,45 +1064,252 @@ fn lex_word(word: &str, lexer: &mut Lexer) -> Result<(), String> {
 		},
 		"₫" | "đ" | "Đ" => Token::unit(VND),
 		"kč" | "kc" => Token::unit(CZK),
-		"kr" => match get_region().as_str() {
+		"kr" => match lexer.region {
 			"NO" => Token::unit(NOK),
 			"SE" => Token::unit(SEK),
 			"DK" => Token::unit(DKK),
 			"IS" => Token::unit(ISK),
-			region => return Err(format!("\"kr\" is ambiguous in your region \"{region}\"")),
+			region => return Err(lexer.ambiguous_word(start, region)),
 		},
 
 		string if let Ok(unit) = currency_code_to_unit(string) => Token::unit(unit),
-		string => {
-			return Err(format!("Invalid string: {}", string));
+		_ => lex_identifier(lexer, start)?,
+	};
+	let span = lexer.span_from(start);
+	lexer.push(token, span);
+	Ok(())
+}
+
+pub(crate) fn is_identifier(name: &str) -> bool {
+	let mut chars = name.chars();
+	match chars.next() {
+		Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+			chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
 		}
+		_ => false,
+	}
+}
+
+/// Turn words into [`Identifier`](Token::Identifier)s where they name a
+/// user-defined function, like `f` in `f(2)`, or a parameter of a function
+/// definition, like `m` and `h` in `bmi(m, h) = m / h^2`. Otherwise they could
+/// be read as units.
+///
+/// Definitions can't redefine built-in names like `pi`, `km` or `sqrt`.
+fn bind_identifiers(
+	tokens: &mut [SpannedToken],
+	input: &str,
+	context: &Context,
+) -> Result<(), Error> {
+	let word = |token: &SpannedToken| {
+		let text = &input[token.span.start..token.span.end];
+		is_identifier(text).then(|| text.to_string())
 	};
-	lexer.tokens.push(token);
+	let token_at = |tokens: &[SpannedToken], pos: usize| tokens.get(pos).map(|t| t.token.clone());
+
+	// a definition like 'f(x, y) = ...'
+	let mut defined_name = None;
+	let mut parameters = Vec::new();
+	if let Some(name) = tokens.first().and_then(word)
+		&& token_at(tokens, 1) == Some(Token::Operator(LeftParen))
+	{
+		let mut pos = 2;
+		while let Some(parameter) = tokens.get(pos).and_then(word) {
+			parameters.push((parameter, pos));
+			match token_at(tokens, pos + 1) {
+				Some(Token::Operator(Comma)) => pos += 2,
+				Some(Token::Operator(RightParen))
+					if token_at(tokens, pos + 2) == Some(Token::Operator(Assign)) =>
+				{
+					defined_name = Some(name);
+					break;
+				}
+				_ => break,
+			}
+		}
+		if defined_name.is_none() {
+			parameters.clear();
+		}
+	}
+	if defined_name.is_some() {
+		let names = [("function", 0)]
+			.into_iter()
+			.chain(parameters.iter().map(|(_, pos)| ("parameter", *pos)));
+		for (kind, pos) in names {
+			if is_built_in(&tokens[pos], input) {
+				let span = tokens[pos].span;
+				return Err(Error::InvalidDefinition {
+					message: format!(
+						"\"{}\" can't be used as a {kind} name",
+						&input[span.start..span.end]
+					),
+					span,
+				});
+			}
+		}
+	}
+	let parameters: Vec<String> = parameters.into_iter().map(|(name, _)| name).collect();
+
+	for pos in 0..tokens.len() {
+		if matches!(tokens[pos].token, Token::FunctionIdentifier(_)) {
+			continue;
+		}
+		let Some(text) = word(&tokens[pos]) else {
+			continue;
+		};
+		let is_call = token_at(tokens, pos + 1) == Some(Token::Operator(LeftParen))
+			&& (context.functions.contains_key(&text) || defined_name.as_ref() == Some(&text));
+		if is_call || parameters.contains(&text) {
+			tokens[pos].token = Token::Identifier(text);
+		}
+	}
 	Ok(())
 }
 
+/// Whether a word already means something, like the constant `pi`, the unit
+/// `km` or the keyword `to`. Single letters like `f`, `h` and `r` are units
+/// too, but they're the usual names for functions and parameters, so only the
+/// constant `e` counts.
+fn is_built_in(token: &SpannedToken, input: &str) -> bool {
+	match &token.token {
+		Token::Identifier(_) => false,
+		Token::Constant(_) => true,
+		_ => input[token.span.start..token.span.end].chars().count() > 1,
+	}
+}
+
+/// Whether the token at `index` comes after a number with a degree sign or
+/// arcminutes, like the `'` and `"` in `45°30'15"`
+fn follows_angle(tokens: &[SpannedToken], index: usize) -> bool {
+	let Some(before) = index.checked_sub(2) else {
+		return false;
+	};
+	if !matches!(tokens[before + 1].token, Token::Number(_)) {
+		return false;
+	}
+	match &tokens[before].token {
+		Token::Unit(unit) => match unit.as_slice() {
+			[(Degree | Arcminute, 1)] => true,
+			[(Ambiguity(ambiguity), 1)] => ambiguity.string == "degree",
+			_ => false,
+		},
+		_ => false,
+	}
+}
+
+/// Read the rest of an identifier like `rate` or `line12`, starting from a word
+/// that didn't match anything else
+fn lex_identifier(lexer: &mut Lexer, start: usize) -> Result<Token, Error> {
+	while let Some((_i, grapheme)) = lexer.graphemes.peek()
+		&& (is_numeric_str(grapheme) && *grapheme != "." || is_word_char_str(grapheme))
+	{
+		lexer.graphemes.next();
+	}
+	let span = lexer.span_from(start);
+	let name = &lexer.input[span.start..span.end];
+	if name.eq_ignore_ascii_case("atan2") {
+		return Ok(Token::FunctionIdentifier(Atan2));
+	}
+	if let Some(unit) = find_custom_unit(name, lexer.custom_units) {
+		return Ok(Token::unit(unit));
+	}
+	match is_identifier(name) {
+		true => Ok(Token::Identifier(name.to_string())),
+		false => Err(lexer.unknown_word(start)),
+	}
+}
+
 struct Lexer<'a> {
 	left_paren_count: u16,
 	right_paren_count: u16,
 	input: &'a str,
+	/// Region used to decide what ambiguous words like `$` mean
+	region: &'a str,
+	/// Separators used in numbers, like the decimal comma in `1,5`
+	separators: Separators,
+	/// Units defined in the context
+	custom_units: &'a [CustomUnit],
 	graphemes: Peekable<GraphemeIndices<'a>>,
-	tokens: Vec<Token>,
+	tokens: Vec<SpannedToken>,
+	/// Byte offset of the last word read by [`read_word`] or [`read_immediate_word`]
+	word_start: usize,
 }
 impl<'a> Lexer<'a> {
+	/// Byte offset of the next grapheme
+	fn pos(&mut self) -> usize {
+		match self.graphemes.peek() {
+			Some((i, _)) => *i,
+			None => self.input.len(),
+		}
+	}
+	/// Span of the words read since `start`, without trailing whitespace
+	fn span_from(&mut self, start: usize) -> Span {
+		let end = self.pos();
+		let word = self.input[start..end].trim_end();
+		Span::new(start, start + word.len())
+	}
+	/// Skip the graphemes before the byte offset `end`
+	fn skip_to(&mut self, end: usize) {
+		while self.pos() < end {
+			self.graphemes.next();
+		}
+	}
+	fn push(&mut self, token: Token, span: Span) {
+		self.tokens.push(SpannedToken { token, span });
+	}
+	/// Whether a time zone can be next: right after a date or time, or after
+	/// `to` or `in` in an expression with a date or time
+	fn expects_time_zone(&self) -> bool {
+		let is_date = |token: &Token| {
+			matches!(
+				token,
+				Token::Date(_) | Token::Now | Token::Today | Token::Time(_)
+			)
+		};
+		match self.tokens.last().map(|t| &t.token) {
+			Some(token) if is_date(token) => true,
+			Some(Token::TextOperator(To) | Token::LexerKeyword(In)) => {
+				self.tokens.iter().any(|t| is_date(&t.token))
+			}
+			_ => false,
+		}
+	}
+	/// Error for the words read since `start`
+	fn unknown_word(&mut self, start: usize) -> Error {
+		let span = self.span_from(start);
+		Error::UnknownWord {
+			word: self.input[span.start..span.end].to_string(),
+			span,
+		}
+	}
+	fn ambiguous_word(&mut self, start: usize, region: &str) -> Error {
+		let span = self.span_from(start);
+		Error::AmbiguousWord {
+			word: self.input[span.start..span.end].to_string(),
+			region: region.to_string(),
+			span,
+		}
+	}
 	fn read_word(&mut self) -> String {
 		read_word(self)
 	}
 	fn read_immediate_word(&mut self) -> String {
 		read_immediate_word(self)
 	}
-	fn lex_token(&mut self) -> Result<(), String> {
+	fn lex_token(&mut self) -> Result<(), Error> {
 		lex_token(self)
 	}
 }
 
-/// Lex an input string and returns [`Token`]s
-pub fn lex(input: &str, remove_trailing_operator: bool) -> Result<Vec<Token>, String> {
-	let mut input = input.replace(',', "");
+/// Lex an input string and returns [`Token`]s with their [`Span`]s
+///
+/// `region` is a region code like `US`, used for words like `$` that mean different things in different regions.
+pub fn lex(
+	input: &str,
+	remove_trailing_operator: bool,
+	context: &Context,
+) -> Result<Vec<SpannedToken>, Error> {
+	let mut input = input.to_string();
 
 	if remove_trailing_operator {
 		match &input.chars().last().unwrap_or('x') {
@@ -780,11 +1324,15 @@ pub fn lex(input: &str, remove_trailing_operator: bool) -> Result<Vec<Token>, St
 		left_paren_count: 0,
 		right_paren_count: 0,
 		input: &input,
+		region: context.region(),
+		separators: context.separators(),
+		custom_units: &context.custom_units,
 		graphemes: UnicodeSegmentation::grapheme_indices(input.as_str(), true).peekable(),
 		tokens: Vec::new(),
+		word_start: 0,
 	};
 
-	while let Some(_) = lexer.graphemes.peek() {
+	while lexer.graphemes.peek().is_some() {
 		lexer.lex_token()?;
 	}
 	let tokens = &mut lexer.tokens;
@@ -792,88 +1340,209 @@ pub fn lex(input: &str, remove_trailing_operator: bool) -> Result<Vec<Token>, St
 	if lexer.left_paren_count > lexer.right_paren_count {
 		let missing_right_parens = lexer.left_paren_count - lexer.right_paren_count;
 		for _ in 0..missing_right_parens {
-			tokens.push(Token::Operator(RightParen));
+			tokens.push(SpannedToken {
+				token: Token::Operator(RightParen),
+				span: Span::new(input.len(), input.len()),
+			});
 		}
 	} else if lexer.left_paren_count < lexer.right_paren_count {
 		let missing_left_parens = lexer.right_paren_count - lexer.left_paren_count;
 		for _ in 0..missing_left_parens {
-			tokens.insert(0, Token::Operator(LeftParen));
+			tokens.insert(
+				0,
+				SpannedToken {
+					token: Token::Operator(LeftParen),
+					span: Span::new(0, 0),
+				},
+			);
 		}
 	}
 
 	if tokens.is_empty() {
-		return Err("Input was empty".to_string());
+		return Err(Error::EmptyInput {
+			span: Span::new(0, input.len()),
+		});
 	}
 
+	bind_identifiers(tokens, &input, context)?;
+
 	let mut token_index = 0;
 	loop {
-		match tokens[token_index] {
+		match tokens[token_index].token {
 			// decide if % is percent or modulo
 			Token::LexerKeyword(PercentChar) => {
-				match tokens.get(token_index + 1) {
+				match tokens.get(token_index + 1).map(|t| &t.token) {
 					Some(Token::TextOperator(Of)) => {
 						// "10% of 1km" should be percentage
-						tokens[token_index] = Token::UnaryOperator(Percent);
+						tokens[token_index].token = Token::UnaryOperator(Percent);
 					}
 					Some(Token::Operator(operator)) => {
 						match operator {
 							LeftParen => {
 								// "10%(2)" should be modulo
-								tokens[token_index] = Token::Operator(Modulo);
+								tokens[token_index].token = Token::Operator(Modulo);
 							}
 							_ => {
 								// "10%*2" should be a percentage
-								tokens[token_index] = Token::UnaryOperator(Percent);
+								tokens[token_index].token = Token::UnaryOperator(Percent);
 							}
 						}
 					}
 					Some(Token::UnaryOperator(_)) => {
 						// "10%!" should be a percentage
-						tokens[token_index] = Token::UnaryOperator(Percent);
+						tokens[token_index].token = Token::UnaryOperator(Percent);
 					}
 					Some(Token::LexerKeyword(PercentChar)) => {
 						// "10%%" should be a percentage
-						tokens[token_index] = Token::UnaryOperator(Percent);
+						tokens[token_index].token = Token::UnaryOperator(Percent);
 					}
 					None => {
 						// percent if there's no element afterwards
-						tokens[token_index] = Token::UnaryOperator(Percent);
+						tokens[token_index].token = Token::UnaryOperator(Percent);
 					}
 					_ => {
 						// everything else should be modulo, for example if the % is
 						// before a number, function or constants
-						tokens[token_index] = Token::Operator(Modulo);
+						tokens[token_index].token = Token::Operator(Modulo);
 					}
 				}
 			}
+			// ' and " after an angle are arcminutes and arcseconds, like in 45°30'15"
+			Token::Unit(ref unit)
+				if unit == &[(Foot, 1)]
+					&& ["'", "′"].contains(
+						&&input[tokens[token_index].span.start..tokens[token_index].span.end],
+					) && follows_angle(tokens, token_index) =>
+			{
+				tokens[token_index - 2].token = Token::unit(Degree);
+				tokens[token_index].token = Token::unit(Arcminute);
+			}
+			Token::LexerKeyword(DoubleQuotes) if follows_angle(tokens, token_index) => {
+				if tokens[token_index - 2].token != Token::unit(Arcminute) {
+					tokens[token_index - 2].token = Token::unit(Degree);
+				}
+				tokens[token_index].token = Token::unit(Arcsecond);
+			}
 			// decide if " is 'inch' or 'inch of mercury'
 			Token::LexerKeyword(DoubleQuotes) => {
-				match tokens.get(token_index + 1) {
+				match tokens.get(token_index + 1).map(|t| &t.token) {
 					Some(Token::LexerKeyword(Hg)) => {
 						// "hg should be inch of mercury
-						tokens[token_index] = Token::unit(InchOfMercury);
+						tokens[token_index].token = Token::unit(InchOfMercury);
+						tokens[token_index].span.end = tokens[token_index + 1].span.end;
 						tokens.remove(token_index + 1);
 					}
 					_ => {
 						// otherwise, Inch
-						tokens[token_index] = Token::unit(Inch);
+						tokens[token_index].token = Token::unit(Inch);
+					}
+				}
+			}
+			// "min(" and "sec(" are functions rather than minutes and seconds
+			Token::Unit(ref unit) if unit == &[(Minute, 1)] || unit == &[(Second, 1)] => {
+				let span = tokens[token_index].span;
+				let next_token = tokens.get(token_index + 1).map(|t| &t.token);
+				let function = match input[span.start..span.end].to_ascii_lowercase().as_str() {
+					"min" => Some(Min),
+					"sec" => Some(Sec),
+					_ => None,
+				};
+				if next_token == Some(&Token::Operator(LeftParen))
+					&& let Some(function) = function
+				{
+					tokens[token_index].token = Token::FunctionIdentifier(function);
+				}
+			}
+			// "sum" without "(" is a variable, like the sum of the lines above in
+			// a document
+			Token::FunctionIdentifier(Sum)
+				if tokens.get(token_index + 1).map(|t| &t.token)
+					!= Some(&Token::Operator(LeftParen)) =>
+			{
+				tokens[token_index].token = Token::Identifier("sum".to_string());
+			}
+			// "base 36" is an output format, otherwise "base" is a variable
+			Token::LexerKeyword(Base) => match tokens.get(token_index + 1).map(|t| &t.token) {
+				Some(Token::Number(radix)) => {
+					let span = Span::new(
+						tokens[token_index].span.start,
+						tokens[token_index + 1].span.end,
+					);
+					let radix = Some(*radix)
+						.filter(|radix| radix.floor() == *radix)
+						.and_then(|radix| u32::try_from(radix).ok())
+						.filter(|radix| (2..=36).contains(radix))
+						.ok_or_else(|| {
+							Error::domain("The base must be a whole number from 2 to 36")
+								.with_span(span)
+						})?;
+					tokens[token_index].token = Token::Format(OutputFormat::Base(radix));
+					tokens[token_index].span = span;
+					tokens.remove(token_index + 1);
+				}
+				_ => tokens[token_index].token = Token::Identifier("base".to_string()),
+			},
+			// "nearest 1/16" is an output format, otherwise "nearest" is a variable
+			Token::LexerKeyword(Nearest) => {
+				let next: Vec<&Token> = tokens[token_index + 1..]
+					.iter()
+					.take(3)
+					.map(|t| &t.token)
+					.collect();
+				match next.as_slice() {
+					[
+						Token::Number(one),
+						Token::Operator(Divide),
+						Token::Number(denominator),
+					] if *one == D128::ONE => {
+						let span = Span::new(
+							tokens[token_index].span.start,
+							tokens[token_index + 3].span.end,
+						);
+						let denominator = to_i128(*denominator)
+							.and_then(|denominator| u32::try_from(denominator).ok())
+							.filter(|denominator| *denominator > 0)
+							.ok_or_else(|| {
+								Error::domain("The denominator must be a positive whole number")
+									.with_span(span)
+							})?;
+						tokens[token_index].token =
+							Token::Format(OutputFormat::Fraction(Some(denominator)));
+						tokens[token_index].span = span;
+						tokens.drain(token_index + 1..token_index + 4);
 					}
+					_ => tokens[token_index].token = Token::Identifier("nearest".to_string()),
 				}
 			}
 			// if hg wasn't already turned into inch of mercury, it's hectogram
 			Token::LexerKeyword(Hg) => {
-				tokens[token_index] = Token::unit(Hectogram);
+				tokens[token_index].token = Token::unit(Hectogram);
 			}
 			// decide if "in" is Inch or To
+			Token::LexerKeyword(In)
+				if token_index > 0 && tokens[token_index - 1].token == Token::TextOperator(To) =>
+			{
+				// "to in ft" should be Inch
+				tokens[token_index].token = Token::unit(Inch);
+			}
 			Token::LexerKeyword(In) => {
-				match tokens.get(token_index + 1) {
-					Some(Token::Unit(_)) => {
+				match tokens.get(token_index + 1).map(|t| &t.token) {
+					Some(Token::Unit(_) | Token::Format(_) | Token::TimeZone(_)) => {
 						// "in" should be To
-						tokens[token_index] = Token::TextOperator(To);
+						tokens[token_index].token = Token::TextOperator(To);
+					}
+					Some(Token::LexerKeyword(Base | Nearest))
+						if matches!(
+							tokens.get(token_index + 2).map(|t| &t.token),
+							Some(Token::Number(_))
+						) =>
+					{
+						// "in base 36" and "in nearest 1/16" should be To
+						tokens[token_index].token = Token::TextOperator(To);
 					}
 					_ => {
 						// otherwise, Inch
-						tokens[token_index] = Token::unit(Inch);
+						tokens[token_index].token = Token::unit(Inch);
 					}
 				}
 			}
@@ -885,16 +1554,197 @@ pub fn lex(input: &str, remove_trailing_operator: bool) -> Result<Vec<Token>, St
 			token_index += 1;
 		}
 	}
+	bind_mixed_units(tokens);
 
 	Ok(lexer.tokens)
 }
 
+/// Turn a conversion to several units of the same type at the end of the
+/// input, like `to ft in`, into a [`Mixed`](OutputFormat::Mixed) output format
+fn bind_mixed_units(tokens: &mut Vec<SpannedToken>) {
+	let Some(to_index) = tokens
+		.iter()
+		.rposition(|t| t.token == Token::TextOperator(To))
+	else {
+		return;
+	};
+	let units: Option<Vec<Unit>> = tokens[to_index + 1..]
+		.iter()
+		.map(|t| match &t.token {
+			Token::Unit(unit) => match unit.as_slice() {
+				[(unit, 1)] => Some(unit.clone()),
+				_ => None,
+			},
+			_ => None,
+		})
+		.collect();
+	let Some(units) = units else {
+		return;
+	};
+	let category = units.first().map(Unit::category);
+	let is_mixed = units.len() >= 2
+		&& units.iter().all(|unit| Some(unit.category()) == category)
+		&& units
+			.iter()
+			.enumerate()
+			.all(|(i, unit)| !units[..i].contains(unit))
+		&& !matches!(
+			category,
+			Some(UnitType::Temperature | UnitType::Currency | UnitType::Unknown)
+		);
+	if is_mixed {
+		let span = Span::new(
+			tokens[to_index + 1].span.start,
+			tokens[tokens.len() - 1].span.end,
+		);
+		tokens.truncate(to_index + 1);
+		tokens.push(SpannedToken {
+			token: Token::Format(OutputFormat::Mixed(units)),
+			span,
+		});
+	}
+}
+
 #[cfg(test)]
 mod tests {
 	use super::*;
 	use crate::{numtok, units::Ambiguity};
 	use regex::Regex;
 
+	fn lex_tokens(input: &str, remove_trailing_operator: bool) -> Result<Vec<Token>, Error> {
+		let tokens = lex(input, remove_trailing_operator, &Context::new("nb-NO"))?;
+		Ok(tokens.into_iter().map(|t| t.token).collect())
+	}
+
+	#[track_caller]
+	fn assert_spans(input: &str, expected: &[&str]) {
+		let spans: Vec<&str> = lex(input, false, &Context::new("nb-NO"))
+			.unwrap()
+			.iter()
+			.map(|t| &input[t.span.start..t.span.end])
+			.collect();
+		assert_eq!(spans, expected, "input: {input}");
+	}
+
+	#[test]
+	fn test_spans() {
+		assert_spans("12 cm + 3", &["12", "cm", "+", "3"]);
+		assert_spans("1,000 m  to  km", &["1,000", "m", "to", "km"]);
+		assert_spans("1 000 m", &["1 000", "m"]);
+		assert_spans("3 multiplied by 2", &["3", "multiplied by", "2"]);
+		assert_spans("10% of 5", &["10", "%", "of", "5"]);
+		assert_spans("6'4\"", &["6", "'", "4", "\""]);
+		assert_spans("30\"hg", &["30", "\"hg"]);
+		assert_spans("3 in in cm", &["3", "in", "in", "cm"]);
+		assert_spans("1 watt second", &["1", "watt", "second"]);
+		assert_spans("2 pound-foot", &["2", "pound", "-", "foot"]);
+		assert_spans("(1 + 2", &["(", "1", "+", "2", ""]);
+		assert_spans("1 + 2)", &["", "1", "+", "2", ")"]);
+		assert_spans("1.5e3 + 2³", &["1.5e3", "+", "2", "", "³"]);
+		assert_spans("6.02 × 10^23 m", &["6.02 × 10^23", "m"]);
+		assert_spans("10⁻²", &["10", "", "⁻", "²"]);
+		assert_spans("2026-10-16-3 days", &["2026-10-16", "-", "3", "days"]);
+		assert_spans(
+			"16 Oct 2026 14:30 - today",
+			&["16 Oct 2026 14:30", "-", "today"],
+		);
+		assert_spans("3pm PST in new  york", &["3pm", "PST", "in", "new  york"]);
+		assert_spans("now to Europe/Oslo", &["now", "to", "Europe/Oslo"]);
+	}
+
+	#[test]
+	fn test_identifiers() {
+		let identifier = |name: &str| Token::Identifier(name.to_string());
+		assert_eq!(
+			lex_tokens("rate = 45", false).unwrap(),
+			vec![identifier("rate"), Token::Operator(Assign), numtok!(45)],
+		);
+		assert_eq!(
+			lex_tokens("line12 + _ * Ans", false).unwrap(),
+			vec![
+				identifier("line12"),
+				Token::Operator(Plus),
+				identifier("_"),
+				Token::Operator(Multiply),
+				identifier("Ans"),
+			],
+		);
+		assert!(lex_tokens("x²", false).is_err());
+		assert_eq!(
+			lex_tokens("bmi(m, h) = m / h", false).unwrap(),
+			vec![
+				identifier("bmi"),
+				Token::Operator(LeftParen),
+				identifier("m"),
+				Token::Operator(Comma),
+				identifier("h"),
+				Token::Operator(RightParen),
+				Token::Operator(Assign),
+				identifier("m"),
+				Token::Operator(Divide),
+				identifier("h"),
+			],
+		);
+		assert_eq!(
+			lex_tokens("f(1 000, 2)", false).unwrap(),
+			vec![
+				Token::unit(Fahrenheit),
+				Token::Operator(LeftParen),
+				numtok!(1000),
+				Token::Operator(Comma),
+				numtok!(2),
+				Token::Operator(RightParen),
+			],
+		);
+		assert_eq!(
+			lex_tokens("sum([2]) + sum", false).unwrap(),
+			vec![
+				Token::FunctionIdentifier(Sum),
+				Token::Operator(LeftParen),
+				Token::Operator(LeftBracket),
+				numtok!(2),
+				Token::Operator(RightBracket),
+				Token::Operator(RightParen),
+				Token::Operator(Plus),
+				identifier("sum"),
+			],
+		);
+	}
+
+	#[test]
+	fn test_locale_numbers() {
+		let lex_in = |input: &str, context: &Context| -> Vec<Token> {
+			let tokens = lex(input, false, context).unwrap();
+			tokens.into_iter().map(|t| t.token).collect()
+		};
+		let de = Context::new("de-DE");
+		assert_eq!(lex_in("1,5", &de), vec![numtok!(1.5)]);
+		assert_eq!(lex_in("1.000,25", &de), vec![numtok!(1000.25)]);
+		assert_eq!(lex_in("1.5", &de), vec![numtok!(1.5)]);
+		assert_eq!(
+			lex_in("max(1, 5)", &de),
+			vec![
+				Token::FunctionIdentifier(Max),
+				Token::Operator(LeftParen),
+				numtok!(1),
+				Token::Operator(Comma),
+				numtok!(5),
+				Token::Operator(RightParen),
+			],
+		);
+		let nb = Context::new("nb-NO");
+		assert_eq!(lex_in("1 000 000,5", &nb), vec![numtok!(1000000.5)]);
+		assert_eq!(lex_in("1\u{a0}000", &nb), vec![numtok!(1000)]);
+		assert_eq!(lex_in("12 3456", &nb), vec![numtok!(12), numtok!(3456)]);
+		let ch = Context::new("de-CH");
+		assert_eq!(lex_in("1'000.5", &ch), vec![numtok!(1000.5)]);
+		let us = Context::new("en-US");
+		assert_eq!(lex_in("1,000.5", &us), vec![numtok!(1000.5)]);
+		let mut independent = Context::new("de-DE");
+		independent.locale_independent = true;
+		assert_eq!(lex_in("1,000.5", &independent), vec![numtok!(1000.5)]);
+	}
+
 	#[test]
 	fn test_lex() {
 		let strip_operator_spacing = Regex::new(r" ([+\-*/]) ").unwrap();
@@ -908,7 +1758,7 @@ mod tests {
 			strip_operator_spacing: &Regex,
 			strip_afterdigit_spacing: &Regex,
 		) {
-			let tokens = match lex(input, false) {
+			let tokens = match lex_tokens(input, false) {
 				Ok(tokens) => tokens,
 				Err(e) => {
 					panic!("lex error: {}\nrun_lex input: {}", e, input);
@@ -922,19 +1772,19 @@ mod tests {
 
 			// Prove we can handle multiple spaces wherever we handle a single space
 			let input_extra_spaces = input.replace(" ", "   ");
-			let tokens_extra_spaces = lex(&input_extra_spaces, false).unwrap();
+			let tokens_extra_spaces = lex_tokens(&input_extra_spaces, false).unwrap();
 			assert_eq!(tokens_extra_spaces, expected_tokens, "{info_msg}");
 
 			// Prove we don't need spaces around operators
 			let input_stripped_spaces = strip_operator_spacing.replace_all(input, "$1");
-			let tokens_stripped_spaces = lex(&input_stripped_spaces, false).unwrap();
+			let tokens_stripped_spaces = lex_tokens(&input_stripped_spaces, false).unwrap();
 			assert_eq!(tokens_stripped_spaces, expected_tokens, "{info_msg}");
 
 			// Prove we don't need a space after a digit
 			let input_afterdigit_stripped_spaces =
 				strip_afterdigit_spacing.replace_all(input, "$1");
 			let tokens_afterdigit_stripped_spaces =
-				lex(&input_afterdigit_stripped_spaces, false).unwrap();
+				lex_tokens(&input_afterdigit_stripped_spaces, false).unwrap();
 			assert_eq!(
 				tokens_afterdigit_stripped_spaces, expected_tokens,
 				"{info_msg}"
@@ -951,7 +1801,7 @@ mod tests {
 
 			// Prove plural and non-plural data units behave identically
 			let input_nonplural_units = nonplural_data_units.replace_all(input, "$1");
-			let tokens_nonplural_units = lex(&input_nonplural_units, false).unwrap();
+			let tokens_nonplural_units = lex_tokens(&input_nonplural_units, false).unwrap();
 			let info_msg = format!(
 				"run_datarate_lex input: {}\n  left: {:?}\n right: {:?}",
 				input, expected_tokens, tokens_nonplural_units
@@ -3093,6 +3943,74 @@ mod tests {
 			&strip_operator_spacing,
 			&strip_afterdigit_spacing,
 		);
+		run_lex(
+			"45° 30' 15\" to dms",
+			vec![
+				numtok!(45),
+				Token::unit(Degree),
+				numtok!(30),
+				Token::unit(Arcminute),
+				numtok!(15),
+				Token::unit(Arcsecond),
+				Token::TextOperator(To),
+				Token::Format(OutputFormat::Dms),
+			],
+			&strip_operator_spacing,
+			&strip_afterdigit_spacing,
+		);
+		run_lex(
+			"1 << 4 >> 2 and not 3 div 2",
+			vec![
+				numtok!(1),
+				Token::Operator(ShiftLeft),
+				numtok!(4),
+				Token::Operator(ShiftRight),
+				numtok!(2),
+				Token::Operator(And),
+				Token::UnaryOperator(Not),
+				numtok!(3),
+				Token::Operator(IntegerDivide),
+				numtok!(2),
+			],
+			&strip_operator_spacing,
+			&strip_afterdigit_spacing,
+		);
+		run_lex(
+			"0x1F4 + 0b1010 + 0o755 in base 36",
+			vec![
+				numtok!(500),
+				Token::Operator(Plus),
+				numtok!(10),
+				Token::Operator(Plus),
+				numtok!(493),
+				Token::TextOperator(To),
+				Token::Format(OutputFormat::Base(36)),
+			],
+			&strip_operator_spacing,
+			&strip_afterdigit_spacing,
+		);
+		run_lex(
+			"1.68 m to ft in",
+			vec![
+				numtok!(1.68),
+				Token::unit(Meter),
+				Token::TextOperator(To),
+				Token::Format(OutputFormat::Mixed(vec![Foot, Inch])),
+			],
+			&strip_operator_spacing,
+			&strip_afterdigit_spacing,
+		);
+		run_lex(
+			"0.3 in in nearest 1/16",
+			vec![
+				numtok!(0.3),
+				Token::unit(Inch),
+				Token::TextOperator(To),
+				Token::Format(OutputFormat::Fraction(Some(16))),
+			],
+			&strip_operator_spacing,
+			&strip_afterdigit_spacing,
+		);
 		run_lex(
 			"50 metric tonnes",
 			vec![numtok!(50), Token::unit(MetricTon)],
@@ -8,13 +8,13 @@
 //!
 //! # Example usage
 //! ```rust
-//! use cpc::eval;
+//! use cpc::{eval, EvalOptions};
 //! use cpc::units::Unit;
 //!
-//! match eval("3m + 1cm", true, false) {
-//!     Ok(answer) => {
-//!         // answer: Number { value: 301, unit: Unit::Centimeter }
-//!         println!("Evaluated value: {} {:?}", answer.value, answer.unit)
+//! match eval("3m + 1cm", &EvalOptions::new()) {
+//!     Ok(output) => {
+//!         // output.value: Number { value: 301, unit: Unit::Centimeter }
+//!         println!("Evaluated value: {}", output.value)
 //!     },
 //!     Err(e) => {
 //!         println!("{e}")
@@ -22,27 +22,56 @@
 //! }
 //! ```
 
+use crate::datetime::{DateValue, TimeOfDay};
+use crate::parser::AstNode;
+use crate::rational::Rational;
+use crate::time_zones::zone_name;
 use crate::units::{Unit, UnitType, primitive_unit, sort_units};
 use fastnum::{D128, dec128 as d};
 use serde::{Deserialize, Serialize};
 use std::{
 	fmt::{self, Debug, Display},
 	sync::{OnceLock, RwLock, RwLockReadGuard, RwLockWriteGuard},
+	time::Duration,
 };
 use web_time::Instant;
 
+/// Per-evaluation state like the locale and exchange rates
+pub mod context;
 /// Currency exchange rates
 pub mod currency;
+/// Calendar dates and times, and the [`Clock`](datetime::Clock) `now` and
+/// `today` are read from
+pub mod datetime;
+/// Loading units from unit definition files
+pub mod definitions;
+/// Evaluating documents with one expression per line
+pub mod document;
+/// The [`Error`] type returned when an expression can't be evaluated
+pub mod error;
 /// Turns an [`AstNode`](parser::AstNode) into a [`Number`]
 pub mod evaluator;
+/// Showing numbers with [`FormatOptions`], and in formats like degrees,
+/// minutes and seconds
+pub mod format;
 /// Turns a string into [`Token`]s
 pub mod lexer;
 mod lookup;
 /// Turns [`Token`]s into an [`AstNode`](parser::AstNode)
 pub mod parser;
+/// Exact fractions, used while numbers are rational
+pub mod rational;
+mod time_zones;
 /// Units, and functions you can use with them
 pub mod units;
 
+pub use context::{Context, UserFunction};
+pub use currency::CurrencyPolicy;
+pub use definitions::{DefinitionError, load_units, load_units_from_file};
+pub use document::{LineResult, eval_document, eval_document_with_context};
+pub use error::{Error, Span};
+pub use format::FormatOptions;
+
 #[derive(Clone)]
 /// A number with a `Unit`.
 ///
@@ -59,22 +88,67 @@ pub struct Number {
 	pub value: D128,
 	/// The unit and exponent
 	pub unit: Vec<(Unit, isize)>,
+	/// The exact value while it's rational, like `1/3`. The value is then the
+	/// closest decimal to it.
+	pub rational: Option<Rational>,
 }
 impl Number {
 	pub fn new_unitless(value: D128) -> Number {
+		Number::with_unit(value, vec![])
+	}
+	pub fn with_basic_unit(value: D128, unit: Unit) -> Number {
+		Number::with_unit(value, vec![(unit, 1)])
+	}
+	pub fn with_unit(value: D128, unit: Vec<(Unit, isize)>) -> Number {
 		Number {
 			value,
-			unit: vec![],
+			unit,
+			rational: Rational::from_decimal(value),
 		}
 	}
-	pub fn with_basic_unit(value: D128, unit: Unit) -> Number {
+	/// A number with an exact rational value
+	pub fn from_rational(rational: Rational, unit: Vec<(Unit, isize)>) -> Number {
 		Number {
-			value,
-			unit: vec![(unit, 1)],
+			value: rational.to_decimal(),
+			unit,
+			rational: Some(rational),
 		}
 	}
-	pub fn with_unit(value: D128, unit: Vec<(Unit, isize)>) -> Number {
-		Number { value, unit }
+	/// Whether the number is exact, either as a decimal or as a fraction like
+	/// `1/3` whose decimal is rounded
+	pub fn is_exact(&self) -> bool {
+		self.rational.is_some() || !self.value.is_op_inexact()
+	}
+	/// A number calculated from the values of this number and another. It's
+	/// calculated exactly with `rational` while both numbers are rational,
+	/// and with `decimal` otherwise.
+	pub(crate) fn combine(
+		&self,
+		other: &Number,
+		decimal: impl FnOnce(D128, D128) -> D128,
+		rational: impl FnOnce(Rational, Rational) -> Option<Rational>,
+		unit: Vec<(Unit, isize)>,
+	) -> Number {
+		let exact = self
+			.rational
+			.zip(other.rational)
+			.and_then(|(a, b)| rational(a, b));
+		match exact {
+			Some(exact) => Number::from_rational(exact, unit),
+			None => Number::with_unit(decimal(self.value, other.value), unit),
+		}
+	}
+	/// Like [`Number::combine`], but with one number
+	pub(crate) fn map(
+		&self,
+		decimal: impl FnOnce(D128) -> D128,
+		rational: impl FnOnce(Rational) -> Option<Rational>,
+		unit: Vec<(Unit, isize)>,
+	) -> Number {
+		match self.rational.and_then(rational) {
+			Some(exact) => Number::from_rational(exact, unit),
+			None => Number::with_unit(decimal(self.value), unit),
+		}
 	}
 	pub fn has_unit(&self) -> bool {
 		!self.unit.is_empty()
@@ -100,7 +174,7 @@ impl Number {
 			if unit.1 <= 0 {
 				continue;
 			}
-			if s.len() != 0 {
+			if !s.is_empty() {
 				s.push_str(" * ");
 			}
 			// only the last multiplication should be plural: `100 minute meters / volt hour`
@@ -110,7 +184,7 @@ impl Number {
 				true => s.push_str(unit.0.plural()),
 			};
 			if unit.1.abs() >= 2 {
-				s.push_str("^");
+				s.push('^');
 				s.push_str(&unit.1.to_string());
 			}
 		}
@@ -124,7 +198,7 @@ impl Number {
 			}
 			s.push_str(unit.0.singular());
 			if unit.1.abs() >= 2 {
-				s.push_str("^");
+				s.push('^');
 				s.push_str(&unit.1.to_string());
 			}
 		}
@@ -139,20 +213,7 @@ impl Number {
 }
 impl Display for Number {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		let value = self.get_simplified_value();
-		let word = match self.value == d!(1) {
-			true => self.singular(),
-			false => self.plural(),
-		};
-		let approx_str = match value.is_op_inexact() {
-			true => "≈ ",
-			false => "",
-		};
-		let output = match word.as_str() {
-			"" => format!("{approx_str}{value}"),
-			_ => format!("{approx_str}{value} {word}"),
-		};
-		write!(f, "{output}")
+		write!(f, "{}", self.format(&FormatOptions::default()))
 	}
 }
 impl Debug for Number {
@@ -176,6 +237,43 @@ impl PartialEq for Number {
 	}
 }
 
+#[derive(Clone, Debug, PartialEq)]
+/// The result of an evaluation
+pub enum Value {
+	/// A [`Number`], for example from `3 km to mi`
+	Number(Number),
+	/// A function definition like `f(x) = x^2`, shown as its signature `f(x)`
+	Function(String),
+	/// A [`Number`] shown in an [`OutputFormat`], for example from
+	/// `45.5 deg to dms`
+	Formatted(Number, OutputFormat),
+	/// A list of [`Number`]s, for example from `[3 km, 5 mi]`
+	List(Vec<Number>),
+	/// A date, or a date with a time, for example from `today + 90 days`
+	Date(DateValue),
+}
+impl Value {
+	/// The [`Number`], if this value is one
+	pub fn as_number(&self) -> Option<&Number> {
+		match self {
+			Value::Number(number) | Value::Formatted(number, _) => Some(number),
+			_ => None,
+		}
+	}
+	/// The [`Number`], if this value is one
+	pub fn into_number(self) -> Option<Number> {
+		match self {
+			Value::Number(number) | Value::Formatted(number, _) => Some(number),
+			_ => None,
+		}
+	}
+}
+impl Display for Value {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.format(&FormatOptions::default()))
+	}
+}
+
 #[derive(Clone, Debug, PartialEq)]
 /// Math operators like [`Multiply`](Operator::Multiply), parentheses, etc.
 pub enum Operator {
@@ -184,9 +282,29 @@ pub enum Operator {
 	Multiply,
 	Divide,
 	Modulo,
+	/// `div`, division rounded towards zero
+	IntegerDivide,
 	Caret,
-	LeftParen,  // lexer only
-	RightParen, // lexer only
+	/// Bitwise `and`
+	And,
+	/// Bitwise `or`
+	Or,
+	/// Bitwise `xor`
+	Xor,
+	/// `<<`
+	ShiftLeft,
+	/// `>>`
+	ShiftRight,
+	/// `=` in an assignment like `x = 5`
+	Assign,
+	/// `,` between function arguments
+	Comma,
+	/// `..` in a range like `1..10`
+	Range,
+	LeftParen,    // lexer only
+	RightParen,   // lexer only
+	LeftBracket,  // lexer only
+	RightBracket, // lexer only
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -194,6 +312,8 @@ pub enum Operator {
 pub enum UnaryOperator {
 	Percent,
 	Factorial,
+	/// Bitwise `not`, which comes before the number like `not 5`
+	Not,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -202,6 +322,26 @@ pub enum TextOperator {
 	To,
 	Of,
 	Per,
+	/// The step of a range like `1..10 step 2`
+	Step,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// A format to show a result in, used after [`To`](TextOperator::To) like in
+/// `45.5 deg to dms`
+pub enum OutputFormat {
+	/// Degrees, minutes and seconds, like `45° 30′ 15″`
+	Dms,
+	/// An integer in a base from 2 to 36, like `0xFF` for `to hex` or `73₃₆`
+	/// for `to base 36`
+	Base(u32),
+	/// A fraction like `1 5/16` for `to fraction`, or rounded to a denominator
+	/// like 16 for `to nearest 1/16`
+	Fraction(Option<u32>),
+	/// Several units of the same type, like `5 feet 6 inches` for `to ft in`
+	Mixed(Vec<Unit>),
+	/// A duration like `01:23:20` for `to hh:mm:ss`
+	Clock,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -245,6 +385,7 @@ pub enum Constant {
 pub enum FunctionIdentifier {
 	Sqrt,
 	Cbrt,
+	Hypot,
 
 	Log,
 	Ln,
@@ -254,10 +395,36 @@ pub enum FunctionIdentifier {
 	Ceil,
 	Floor,
 	Abs,
+	Popcount,
+
+	Min,
+	Max,
+	Clamp,
+
+	Sum,
+	Avg,
+	Median,
+	Stddev,
+	Variance,
+	Count,
 
 	Sin,
 	Cos,
 	Tan,
+	Sec,
+	Csc,
+	Cot,
+	Asin,
+	Acos,
+	Atan,
+	Atan2,
+
+	Sinh,
+	Cosh,
+	Tanh,
+	Asinh,
+	Acosh,
+	Atanh,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -277,6 +444,8 @@ pub enum LexerKeyword {
 	PoundForce,
 	Force,
 	Revolution,
+	Base,
+	Nearest,
 }
 
 #[derive(Clone, PartialEq)]
@@ -291,6 +460,8 @@ pub enum Token {
 	Constant(Constant),
 	/// Used by the parser only
 	Paren,
+	/// A list like `[1, 2, 3]`, with the elements as children. Used by the parser only
+	List,
 	/// Used by the lexer only
 	LexerKeyword(LexerKeyword),
 	TextOperator(TextOperator),
@@ -298,6 +469,21 @@ pub enum Token {
 	/// The `-` symbol, specifically when used as `-5` and not `5-5`. Used by the parser only
 	Negative,
 	Unit(Vec<(Unit, isize)>),
+	/// The name of a variable, like `rate` or `ans`
+	Identifier(String),
+	/// An output format after `to`, like `dms`
+	Format(OutputFormat),
+	/// A date like `2026-10-16` or `16 Oct 2026 14:30`
+	Date(DateValue),
+	/// The current date and time
+	Now,
+	/// The current date
+	Today,
+	/// A time of day like `14:30` or `3pm`, on the current date
+	Time(jiff::civil::Time),
+	/// A time zone like `PST`, `Tokyo` or `Europe/Oslo`. When it's after a
+	/// date or time, the parser gives it that as a child.
+	TimeZone(jiff::tz::TimeZone),
 }
 impl Token {
 	fn unit(u: Unit) -> Token {
@@ -313,15 +499,86 @@ impl fmt::Debug for Token {
 			Token::FunctionIdentifier(id) => write!(f, "FunctionIdentifier({:?})", id),
 			Token::Constant(c) => write!(f, "Constant({:?})", c),
 			Token::Paren => write!(f, "Paren"),
+			Token::List => write!(f, "List"),
 			Token::LexerKeyword(op) => write!(f, "LexerKeyword({:?})", op),
 			Token::TextOperator(op) => write!(f, "TextOperator({:?})", op),
 			Token::NamedNumber(num) => write!(f, "NamedNumber({:?})", num),
 			Token::Negative => write!(f, "Negative"),
 			Token::Unit(u) => write!(f, "Unit({:?})", u),
+			Token::Identifier(name) => write!(f, "Identifier({name})"),
+			Token::Format(format) => write!(f, "Format({format:?})"),
+			Token::Date(date) => write!(f, "Date({date})"),
+			Token::Now => write!(f, "Now"),
+			Token::Today => write!(f, "Today"),
+			Token::Time(time) => write!(f, "Time({time})"),
+			Token::TimeZone(zone) => write!(f, "TimeZone({})", zone_name(zone)),
+		}
+	}
+}
+impl Display for Token {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Token::Operator(op) => match op {
+				Operator::Plus => write!(f, "+"),
+				Operator::Minus => write!(f, "-"),
+				Operator::Multiply => write!(f, "*"),
+				Operator::Divide => write!(f, "/"),
+				Operator::Modulo => write!(f, "mod"),
+				Operator::IntegerDivide => write!(f, "div"),
+				Operator::Caret => write!(f, "^"),
+				Operator::And => write!(f, "and"),
+				Operator::Or => write!(f, "or"),
+				Operator::Xor => write!(f, "xor"),
+				Operator::ShiftLeft => write!(f, "<<"),
+				Operator::ShiftRight => write!(f, ">>"),
+				Operator::Assign => write!(f, "="),
+				Operator::Comma => write!(f, ","),
+				Operator::Range => write!(f, ".."),
+				Operator::LeftParen => write!(f, "("),
+				Operator::RightParen => write!(f, ")"),
+				Operator::LeftBracket => write!(f, "["),
+				Operator::RightBracket => write!(f, "]"),
+			},
+			Token::UnaryOperator(op) => match op {
+				UnaryOperator::Percent => write!(f, "%"),
+				UnaryOperator::Factorial => write!(f, "!"),
+				UnaryOperator::Not => write!(f, "not"),
+			},
+			Token::Number(num) => write!(f, "{num}"),
+			Token::FunctionIdentifier(id) => write!(f, "{}", format!("{id:?}").to_lowercase()),
+			Token::Constant(Constant::Pi) => write!(f, "pi"),
+			Token::Constant(Constant::E) => write!(f, "e"),
+			Token::Paren => write!(f, "()"),
+			Token::List => write!(f, "[]"),
+			Token::LexerKeyword(keyword) => write!(f, "{}", format!("{keyword:?}").to_lowercase()),
+			Token::TextOperator(op) => write!(f, "{}", format!("{op:?}").to_lowercase()),
+			Token::NamedNumber(num) => write!(f, "{}", format!("{num:?}").to_lowercase()),
+			Token::Negative => write!(f, "-"),
+			Token::Unit(unit) => match unit.as_slice() {
+				[(Unit::Ambiguity(ambiguity), 1)] => write!(f, "{}", ambiguity.string),
+				_ => write!(f, "{}", Number::with_unit(d!(1), unit.clone()).singular()),
+			},
+			Token::Identifier(name) => write!(f, "{name}"),
+			Token::Format(format) => write!(f, "{format}"),
+			Token::Date(date) => write!(f, "{date}"),
+			Token::Now => write!(f, "now"),
+			Token::Today => write!(f, "today"),
+			Token::Time(time) => write!(f, "{}", TimeOfDay(*time)),
+			Token::TimeZone(zone) => write!(f, "{}", zone_name(zone)),
 		}
 	}
 }
 
+#[derive(Clone, Debug, PartialEq)]
+/// A [`Token`] together with the [`Span`] of the input it was read from.
+///
+/// Tokens inserted by the [`lexer`], like automatically added parentheses, have
+/// an empty span.
+pub struct SpannedToken {
+	pub token: Token,
+	pub span: Span,
+}
+
 #[macro_export]
 macro_rules! numtok {
 	( $num:literal ) => {
@@ -334,12 +591,14 @@ static SETTINGS: OnceLock<RwLock<Settings>> = OnceLock::new();
 #[derive(Serialize, Deserialize)]
 pub struct Settings {
 	pub locale: String,
+	/// How results are shown by `wasm_eval`
+	#[serde(default)]
+	pub format: FormatOptions,
 }
 
 impl Settings {
 	pub fn region(&self) -> String {
-		let region = self.locale.split('-').last().unwrap().to_string();
-		region
+		context::locale_region(&self.locale).to_string()
 	}
 	pub fn get() -> &'static RwLock<Settings> {
 		SETTINGS.get_or_init(|| RwLock::new(Settings::default()))
@@ -350,10 +609,6 @@ impl Settings {
 	pub fn write() -> RwLockWriteGuard<'static, Settings> {
 		Self::get().write().unwrap()
 	}
-	#[cfg(test)]
-	pub fn reset_to_default() {
-		*Self::write() = Settings::default();
-	}
 }
 impl Default for Settings {
 	fn default() -> Self {
@@ -361,6 +616,7 @@ impl Default for Settings {
 		{
 			Settings {
 				locale: "nb-NO".to_string(),
+				format: FormatOptions::default(),
 			}
 		}
 		#[cfg(not(test))]
@@ -369,11 +625,13 @@ impl Default for Settings {
 			{
 				Settings {
 					locale: "".to_string(),
+					format: FormatOptions::default(),
 				}
 			}
 			#[cfg(not(target_arch = "wasm32"))]
 			Settings {
 				locale: sys_locale::get_locale().unwrap_or("".to_string()),
+				format: FormatOptions::default(),
 			}
 		}
 	}
@@ -392,67 +650,190 @@ pub fn get_region() -> String {
 	Settings::read().region()
 }
 
-/// Evaluates a string into a resulting [`Number`].
+/// Limit the sig figs (precision) of the number
+pub(crate) fn round_to_sig_figs(x: D128, sig_figs: i32) -> D128 {
+	if x.is_zero() {
+		return x;
+	}
+	let mag: i32 = x.log10().floor().try_into().unwrap();
+	let shift = sig_figs - 1 - mag;
+	let factor = D128::TEN.powi(shift);
+	(x * factor).round(0) / factor
+}
+
+/// Options for [`eval`].
+///
+/// Example:
+/// ```rust
+/// use cpc::{eval, EvalOptions};
+///
+/// let options = EvalOptions::new()
+///     .allow_trailing_operators(true)
+///     .locale("en-US")
+///     .precision(3);
+/// let output = eval("1/3 +", &options).unwrap();
+/// assert_eq!(output.value.to_string(), "≈ 0.333");
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct EvalOptions {
+	allow_trailing_operators: bool,
+	locale: Option<String>,
+	precision: Option<u32>,
+	currency_policy: CurrencyPolicy,
+	locale_independent: bool,
+}
+impl EvalOptions {
+	pub fn new() -> EvalOptions {
+		EvalOptions::default()
+	}
+	/// Ignore an operator at the end of the input, like the `+` in `1 +`. Useful
+	/// when evaluating as the user types.
+	pub fn allow_trailing_operators(mut self, allow: bool) -> EvalOptions {
+		self.allow_trailing_operators = allow;
+		self
+	}
+	/// Locale like `en-US`, used for words like `$` that mean different things
+	/// in different regions. Defaults to the locale of the [`Context`].
+	pub fn locale(mut self, locale: impl Into<String>) -> EvalOptions {
+		self.locale = Some(locale.into());
+		self
+	}
+	/// Round the result to this many significant digits
+	pub fn precision(mut self, significant_digits: u32) -> EvalOptions {
+		self.precision = Some(significant_digits.max(1));
+		self
+	}
+	/// What to do when the expression contains a currency
+	pub fn currency_policy(mut self, policy: CurrencyPolicy) -> EvalOptions {
+		self.currency_policy = policy;
+		self
+	}
+	/// Read numbers with `.` as the decimal separator and `,` between
+	/// thousands, whatever the locale. Useful for scripts.
+	pub fn locale_independent(mut self, locale_independent: bool) -> EvalOptions {
+		self.locale_independent = locale_independent;
+		self
+	}
+}
+
+/// The result of [`eval`], along with the intermediate steps
+#[derive(Clone, Debug)]
+pub struct EvalOutput {
+	/// The resulting value
+	pub value: Value,
+	/// The tokens the input was lexed into
+	pub tokens: Vec<SpannedToken>,
+	/// The tree the tokens were parsed into, with ambiguous units resolved
+	pub ast: AstNode,
+	/// Time spent lexing
+	pub lex_time: Duration,
+	/// Time spent parsing
+	pub parse_time: Duration,
+	/// Time spent evaluating
+	pub eval_time: Duration,
+	/// Whether [`value`](EvalOutput::value) is exact, see [`Number::is_exact`]
+	pub exact: bool,
+}
+
+/// Evaluates a string into a resulting [`Value`].
 ///
 /// Example:
 /// ```rust
-/// use cpc::eval;
+/// use cpc::{eval, EvalOptions};
 /// use cpc::units::Unit;
 ///
-/// match eval("3m + 1cm", true, false) {
-///     Ok(answer) => {
-///         // answer: Number { value: 301, unit: Unit::Centimeter }
-///         println!("Evaluated value: {} {:?}", answer.value, answer.unit)
+/// match eval("3m + 1cm", &EvalOptions::new()) {
+///     Ok(output) => {
+///         // output.value: Number { value: 301, unit: Unit::Centimeter }
+///         println!("Evaluated value: {}", output.value)
 ///     },
 ///     Err(e) => {
 ///         println!("{e}")
 ///     }
 /// }
 /// ```
-pub fn eval(input: &str, allow_trailing_operators: bool, verbose: bool) -> Result<Number, String> {
-	let lex_start = Instant::now();
+///
+/// This uses the global [`Settings`] and currency cache. To evaluate with
+/// your own locale and exchange rates, use [`eval_with_context`].
+pub fn eval(input: &str, options: &EvalOptions) -> Result<EvalOutput, Error> {
+	let mut context = Context::from_globals();
+	let result = eval_with_context(input, options, &mut context);
+	if let Some(exchange_rates) = &context.exchange_rates {
+		currency::cache_rates(exchange_rates);
+	}
+	result
+}
 
-	match lexer::lex(input, allow_trailing_operators) {
-		Ok(tokens) => {
-			let lex_time = Instant::now().duration_since(lex_start).as_nanos() as f32;
-			if verbose {
-				println!("Lexed TokenVector: {:?}", tokens);
-			}
+/// Evaluates a string into a resulting [`Value`], using a [`Context`] instead
+/// of global state.
+///
+/// Variables and functions defined in the input are stored in the context,
+/// and so is a resulting number, as `ans`. If exchange rates are loaded during the evaluation, they
+/// are stored too.
+pub fn eval_with_context(
+	input: &str,
+	options: &EvalOptions,
+	context: &mut Context,
+) -> Result<EvalOutput, Error> {
+	let context_locale_independent = context.locale_independent;
+	context.locale_independent |= options.locale_independent;
+	let result = match &options.locale {
+		Some(locale) => {
+			let context_locale = std::mem::replace(&mut context.locale, locale.clone());
+			let result = evaluate_input(input, options, context);
+			context.locale = context_locale;
+			result
+		}
+		None => evaluate_input(input, options, context),
+	};
+	context.locale_independent = context_locale_independent;
+	result
+}
 
-			let parse_start = Instant::now();
-			match parser::parse(&tokens) {
-				Ok(mut ast) => {
-					let parse_time = Instant::now().duration_since(parse_start).as_nanos() as f32;
-					if verbose {
-						println!("Parsed AstNode: {:#?}", ast);
-					}
-
-					let eval_start = Instant::now();
-					match evaluator::evaluate(&mut ast) {
-						Ok(answer) => {
-							let eval_time =
-								Instant::now().duration_since(eval_start).as_nanos() as f32;
-
-							if verbose {
-								println!("Evaluated value: {} {:?}", answer.value, answer.unit);
-								println!("\u{23f1}  {:.3}ms lexing", lex_time / 1000.0 / 1000.0);
-								println!("\u{23f1}  {:.3}ms parsing", parse_time / 1000.0 / 1000.0);
-								println!(
-									"\u{23f1}  {:.3}ms evaluation",
-									eval_time / 1000.0 / 1000.0
-								);
-							}
-
-							Ok(answer)
-						}
-						Err(e) => Err(format!("Eval error: {}", e)),
-					}
-				}
-				Err(e) => Err(format!("Parsing error: {}", e)),
+fn evaluate_input(
+	input: &str,
+	options: &EvalOptions,
+	context: &mut Context,
+) -> Result<EvalOutput, Error> {
+	let lex_start = Instant::now();
+	let tokens = lexer::lex(input, options.allow_trailing_operators, context)?;
+	let lex_time = Instant::now().duration_since(lex_start);
+
+	let parse_start = Instant::now();
+	let mut ast = parser::parse(&tokens)?;
+	let parse_time = Instant::now().duration_since(parse_start);
+
+	let eval_start = Instant::now();
+	let mut value = evaluator::evaluate(&mut ast, context, options.currency_policy)?;
+	let mut exact = true;
+	if let Value::Number(number) | Value::Formatted(number, _) = &mut value {
+		if let Some(precision) = options.precision {
+			let value = round_to_sig_figs(number.value, precision as i32);
+			*number = Number::with_unit(value, number.unit.clone());
+		}
+		exact = number.is_exact();
+		context.variables.insert("ans".to_string(), number.clone());
+	}
+	if let Value::List(numbers) = &mut value {
+		for number in numbers.iter_mut() {
+			if let Some(precision) = options.precision {
+				let value = round_to_sig_figs(number.value, precision as i32);
+				*number = Number::with_unit(value, number.unit.clone());
 			}
+			exact &= number.is_exact();
 		}
-		Err(e) => Err(format!("Lexing error: {}", e)),
 	}
+	let eval_time = Instant::now().duration_since(eval_start);
+
+	Ok(EvalOutput {
+		value,
+		tokens,
+		ast,
+		lex_time,
+		parse_time,
+		eval_time,
+		exact,
+	})
 }
 
 #[cfg(target_arch = "wasm32")]
@@ -463,9 +844,17 @@ use wasm_bindgen::prelude::*;
 pub fn wasm_eval(expression: &str) -> String {
 	console_error_panic_hook::set_once();
 
-	let result = eval(expression, true, false);
+	let result = eval(
+		expression,
+		&EvalOptions::new().allow_trailing_operators(true),
+	);
+	let settings = Settings::read();
+	let format_options = match settings.format.has_separators() {
+		true => settings.format.clone(),
+		false => settings.format.clone().locale(&settings.locale),
+	};
 	match result {
-		Ok(result) => result.to_string(),
+		Ok(output) => output.value.format(&format_options),
 		Err(e) => format!("Error: {e}"),
 	}
 }
@@ -475,7 +864,8 @@ mod tests {
 	use super::*;
 
 	fn default_eval(input: &str) -> Number {
-		eval(input, true, false).unwrap()
+		let options = EvalOptions::new().allow_trailing_operators(true);
+		eval(input, &options).unwrap().value.into_number().unwrap()
 	}
 
 	#[test]
@@ -497,4 +887,70 @@ mod tests {
 		assert_eq!(default_eval("-3^2"), Number::new_unitless(d!(-9)));
 		assert_eq!(default_eval("-1+2"), Number::new_unitless(d!(1)));
 	}
+
+	#[test]
+	fn test_eval_options() {
+		let output = eval("1/4", &EvalOptions::new()).unwrap();
+		assert!(output.exact);
+		assert_eq!(output.tokens.len(), 3);
+		assert_eq!(output.ast.token, Token::Operator(Operator::Divide));
+
+		let output = eval("1/3", &EvalOptions::new()).unwrap();
+		assert!(output.exact);
+		assert_eq!(
+			output.value.to_string(),
+			"≈ 0.333333333333333333333333333333333333333"
+		);
+		assert!(!eval("sqrt(2)", &EvalOptions::new()).unwrap().exact);
+		assert!(!eval("[1/4, pi]", &EvalOptions::new()).unwrap().exact);
+
+		let output = eval("2/3", &EvalOptions::new().precision(2)).unwrap();
+		assert_eq!(output.value.to_string(), "≈ 0.67");
+		assert!(!output.exact);
+
+		assert!(eval("1 +", &EvalOptions::new()).is_err());
+		assert!(eval("1 +", &EvalOptions::new().allow_trailing_operators(true)).is_ok());
+
+		let us = eval("1 $", &EvalOptions::new().locale("en-US")).unwrap();
+		assert_eq!(us.value.as_number().unwrap().unit, vec![(Unit::USD, 1)]);
+		let au = eval("1 $", &EvalOptions::new().locale("en-AU")).unwrap();
+		assert_eq!(au.value.as_number().unwrap().unit, vec![(Unit::AUD, 1)]);
+	}
+
+	#[test]
+	fn test_currency_policy() {
+		let disabled = EvalOptions::new().currency_policy(CurrencyPolicy::Disabled);
+		assert!(eval("1 EUR + 2 EUR", &disabled).is_ok());
+		let error = eval("1 EUR to USD", &disabled).unwrap_err();
+		assert!(matches!(error, Error::CurrencyUnavailable { .. }));
+		assert_eq!(error.span(), Span::new(9, 12));
+
+		let offline = EvalOptions::new().currency_policy(CurrencyPolicy::Offline);
+		let error = eval("1 EUR to XPT", &offline).unwrap_err();
+		assert!(matches!(error, Error::CurrencyUnavailable { .. }));
+	}
+
+	#[test]
+	fn test_context_per_thread() {
+		let threads: Vec<_> = [
+			("en-US", Unit::USD),
+			("en-AU", Unit::AUD),
+			("es-MX", Unit::MXN),
+		]
+		.into_iter()
+		.map(|(locale, expected)| {
+			std::thread::spawn(move || {
+				let mut context = Context::new(locale);
+				for _ in 0..20 {
+					let output = eval_with_context("1 $", &EvalOptions::new(), &mut context);
+					let number = output.unwrap().value.into_number().unwrap();
+					assert_eq!(number.unit, vec![(expected.clone(), 1)]);
+				}
+			})
+		})
+		.collect();
+		for thread in threads {
+			thread.join().unwrap();
+		}
+	}
 }
@@ -1,6 +1,11 @@
-use cpc::eval;
+use cpc::format::Notation;
+use cpc::{
+	Context, EvalOptions, FormatOptions, Settings, Value, eval_with_context, load_units_from_file,
+};
 use std::env;
+use std::path::PathBuf;
 use std::process::exit;
+use std::time::Duration;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -9,18 +14,40 @@ fn print_help() {
 		"Usage: cpc '<expression>' [options]",
 		"\n",
 		"\nOptions:",
-		"\n    --verbose   Enable verbose logging",
-		"\n    --version   Show cpc version",
-		"\n    --help      Show this help page",
+		"\n    --digits <n>  Round results to n significant digits",
+		"\n    --sci         Show results in scientific notation",
+		"\n    --locale-independent",
+		"\n                  Read and show numbers with . as the decimal separator,",
+		"\n                  whatever your locale",
+		"\n    --verbose     Enable verbose logging",
+		"\n    --version     Show cpc version",
+		"\n    --help        Show this help page",
+		"\n",
+		"\nCustom units are loaded from cpc/units.txt in your config directory,",
+		"\nwith one definition per line, like `furlong = 220 yd`.",
 	));
 }
 
+/// The unit definition file in the user's config directory, like
+/// `~/.config/cpc/units.txt`
+fn units_file_path() -> Option<PathBuf> {
+	let config_dir = env::var_os("XDG_CONFIG_HOME")
+		.or_else(|| env::var_os("APPDATA"))
+		.map(PathBuf::from)
+		.or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+	Some(config_dir.join("cpc").join("units.txt"))
+}
+
 fn get_args() -> env::Args {
 	let mut args = env::args();
 	args.next(); // skip binary name
 	args
 }
 
+fn ms(duration: Duration) -> f64 {
+	duration.as_secs_f64() * 1000.0
+}
+
 /// CLI interface
 fn main() {
 	// parse these first so they work if there are unexpected args
@@ -38,10 +65,24 @@ fn main() {
 		}
 	}
 	let mut verbose = false;
+	let mut locale_independent = false;
 	let mut expression_opt = None;
-	for arg in get_args() {
+	let mut format_options = FormatOptions::new();
+	let mut args = get_args();
+	while let Some(arg) = args.next() {
 		match arg.as_str() {
 			"-v" | "--verbose" => verbose = true,
+			"--locale-independent" => locale_independent = true,
+			"--sci" => format_options = format_options.notation(Notation::Scientific),
+			"--digits" => match args.next().map(|digits| digits.parse()) {
+				Some(Ok(digits)) if digits > 0 => {
+					format_options = format_options.max_digits(digits)
+				}
+				_ => {
+					eprintln!("--digits needs a number of digits from 1, like --digits 6");
+					exit(1);
+				}
+			},
 			_ => {
 				if expression_opt.is_none() {
 					expression_opt = Some(arg);
@@ -60,10 +101,35 @@ fn main() {
 		}
 	};
 
-	match eval(&expression, true, verbose) {
-		Ok(answer) => {
-			if !verbose {
-				println!("{answer}");
+	let mut context = Context::from_globals();
+	if let Some(path) = units_file_path()
+		&& path.exists()
+		&& let Err(e) = load_units_from_file(&path, &mut context)
+	{
+		eprintln!("{}: {e}", path.display());
+	}
+
+	if !locale_independent {
+		format_options = format_options.locale(&Settings::read().locale);
+	}
+	let options = EvalOptions::new()
+		.allow_trailing_operators(true)
+		.locale_independent(locale_independent);
+	match eval_with_context(&expression, &options, &mut context) {
+		Ok(output) => {
+			if verbose {
+				println!("Lexed TokenVector: {:?}", output.tokens);
+				println!("Parsed AstNode: {:#?}", output.ast);
+				println!("Evaluated value: {:?}", output.value);
+				println!("\u{23f1}  {:.3}ms lexing", ms(output.lex_time));
+				println!("\u{23f1}  {:.3}ms parsing", ms(output.parse_time));
+				println!("\u{23f1}  {:.3}ms evaluation", ms(output.eval_time));
+			} else if let Value::List(numbers) = &output.value {
+				for number in numbers {
+					println!("{}", number.format(&format_options));
+				}
+			} else {
+				println!("{}", output.value.format(&format_options));
 			}
 		}
 		Err(e) => {
@@ -1,9 +1,9 @@
 use crate::Operator::*;
 use crate::TextOperator::*;
-use crate::Token;
 use crate::UnaryOperator::*;
-use crate::units::Unit;
-use crate::units::Unit::{Foot, Inch};
+use crate::units::{Unit, primitive_unit};
+use crate::{Error, Operator, Span};
+use crate::{SpannedToken, Token};
 use core::fmt;
 
 /// A struct with a [`Token`](AstNode::token) and [`AstNode`] [`children`](AstNode::children)
@@ -13,12 +13,23 @@ pub struct AstNode {
 	pub children: Vec<AstNode>,
 	/// The token of the [`AstNode`]
 	pub token: Token,
+	/// The part of the input this [`AstNode`] and its children were parsed from
+	pub span: Span,
 }
 impl AstNode {
-	pub const fn new(token: Token) -> AstNode {
+	pub const fn new(token: Token, span: Span) -> AstNode {
 		AstNode {
 			children: Vec::new(),
 			token,
+			span,
+		}
+	}
+	/// Create a node with two children, spanning from the start of `left` to the end of `right`
+	fn binary(token: Token, left: AstNode, right: AstNode) -> AstNode {
+		AstNode {
+			span: Span::new(left.span.start, right.span.end),
+			children: vec![left, right],
+			token,
 		}
 	}
 }
@@ -26,6 +37,7 @@ impl fmt::Debug for AstNode {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
 		let mut d = f.debug_struct("AstNode");
 		d.field("token", &self.token);
+		d.field("span", &self.span);
 		if self.children.is_empty() {
 			d.field("children", &self.children);
 		} else {
@@ -37,37 +49,135 @@ impl fmt::Debug for AstNode {
 	}
 }
 
+/// The [`Token`] at `pos`, if there is one
+fn token_at(tokens: &[SpannedToken], pos: usize) -> Option<&Token> {
+	tokens.get(pos).map(|t| &t.token)
+}
+
+fn span_at(tokens: &[SpannedToken], pos: usize) -> Span {
+	tokens.get(pos).map(|t| t.span).unwrap_or_default()
+}
+
+fn unexpected(tokens: &[SpannedToken], pos: usize) -> Error {
+	match tokens.get(pos) {
+		Some(t) => Error::UnexpectedToken {
+			token: t.token.clone(),
+			span: t.span,
+		},
+		None => {
+			let end = tokens.last().map(|t| t.span.end).unwrap_or_default();
+			Error::UnexpectedEnd {
+				span: Span::new(end, end),
+			}
+		}
+	}
+}
+
 /// Parse [`Token`]s into an Abstract Syntax Tree ([`AstNode`])
-pub fn parse(tokens: &[Token]) -> Result<AstNode, String> {
-	parse_text_operators(tokens, 0).and_then(|(ast, next_pos)| {
+pub fn parse(tokens: &[SpannedToken]) -> Result<AstNode, Error> {
+	parse_assignment(tokens, 0).and_then(|(ast, next_pos)| {
 		if next_pos == tokens.len() {
 			Ok(ast)
 		} else {
-			Err(format!(
-				"Expected end of input, found {:?} at {}",
-				tokens[next_pos], next_pos
-			))
+			Err(unexpected(tokens, next_pos))
 		}
 	})
 }
 
+/// Parse an [`Assign`](crate::Operator::Assign)ment like `x = 5 km` or a
+/// function definition like `f(x) = x^2`, which are only allowed at the start
+/// of the input
+pub fn parse_assignment(tokens: &[SpannedToken], pos: usize) -> Result<(AstNode, usize), Error> {
+	if let Some(Token::Identifier(_)) = token_at(tokens, pos) {
+		let name_node = AstNode::new(tokens[pos].token.clone(), span_at(tokens, pos));
+		let target = match token_at(tokens, pos + 1) {
+			Some(Token::Operator(Assign)) => Some((name_node, pos + 1)),
+			Some(Token::Operator(LeftParen)) => parse_signature(tokens, name_node, pos + 1),
+			_ => None,
+		};
+		if let Some((target_node, assign_pos)) = target
+			&& let Some(Token::Operator(Assign)) = token_at(tokens, assign_pos)
+		{
+			let (value_node, next_pos) = parse_text_operators(tokens, assign_pos + 1)?;
+			let node = AstNode::binary(Token::Operator(Assign), target_node, value_node);
+			return Ok((node, next_pos));
+		}
+	}
+	parse_text_operators(tokens, pos)
+}
+
+/// Parse the parameter list of a function signature like `f(x, y)`, starting
+/// at the `(`. The parameters become the children of `name_node`.
+fn parse_signature(
+	tokens: &[SpannedToken],
+	mut name_node: AstNode,
+	mut pos: usize,
+) -> Option<(AstNode, usize)> {
+	loop {
+		pos += 1;
+		match token_at(tokens, pos) {
+			Some(Token::Identifier(_)) => {
+				let parameter = AstNode::new(tokens[pos].token.clone(), span_at(tokens, pos));
+				name_node.children.push(parameter);
+			}
+			_ => return None,
+		}
+		pos += 1;
+		match token_at(tokens, pos) {
+			Some(Token::Operator(Comma)) => continue,
+			Some(Token::Operator(RightParen)) => break,
+			_ => return None,
+		}
+	}
+	name_node.span.end = span_at(tokens, pos).end;
+	Some((name_node, pos + 1))
+}
+
+/// Parse comma separated arguments, like those of a function call or the
+/// elements of a list, starting after the `(` and ending after the `end` token.
+/// There can be no arguments, like `sum()` or `[]`.
+fn parse_arguments(
+	tokens: &[SpannedToken],
+	pos: usize,
+	end: Operator,
+) -> Result<(Vec<AstNode>, usize), Error> {
+	let mut arguments = Vec::new();
+	if let Some(Token::Operator(operator)) = token_at(tokens, pos)
+		&& *operator == end
+	{
+		return Ok((arguments, pos + 1));
+	}
+	let mut pos = pos;
+	loop {
+		let (argument, next_pos) = parse_text_operators(tokens, pos)?;
+		arguments.push(argument);
+		match token_at(tokens, next_pos) {
+			Some(Token::Operator(Comma)) => pos = next_pos + 1,
+			Some(Token::Operator(operator)) if *operator == end => {
+				return Ok((arguments, next_pos + 1));
+			}
+			_ => return Err(unexpected(tokens, next_pos)),
+		}
+	}
+}
+
 // level 1 precedence (lowest): to, of
 /// Parse [`To`](crate::TextOperator::To) and [`Of`](crate::TextOperator::Of)
-pub fn parse_text_operators(tokens: &[Token], pos: usize) -> Result<(AstNode, usize), String> {
+pub fn parse_text_operators(
+	tokens: &[SpannedToken],
+	pos: usize,
+) -> Result<(AstNode, usize), Error> {
 	// do higher precedences first, then come back down
-	let (mut node, mut pos) = parse_plus(tokens, pos)?;
+	let (mut node, mut pos) = parse_range(tokens, pos)?;
 	// now we loop through the next tokens
 	loop {
-		let token = tokens.get(pos);
+		let token = token_at(tokens, pos);
 		match token {
 			// if there's a match, we once again do higher precedences, then come
 			// back down again and continue the loop
 			Some(&Token::TextOperator(To)) | Some(&Token::TextOperator(Of)) => {
-				let (right_node, next_pos) = parse_plus(tokens, pos + 1)?;
-				let mut new_node = AstNode::new(token.unwrap().clone());
-				new_node.children.push(node);
-				new_node.children.push(right_node);
-				node = new_node;
+				let (right_node, next_pos) = parse_range(tokens, pos + 1)?;
+				node = AstNode::binary(token.unwrap().clone(), node, right_node);
 				pos = next_pos;
 			}
 			// if there's no match, we go down to a lower precedence
@@ -78,18 +188,81 @@ pub fn parse_text_operators(tokens: &[Token], pos: usize) -> Result<(AstNode, us
 	}
 }
 
+/// Parse a [`Range`](crate::Operator::Range) like `1..10` or `1..10 step 2`,
+/// with the start, end and step as children
+pub fn parse_range(tokens: &[SpannedToken], pos: usize) -> Result<(AstNode, usize), Error> {
+	let (start, pos) = parse_bitwise(tokens, pos)?;
+	let Some(Token::Operator(Range)) = token_at(tokens, pos) else {
+		return Ok((start, pos));
+	};
+	let (end, mut pos) = parse_bitwise(tokens, pos + 1)?;
+	let mut node = AstNode::binary(Token::Operator(Range), start, end);
+	if let Some(Token::TextOperator(Step)) = token_at(tokens, pos) {
+		let (step, next_pos) = parse_bitwise(tokens, pos + 1)?;
+		node.span.end = step.span.end;
+		node.children.push(step);
+		pos = next_pos;
+	}
+	Ok((node, pos))
+}
+
+/// Bitwise operators from lowest to highest precedence, like in C
+const BITWISE_OPERATORS: [Operator; 3] = [Or, Xor, And];
+
+/// Parse [`Or`](crate::Operator::Or), [`Xor`](crate::Operator::Xor) and
+/// [`And`](crate::Operator::And)
+pub fn parse_bitwise(tokens: &[SpannedToken], pos: usize) -> Result<(AstNode, usize), Error> {
+	parse_bitwise_level(tokens, pos, 0)
+}
+
+/// Parse the bitwise operator at `level` in [`BITWISE_OPERATORS`], with the
+/// operators after it as higher precedences
+fn parse_bitwise_level(
+	tokens: &[SpannedToken],
+	pos: usize,
+	level: usize,
+) -> Result<(AstNode, usize), Error> {
+	let Some(operator) = BITWISE_OPERATORS.get(level) else {
+		return parse_shift(tokens, pos);
+	};
+	let (mut node, mut pos) = parse_bitwise_level(tokens, pos, level + 1)?;
+	while let Some(Token::Operator(token_operator)) = token_at(tokens, pos)
+		&& token_operator == operator
+	{
+		let (right_node, next_pos) = parse_bitwise_level(tokens, pos + 1, level + 1)?;
+		node = AstNode::binary(Token::Operator(operator.clone()), node, right_node);
+		pos = next_pos;
+	}
+	Ok((node, pos))
+}
+
+/// Parse [`<<`](crate::Operator::ShiftLeft), [`>>`](crate::Operator::ShiftRight)
+pub fn parse_shift(tokens: &[SpannedToken], pos: usize) -> Result<(AstNode, usize), Error> {
+	let (mut node, mut pos) = parse_plus(tokens, pos)?;
+	loop {
+		let token = token_at(tokens, pos);
+		match token {
+			Some(&Token::Operator(ShiftLeft)) | Some(&Token::Operator(ShiftRight)) => {
+				let (right_node, next_pos) = parse_plus(tokens, pos + 1)?;
+				node = AstNode::binary(token.unwrap().clone(), node, right_node);
+				pos = next_pos;
+			}
+			_ => {
+				return Ok((node, pos));
+			}
+		}
+	}
+}
+
 /// Parse [`+`](crate::Operator::Plus), [`-`](crate::Operator::Minus)
-pub fn parse_plus(tokens: &[Token], pos: usize) -> Result<(AstNode, usize), String> {
+pub fn parse_plus(tokens: &[SpannedToken], pos: usize) -> Result<(AstNode, usize), Error> {
 	let (mut node, mut pos) = parse_unary(tokens, pos)?;
 	loop {
-		let token = tokens.get(pos);
+		let token = token_at(tokens, pos);
 		match token {
 			Some(&Token::Operator(Plus)) | Some(&Token::Operator(Minus)) => {
 				let (right_node, next_pos) = parse_unary(tokens, pos + 1)?;
-				let mut new_node = AstNode::new(token.unwrap().clone());
-				new_node.children.push(node);
-				new_node.children.push(right_node);
-				node = new_node;
+				node = AstNode::binary(token.unwrap().clone(), node, right_node);
 				pos = next_pos;
 			}
 			_ => {
@@ -99,14 +272,19 @@ pub fn parse_plus(tokens: &[Token], pos: usize) -> Result<(AstNode, usize), Stri
 	}
 }
 
-/// Parse [`unary -`](Token::Negative) (for example -5)
-pub fn parse_unary(tokens: &[Token], pos: usize) -> Result<(AstNode, usize), String> {
+/// Parse [`unary -`](Token::Negative) (for example -5) and [`Not`](crate::UnaryOperator::Not)
+pub fn parse_unary(tokens: &[SpannedToken], pos: usize) -> Result<(AstNode, usize), Error> {
 	// Since a unary operator has no left side, we parse the the unary operator immediately
-	let token = tokens.get(pos);
+	let token = token_at(tokens, pos);
 	match token {
-		Some(&Token::Operator(Minus)) => {
+		Some(&Token::Operator(Minus)) | Some(&Token::UnaryOperator(Not)) => {
 			let (right_node, next_pos) = parse_mult_level(tokens, pos + 1)?;
-			let mut new_node = AstNode::new(Token::Negative);
+			let span = Span::new(span_at(tokens, pos).start, right_node.span.end);
+			let new_token = match token {
+				Some(&Token::UnaryOperator(Not)) => Token::UnaryOperator(Not),
+				_ => Token::Negative,
+			};
+			let mut new_node = AstNode::new(new_token, span);
 			new_node.children.push(right_node);
 			Ok((new_node, next_pos))
 		}
@@ -115,59 +293,100 @@ pub fn parse_unary(tokens: &[Token], pos: usize) -> Result<(AstNode, usize), Str
 }
 
 pub fn is_basic_unit(value: Option<&Token>, u: Unit) -> bool {
+	basic_unit(value) == Some(u)
+}
+
+/// The unit of a token that's a single unit without an exponent, like `m`
+fn basic_unit(value: Option<&Token>) -> Option<Unit> {
 	match value {
 		Some(Token::Unit(unit)) => match unit.as_slice() {
-			[(unit, 1)] => *unit == u,
-			_ => false,
+			[(unit, 1)] => Some(unit.clone()),
+			_ => None,
 		},
-		_ => false,
+		_ => None,
 	}
 }
 
-/// Parse [`*`](crate::Operator::Multiply), [`/`](crate::Operator::Divide), [`Modulo`](crate::Operator::Modulo), implicative multiplication (for example`2pi`), foot-inch syntax (for example `6'4"`)
-pub fn parse_mult_level(tokens: &[Token], pos: usize) -> Result<(AstNode, usize), String> {
-	// parse foot-inch syntax 6'4"
-	let token0 = tokens.get(pos);
-	if let Some(Token::Number(num0)) = token0 {
-		let token1 = tokens.get(pos + 1);
-		if is_basic_unit(token1, Foot) {
-			let token2 = tokens.get(pos + 2);
-			if let Some(Token::Number(num1)) = token2 {
-				let token3 = tokens.get(pos + 3);
-				if is_basic_unit(token3, Inch) {
-					let new_node = AstNode {
-						children: vec![
-							AstNode {
-								children: vec![AstNode::new(Token::Number(*num0))],
-								token: Token::unit(Foot),
-							},
-							AstNode {
-								children: vec![AstNode::new(Token::Number(*num1))],
-								token: Token::unit(Inch),
-							},
-						],
-						token: Token::Operator(Plus),
-					};
-					return Ok((new_node, pos + 4));
-				}
-			}
+/// Whether `unit` can come after `previous` in a compound like `1h 30min`,
+/// which it can if it's a smaller unit of the same type
+fn is_next_compound_unit(previous: Option<&Unit>, unit: &Unit) -> bool {
+	use crate::units::UnitType::*;
+	if matches!(unit.category(), Unknown | Temperature | Currency) {
+		return false;
+	}
+	match previous {
+		Some(previous) => {
+			previous.category() == unit.category()
+				&& primitive_unit(&[(previous.clone(), 1)]) == primitive_unit(&[(unit.clone(), 1)])
+				&& unit.weight() < previous.weight()
 		}
+		None => true,
+	}
+}
+
+/// Parse numbers that each have their own unit, like `6'4"` or `1h 30min`,
+/// into the sum of the parts. The units need to be of the same type, from
+/// largest to smallest.
+fn parse_compound(tokens: &[SpannedToken], pos: usize) -> Option<(AstNode, usize)> {
+	let mut parts = Vec::new();
+	let mut previous = None;
+	let mut pos = pos;
+	while let Some(Token::Number(number)) = token_at(tokens, pos)
+		&& let Some(unit) = basic_unit(token_at(tokens, pos + 1))
+		&& is_next_compound_unit(previous.as_ref(), &unit)
+		// in `2 m 3 cm^2`, the exponent belongs to the unit
+		&& token_at(tokens, pos + 2) != Some(&Token::Operator(Caret))
+	{
+		parts.push(AstNode {
+			children: vec![AstNode::new(Token::Number(*number), span_at(tokens, pos))],
+			token: Token::unit(unit.clone()),
+			span: Span::new(span_at(tokens, pos).start, span_at(tokens, pos + 1).end),
+		});
+		previous = Some(unit);
+		pos += 2;
+	}
+	if parts.len() < 2 {
+		return None;
 	}
+	let in_degrees = parts[0].token == Token::unit(Unit::Degree);
+	let sum = parts
+		.into_iter()
+		.reduce(|sum, part| AstNode::binary(Token::Operator(Plus), sum, part))?;
+	// degrees-minutes-seconds like 45°30'15" stay in degrees, not arcseconds
+	if in_degrees {
+		let span = sum.span;
+		let degrees = AstNode::new(Token::unit(Unit::Degree), span);
+		return Some((
+			AstNode {
+				token: Token::TextOperator(To),
+				children: vec![sum, degrees],
+				span,
+			},
+			pos,
+		));
+	}
+	Some((sum, pos))
+}
 
-	let (mut node, mut pos) = parse_implicit_unit_mult(tokens, pos)?;
+/// Parse [`*`](crate::Operator::Multiply), [`/`](crate::Operator::Divide), [`Modulo`](crate::Operator::Modulo), [`IntegerDivide`](crate::Operator::IntegerDivide), implicative multiplication (for example`2pi`), foot-inch syntax (for example `6'4"`)
+pub fn parse_mult_level(tokens: &[SpannedToken], pos: usize) -> Result<(AstNode, usize), Error> {
+	// parse compounds like foot-inch syntax 6'4", degrees-minutes-seconds
+	// 45°30'15" and 1h 30min
+	let (mut node, mut pos) = match parse_compound(tokens, pos) {
+		Some(compound) => compound,
+		None => parse_implicit_unit_mult(tokens, pos)?,
+	};
 
 	loop {
-		let token = tokens.get(pos);
+		let token = token_at(tokens, pos);
 		match token {
 			Some(&Token::Operator(Multiply))
 			| Some(&Token::Operator(Divide))
 			| Some(&Token::TextOperator(Per))
-			| Some(&Token::Operator(Modulo)) => {
+			| Some(&Token::Operator(Modulo))
+			| Some(&Token::Operator(IntegerDivide)) => {
 				let (right_node, next_pos) = parse_implicit_unit_mult(tokens, pos + 1)?;
-				let mut new_node = AstNode::new(token.unwrap().clone());
-				new_node.children.push(node);
-				new_node.children.push(right_node);
-				node = new_node;
+				node = AstNode::binary(token.unwrap().clone(), node, right_node);
 				pos = next_pos;
 			}
 
@@ -178,16 +397,15 @@ pub fn parse_mult_level(tokens: &[Token], pos: usize) -> Result<(AstNode, usize)
 			// matches nested inside check the [`Token`]s. That's why we for example
 			// match a FunctionIdentifier, and inside that, a RightParen.
 
-			// pi2, )2
+			// pi2, )2, x 2
 			Some(&Token::Number(_)) => {
-				let last_token = tokens.get(pos - 1);
+				let last_token = token_at(tokens, pos - 1);
 				match last_token {
-					Some(&Token::Constant(_)) | Some(&Token::Operator(RightParen)) => {
+					Some(&Token::Constant(_))
+					| Some(&Token::Identifier(_))
+					| Some(&Token::Operator(RightParen)) => {
 						let (right_node, next_pos) = parse_implicit_unit_mult(tokens, pos)?;
-						let mut new_node = AstNode::new(Token::Operator(Multiply));
-						new_node.children.push(node);
-						new_node.children.push(right_node);
-						node = new_node;
+						node = AstNode::binary(Token::Operator(Multiply), node, right_node);
 						pos = next_pos;
 					}
 					_ => {
@@ -195,16 +413,19 @@ pub fn parse_mult_level(tokens: &[Token], pos: usize) -> Result<(AstNode, usize)
 					}
 				}
 			}
-			// 2pi, )pi
-			Some(&Token::Constant(_)) => {
-				let last_token = tokens.get(pos - 1);
+			// 2pi, )pi, 2x, )x, pi x
+			Some(&Token::Constant(_)) | Some(&Token::Identifier(_)) => {
+				let last_token = token_at(tokens, pos - 1);
+				let is_identifier = matches!(token, Some(&Token::Identifier(_)));
 				match last_token {
 					Some(&Token::Number(_)) | Some(&Token::Operator(RightParen)) => {
 						let (right_node, next_pos) = parse_implicit_unit_mult(tokens, pos)?;
-						let mut new_node = AstNode::new(Token::Operator(Multiply));
-						new_node.children.push(node);
-						new_node.children.push(right_node);
-						node = new_node;
+						node = AstNode::binary(Token::Operator(Multiply), node, right_node);
+						pos = next_pos;
+					}
+					Some(&Token::Constant(_)) if is_identifier => {
+						let (right_node, next_pos) = parse_implicit_unit_mult(tokens, pos)?;
+						node = AstNode::binary(Token::Operator(Multiply), node, right_node);
 						pos = next_pos;
 					}
 					_ => {
@@ -214,14 +435,11 @@ pub fn parse_mult_level(tokens: &[Token], pos: usize) -> Result<(AstNode, usize)
 			}
 			// 2log(1), )log(1)
 			Some(&Token::FunctionIdentifier(_)) => {
-				let last_token = tokens.get(pos - 1);
+				let last_token = token_at(tokens, pos - 1);
 				match last_token {
 					Some(&Token::Number(_)) | Some(&Token::Operator(RightParen)) => {
 						let (right_node, next_pos) = parse_implicit_unit_mult(tokens, pos)?;
-						let mut new_node = AstNode::new(Token::Operator(Multiply));
-						new_node.children.push(node);
-						new_node.children.push(right_node);
-						node = new_node;
+						node = AstNode::binary(Token::Operator(Multiply), node, right_node);
 						pos = next_pos;
 					}
 					_ => {
@@ -231,16 +449,13 @@ pub fn parse_mult_level(tokens: &[Token], pos: usize) -> Result<(AstNode, usize)
 			}
 			// 2(3), pi(3), )(3)
 			Some(&Token::Operator(LeftParen)) => {
-				let last_token = tokens.get(pos - 1);
+				let last_token = token_at(tokens, pos - 1);
 				match last_token {
 					Some(&Token::Number(_))
 					| Some(&Token::Constant(_))
 					| Some(&Token::Operator(RightParen)) => {
 						let (right_node, next_pos) = parse_implicit_unit_mult(tokens, pos)?;
-						let mut new_node = AstNode::new(Token::Operator(Multiply));
-						new_node.children.push(node);
-						new_node.children.push(right_node);
-						node = new_node;
+						node = AstNode::binary(Token::Operator(Multiply), node, right_node);
 						pos = next_pos;
 					}
 					_ => {
@@ -256,19 +471,19 @@ pub fn parse_mult_level(tokens: &[Token], pos: usize) -> Result<(AstNode, usize)
 }
 
 /// Parse numbers with units (for example `2km`)
-pub fn parse_implicit_unit_mult(tokens: &[Token], pos: usize) -> Result<(AstNode, usize), String> {
+pub fn parse_implicit_unit_mult(
+	tokens: &[SpannedToken],
+	pos: usize,
+) -> Result<(AstNode, usize), Error> {
 	let (mut node, mut pos) = parse_unit_mult_level(tokens, pos)?;
 	loop {
-		let token = tokens.get(pos);
+		let token = token_at(tokens, pos);
 		match token {
 			// maybe this can be in parse_mult_level instead?
 			Some(&Token::Unit(_)) => {
 				// implicit unit multiplication, e.g. `ft s`
 				let (right_node, next_pos) = parse_unit_mult_level(tokens, pos)?;
-				let mut new_node = AstNode::new(Token::Operator(Multiply));
-				new_node.children.push(node);
-				new_node.children.push(right_node);
-				node = new_node;
+				node = AstNode::binary(Token::Operator(Multiply), node, right_node);
 				pos = next_pos;
 			}
 			_ => {
@@ -279,21 +494,21 @@ pub fn parse_implicit_unit_mult(tokens: &[Token], pos: usize) -> Result<(AstNode
 }
 
 /// Parse multiplication and division with units (for example `km/h` or `km*km`)
-pub fn parse_unit_mult_level(tokens: &[Token], pos: usize) -> Result<(AstNode, usize), String> {
+pub fn parse_unit_mult_level(
+	tokens: &[SpannedToken],
+	pos: usize,
+) -> Result<(AstNode, usize), Error> {
 	let (mut node, mut pos) = parse_caret(tokens, pos)?;
 	loop {
-		let token = tokens.get(pos);
+		let token = token_at(tokens, pos);
 		match token {
 			Some(&Token::Operator(Multiply))
 			| Some(&Token::Operator(Divide))
 			| Some(&Token::TextOperator(Per)) => {
 				// only consume if the right side is a unit
-				if let Some(&Token::Unit(_)) = tokens.get(pos + 1) {
+				if let Some(&Token::Unit(_)) = token_at(tokens, pos + 1) {
 					let (right_node, next_pos) = parse_caret(tokens, pos + 1)?;
-					let mut new_node = AstNode::new(token.unwrap().clone());
-					new_node.children.push(node);
-					new_node.children.push(right_node);
-					node = new_node;
+					node = AstNode::binary(token.unwrap().clone(), node, right_node);
 					pos = next_pos;
 				} else {
 					return Ok((node, pos));
@@ -307,17 +522,14 @@ pub fn parse_unit_mult_level(tokens: &[Token], pos: usize) -> Result<(AstNode, u
 }
 
 /// Parse [`^`](crate::Operator::Caret), unit suffix (for example `2km` or `2km^2`)
-pub fn parse_caret(tokens: &[Token], pos: usize) -> Result<(AstNode, usize), String> {
+pub fn parse_caret(tokens: &[SpannedToken], pos: usize) -> Result<(AstNode, usize), Error> {
 	let (mut node, mut pos) = parse_unary_high(tokens, pos)?;
 	loop {
-		let token = tokens.get(pos);
+		let token = token_at(tokens, pos);
 		match token {
 			Some(&Token::Operator(Caret)) => {
 				let (right_node, next_pos) = parse_unary_high(tokens, pos + 1)?;
-				let mut new_node = AstNode::new(token.unwrap().clone());
-				new_node.children.push(node);
-				new_node.children.push(right_node);
-				node = new_node;
+				node = AstNode::binary(token.unwrap().clone(), node, right_node);
 				pos = next_pos;
 			}
 			_ => {
@@ -327,13 +539,19 @@ pub fn parse_caret(tokens: &[Token], pos: usize) -> Result<(AstNode, usize), Str
 	}
 }
 
-/// Parse [`unary -`](Token::Negative) at high precedence (for example in 3^-2)
-pub fn parse_unary_high(tokens: &[Token], pos: usize) -> Result<(AstNode, usize), String> {
-	let token = tokens.get(pos);
+/// Parse [`unary -`](Token::Negative) and [`Not`](crate::UnaryOperator::Not) at
+/// high precedence (for example in 3^-2 or 2 * not 3)
+pub fn parse_unary_high(tokens: &[SpannedToken], pos: usize) -> Result<(AstNode, usize), Error> {
+	let token = token_at(tokens, pos);
 	match token {
-		Some(&Token::Operator(Minus)) => {
+		Some(&Token::Operator(Minus)) | Some(&Token::UnaryOperator(Not)) => {
 			let (right_node, next_pos) = parse_suffix(tokens, pos + 1)?;
-			let mut new_node = AstNode::new(Token::Negative);
+			let span = Span::new(span_at(tokens, pos).start, right_node.span.end);
+			let new_token = match token {
+				Some(&Token::UnaryOperator(Not)) => Token::UnaryOperator(Not),
+				_ => Token::Negative,
+			};
+			let mut new_node = AstNode::new(new_token, span);
 			new_node.children.push(right_node);
 			Ok((new_node, next_pos))
 		}
@@ -342,10 +560,10 @@ pub fn parse_unary_high(tokens: &[Token], pos: usize) -> Result<(AstNode, usize)
 }
 
 /// Parse [`!!`](crate::UnaryOperator::Factorial), [`Percent`](crate::UnaryOperator::Percent), units attached to values
-pub fn parse_suffix(tokens: &[Token], pos: usize) -> Result<(AstNode, usize), String> {
+pub fn parse_suffix(tokens: &[SpannedToken], pos: usize) -> Result<(AstNode, usize), Error> {
 	let (mut node, mut pos) = parse_highest(tokens, pos)?;
 	loop {
-		let token = tokens.get(pos);
+		let token = token_at(tokens, pos);
 		match token {
 			Some(Token::UnaryOperator(Factorial))
 			| Some(Token::UnaryOperator(Percent))
@@ -353,7 +571,8 @@ pub fn parse_suffix(tokens: &[Token], pos: usize) -> Result<(AstNode, usize), St
 				// Here we are handling unary operators, aka stuff written as
 				// "Number Operator" (3!) instead of "Number Operator Number" (3+3).
 				// Therefore, if we find a match, we don't parse what comes after it.
-				let mut new_node = AstNode::new(token.unwrap().clone());
+				let span = Span::new(node.span.start, span_at(tokens, pos).end);
+				let mut new_node = AstNode::new(token.unwrap().clone(), span);
 				new_node.children.push(node);
 				node = new_node;
 				pos += 1;
@@ -368,70 +587,90 @@ pub fn parse_suffix(tokens: &[Token], pos: usize) -> Result<(AstNode, usize), St
 	}
 }
 
-/// Parse [`Number`](Token::Number), standalone [`Unit`](Token::Unit), [`Constant`](Token::Constant), [`FunctionIdentifier`](Token::FunctionIdentifier), [`Paren`](Token::Paren)
-pub fn parse_highest(tokens: &[Token], pos: usize) -> Result<(AstNode, usize), String> {
-	let token: &Token = tokens
-		.get(pos)
-		.ok_or(format!("Unexpected end of input at {}", pos))?;
+/// Parse [`Number`](Token::Number), standalone [`Unit`](Token::Unit), [`Constant`](Token::Constant), [`Date`](Token::Date), [`TimeZone`](Token::TimeZone), [`Identifier`](Token::Identifier), [`FunctionIdentifier`](Token::FunctionIdentifier), [`Paren`](Token::Paren), [`List`](Token::List)
+pub fn parse_highest(tokens: &[SpannedToken], pos: usize) -> Result<(AstNode, usize), Error> {
+	let token: &Token = token_at(tokens, pos).ok_or_else(|| unexpected(tokens, pos))?;
+	let span = span_at(tokens, pos);
 	match token {
 		Token::Number(_number) => {
-			let node = AstNode::new(token.clone());
+			let node = AstNode::new(token.clone(), span);
 			Ok((node, pos + 1))
 		}
 		Token::Unit(_unit) => {
-			let node = AstNode::new(token.clone());
+			let node = AstNode::new(token.clone(), span);
+			Ok((node, pos + 1))
+		}
+		Token::Format(_format) => {
+			let node = AstNode::new(token.clone(), span);
 			Ok((node, pos + 1))
 		}
 		Token::Constant(_constant) => {
-			let node = AstNode::new(token.clone());
+			let node = AstNode::new(token.clone(), span);
+			Ok((node, pos + 1))
+		}
+		Token::Date(_) | Token::Now | Token::Today | Token::Time(_) => {
+			let node = AstNode::new(token.clone(), span);
+			// a date or time in a time zone, like `3pm PST`
+			if let Some(zone @ Token::TimeZone(_)) = token_at(tokens, pos + 1) {
+				let span = Span::new(span.start, span_at(tokens, pos + 1).end);
+				let mut zone_node = AstNode::new(zone.clone(), span);
+				zone_node.children.push(node);
+				return Ok((zone_node, pos + 2));
+			}
+			Ok((node, pos + 1))
+		}
+		Token::TimeZone(_) => {
+			let node = AstNode::new(token.clone(), span);
+			Ok((node, pos + 1))
+		}
+		Token::Identifier(_name) => {
+			let mut node = AstNode::new(token.clone(), span);
+			// a call to a user-defined function, like 'f(2, 3)'
+			if let Some(&Token::Operator(LeftParen)) = token_at(tokens, pos + 1) {
+				let (arguments, next_pos) = parse_arguments(tokens, pos + 2, RightParen)?;
+				node.children = arguments;
+				node.span.end = span_at(tokens, next_pos - 1).end;
+				return Ok((node, next_pos));
+			}
 			Ok((node, pos + 1))
 		}
 		Token::FunctionIdentifier(_function_identifier) => {
 			let left_paren_pos = pos + 1;
-			let left_paren_token = tokens.get(left_paren_pos);
+			let left_paren_token = token_at(tokens, left_paren_pos);
 			// check if '(' comes after function identifier, like 'log('
 			match left_paren_token {
 				Some(&Token::Operator(LeftParen)) => {
-					// parse everything inside as you would with normal parentheses,
-					// then put it inside an ast node.
-					parse_text_operators(tokens, left_paren_pos + 1).and_then(|(node, next_pos)| {
-						if let Some(&Token::Operator(RightParen)) = tokens.get(next_pos) {
-							let mut function_node = AstNode::new(token.clone());
-							function_node.children.push(node);
-							Ok((function_node, next_pos + 1))
-						} else {
-							Err(format!(
-								"Expected closing paren at {} but found {:?}",
-								next_pos,
-								tokens.get(next_pos)
-							))
-						}
-					})
+					// parse each argument as you would with normal parentheses,
+					// then put them inside an ast node.
+					let (arguments, next_pos) =
+						parse_arguments(tokens, left_paren_pos + 1, RightParen)?;
+					let span = Span::new(span.start, span_at(tokens, next_pos - 1).end);
+					let mut function_node = AstNode::new(token.clone(), span);
+					function_node.children = arguments;
+					Ok((function_node, next_pos))
 				}
-				_ => Err(format!(
-					"Expected ( after {} at {:?} but found {:?}",
-					left_paren_pos, token, left_paren_token
-				)),
+				_ => Err(unexpected(tokens, left_paren_pos)),
 			}
 		}
+		Token::Operator(LeftBracket) => {
+			let mut list_node = AstNode::new(Token::List, span);
+			let (elements, next_pos) = parse_arguments(tokens, pos + 1, RightBracket)?;
+			list_node.children = elements;
+			list_node.span.end = span_at(tokens, next_pos - 1).end;
+			Ok((list_node, next_pos))
+		}
 		Token::Operator(LeftParen) => {
 			parse_text_operators(tokens, pos + 1).and_then(|(node, next_pos)| {
-				if let Some(&Token::Operator(RightParen)) = tokens.get(next_pos) {
-					let mut paren_node = AstNode::new(Token::Paren);
+				if let Some(&Token::Operator(RightParen)) = token_at(tokens, next_pos) {
+					let span = Span::new(span.start, span_at(tokens, next_pos).end);
+					let mut paren_node = AstNode::new(Token::Paren, span);
 					paren_node.children.push(node);
 					Ok((paren_node, next_pos + 1))
 				} else {
-					Err(format!(
-						"Expected closing paren at {} but found {:?}",
-						next_pos,
-						tokens.get(next_pos)
-					))
+					Err(unexpected(tokens, next_pos))
 				}
 			})
 		}
-		_ => Err(format!(
-			"Unexpected token {:?}, expected paren or number",
-			token
-		)),
+		_ => Err(unexpected(tokens, pos)),
 	}
 }
@@ -0,0 +1,257 @@
+use crate::evaluator::to_i128;
+use fastnum::{D128, decimal::RoundingMode};
+use std::fmt;
+
+/// A fraction of two integers, like `1/3`.
+///
+/// Numbers are calculated with fractions while their value stays rational, so
+/// `1/3 * 3` is exactly 1. Fractions are always in lowest terms, with a
+/// positive denominator. Operations that overflow return `None`, and the
+/// calculation falls back to decimals.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Rational {
+	numerator: i128,
+	denominator: i128,
+}
+
+/// The greatest common divisor of two integers
+fn gcd(a: i128, b: i128) -> u128 {
+	let (mut a, mut b) = (a.unsigned_abs(), b.unsigned_abs());
+	while b != 0 {
+		(a, b) = (b, a % b);
+	}
+	a
+}
+
+impl Rational {
+	/// A fraction in lowest terms, or `None` if the denominator is 0 or the
+	/// fraction doesn't fit
+	pub fn new(numerator: i128, denominator: i128) -> Option<Rational> {
+		if denominator == 0 {
+			return None;
+		}
+		let divisor = i128::try_from(gcd(numerator, denominator)).ok()?;
+		let (numerator, denominator) = (numerator / divisor, denominator / divisor);
+		match denominator < 0 {
+			true => Some(Rational {
+				numerator: numerator.checked_neg()?,
+				denominator: denominator.checked_neg()?,
+			}),
+			false => Some(Rational {
+				numerator,
+				denominator,
+			}),
+		}
+	}
+	pub fn from_integer(integer: i128) -> Rational {
+		Rational {
+			numerator: integer,
+			denominator: 1,
+		}
+	}
+	pub fn numerator(&self) -> i128 {
+		self.numerator
+	}
+	pub fn denominator(&self) -> i128 {
+		self.denominator
+	}
+	pub fn is_integer(&self) -> bool {
+		self.denominator == 1
+	}
+	/// The exact value of a decimal, or `None` if the decimal is inexact or
+	/// too large
+	pub fn from_decimal(value: D128) -> Option<Rational> {
+		if value.is_op_inexact() || !value.is_finite() {
+			return None;
+		}
+		let mut numerator = value.digits().to_i128().ok()?;
+		if value.is_sign_negative() {
+			numerator = -numerator;
+		}
+		let scale = value.fractional_digits_count();
+		let power = 10i128.checked_pow(u32::from(scale.unsigned_abs()))?;
+		match scale < 0 {
+			true => Some(Rational::from_integer(numerator.checked_mul(power)?)),
+			false => Rational::new(numerator, power),
+		}
+	}
+	/// The closest decimal to the fraction
+	pub fn to_decimal(self) -> D128 {
+		let numerator = D128::try_from(self.numerator).unwrap_or(D128::NAN);
+		let denominator = D128::try_from(self.denominator).unwrap_or(D128::NAN);
+		match self.is_integer() {
+			true => numerator,
+			false => numerator / denominator,
+		}
+	}
+	/// The closest fraction to a decimal with a denominator up to
+	/// `max_denominator`, found with continued fractions
+	pub fn approximate(value: D128, max_denominator: i128) -> Option<Rational> {
+		if !value.is_finite() {
+			return None;
+		}
+		let mut x = value.abs();
+		// the last two convergents, as (numerator, denominator)
+		let (mut previous, mut current) = ((0i128, 1i128), (1i128, 0i128));
+		for _ in 0..64 {
+			let whole = x.floor();
+			let integer = to_i128(whole)?;
+			let numerator = integer.checked_mul(current.0)?.checked_add(previous.0)?;
+			let denominator = integer.checked_mul(current.1)?.checked_add(previous.1)?;
+			if denominator > max_denominator {
+				break;
+			}
+			(previous, current) = (current, (numerator, denominator));
+			let fraction = x - whole;
+			if fraction.is_zero() {
+				break;
+			}
+			x = D128::ONE / fraction;
+		}
+		let numerator = match value.is_sign_negative() {
+			true => -current.0,
+			false => current.0,
+		};
+		Rational::new(numerator, current.1)
+	}
+	pub fn checked_add(self, other: Rational) -> Option<Rational> {
+		let divisor = i128::try_from(gcd(self.denominator, other.denominator)).ok()?;
+		let numerator = self
+			.numerator
+			.checked_mul(other.denominator / divisor)?
+			.checked_add(other.numerator.checked_mul(self.denominator / divisor)?)?;
+		let denominator = self.denominator.checked_mul(other.denominator / divisor)?;
+		Rational::new(numerator, denominator)
+	}
+	pub fn checked_sub(self, other: Rational) -> Option<Rational> {
+		self.checked_add(other.checked_neg()?)
+	}
+	pub fn checked_mul(self, other: Rational) -> Option<Rational> {
+		// cancel before multiplying, so less overflows
+		let a = i128::try_from(gcd(self.numerator, other.denominator))
+			.ok()?
+			.max(1);
+		let b = i128::try_from(gcd(other.numerator, self.denominator))
+			.ok()?
+			.max(1);
+		let numerator = (self.numerator / a).checked_mul(other.numerator / b)?;
+		let denominator = (self.denominator / b).checked_mul(other.denominator / a)?;
+		Rational::new(numerator, denominator)
+	}
+	/// Divide by another fraction, or return `None` if it's 0
+	pub fn checked_div(self, other: Rational) -> Option<Rational> {
+		self.checked_mul(Rational::new(other.denominator, other.numerator)?)
+	}
+	/// The remainder of a division rounded towards zero, like `%` on decimals
+	pub fn checked_rem(self, other: Rational) -> Option<Rational> {
+		let quotient = Rational::from_integer(self.checked_div(other)?.trunc());
+		self.checked_sub(other.checked_mul(quotient)?)
+	}
+	pub fn checked_neg(self) -> Option<Rational> {
+		Some(Rational {
+			numerator: self.numerator.checked_neg()?,
+			denominator: self.denominator,
+		})
+	}
+	pub fn checked_abs(self) -> Option<Rational> {
+		match self.numerator < 0 {
+			true => self.checked_neg(),
+			false => Some(self),
+		}
+	}
+	/// Raise to an integer power
+	pub fn checked_pow(self, exponent: i64) -> Option<Rational> {
+		let mut base = match exponent < 0 {
+			true => Rational::from_integer(1).checked_div(self)?,
+			false => self,
+		};
+		let mut exponent = exponent.unsigned_abs();
+		let mut result = Rational::from_integer(1);
+		while exponent > 0 {
+			if exponent & 1 == 1 {
+				result = result.checked_mul(base)?;
+			}
+			exponent >>= 1;
+			if exponent > 0 {
+				base = base.checked_mul(base)?;
+			}
+		}
+		Some(result)
+	}
+	/// Round towards zero
+	pub fn trunc(self) -> i128 {
+		self.numerator / self.denominator
+	}
+	pub fn floor(self) -> i128 {
+		self.numerator.div_euclid(self.denominator)
+	}
+	pub fn ceil(self) -> i128 {
+		self.floor() + i128::from(!self.is_integer())
+	}
+	/// Round to the closest integer, with halves rounded away from zero
+	pub fn round(self) -> Option<i128> {
+		let doubled = self.numerator.checked_mul(2)?;
+		let rounded = doubled.checked_add(self.denominator * doubled.signum())?;
+		Some(rounded / self.denominator.checked_mul(2)?)
+	}
+	/// Round to the closest multiple of `1/denominator`, with halves rounded
+	/// away from zero
+	pub fn round_to(self, denominator: i128) -> Option<Rational> {
+		let scaled = self.checked_mul(Rational::from_integer(denominator))?;
+		Rational::new(scaled.round()?, denominator)
+	}
+	/// Round a decimal to the closest multiple of `1/denominator`, with halves
+	/// rounded away from zero
+	pub fn round_decimal_to(value: D128, denominator: i128) -> Option<Rational> {
+		let scaled = value * D128::try_from(denominator).ok()?;
+		let rounded = scaled.with_rounding_mode(RoundingMode::HalfUp).round(0);
+		Rational::new(to_i128(rounded)?, denominator)
+	}
+}
+
+impl fmt::Display for Rational {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self.is_integer() {
+			true => write!(f, "{}", self.numerator),
+			false => write!(f, "{}/{}", self.numerator, self.denominator),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use fastnum::dec128 as d;
+
+	#[test]
+	fn test_rational() {
+		let fraction = |numerator, denominator| Rational::new(numerator, denominator).unwrap();
+		assert_eq!(fraction(6, -8), fraction(-3, 4));
+		assert_eq!(Rational::new(1, 0), None);
+		assert_eq!(Rational::from_decimal(d!(0.3125)), Some(fraction(5, 16)));
+		assert_eq!(Rational::from_decimal(d!(-1.5e3)), Some(fraction(-1500, 1)));
+		assert_eq!(Rational::from_decimal(d!(1) / d!(3)), None);
+		assert_eq!(
+			fraction(1, 3).checked_add(fraction(1, 6)),
+			Some(fraction(1, 2))
+		);
+		assert_eq!(
+			fraction(1, 3).checked_mul(fraction(3, 1)),
+			Some(fraction(1, 1))
+		);
+		assert_eq!(fraction(2, 3).checked_pow(-2), Some(fraction(9, 4)));
+		assert_eq!(fraction(i128::MAX, 1).checked_add(fraction(1, 1)), None);
+		assert_eq!(
+			fraction(-7, 2).checked_rem(fraction(2, 1)),
+			Some(fraction(-3, 2))
+		);
+		assert_eq!((fraction(-7, 2).floor(), fraction(-7, 2).ceil()), (-4, -3));
+		assert_eq!(fraction(-5, 2).round(), Some(-3));
+		assert_eq!(fraction(3, 10).round_to(16), Some(fraction(5, 16)));
+		assert_eq!(
+			Rational::approximate(d!(3.14159265358979), 1000),
+			Some(fraction(355, 113))
+		);
+		assert_eq!(fraction(-3, 4).to_string(), "-3/4");
+	}
+}
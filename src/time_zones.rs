@@ -0,0 +1,307 @@
+use crate::units::Ambiguity;
+use jiff::Timestamp;
+use jiff::tz::{self, TimeZone};
+
+/// Cities and the time zones they're in
+const CITIES: &[(&str, &str)] = &[
+	("amsterdam", "Europe/Amsterdam"),
+	("anchorage", "America/Anchorage"),
+	("athens", "Europe/Athens"),
+	("atlanta", "America/New_York"),
+	("auckland", "Pacific/Auckland"),
+	("bangalore", "Asia/Kolkata"),
+	("bangkok", "Asia/Bangkok"),
+	("barcelona", "Europe/Madrid"),
+	("beijing", "Asia/Shanghai"),
+	("berlin", "Europe/Berlin"),
+	("bogota", "America/Bogota"),
+	("boston", "America/New_York"),
+	("brussels", "Europe/Brussels"),
+	("buenos aires", "America/Argentina/Buenos_Aires"),
+	("cairo", "Africa/Cairo"),
+	("cape town", "Africa/Johannesburg"),
+	("chicago", "America/Chicago"),
+	("copenhagen", "Europe/Copenhagen"),
+	("dallas", "America/Chicago"),
+	("delhi", "Asia/Kolkata"),
+	("denver", "America/Denver"),
+	("dubai", "Asia/Dubai"),
+	("dublin", "Europe/Dublin"),
+	("frankfurt", "Europe/Berlin"),
+	("geneva", "Europe/Zurich"),
+	("helsinki", "Europe/Helsinki"),
+	("hong kong", "Asia/Hong_Kong"),
+	("honolulu", "Pacific/Honolulu"),
+	("houston", "America/Chicago"),
+	("istanbul", "Europe/Istanbul"),
+	("jakarta", "Asia/Jakarta"),
+	("jerusalem", "Asia/Jerusalem"),
+	("johannesburg", "Africa/Johannesburg"),
+	("karachi", "Asia/Karachi"),
+	("kyiv", "Europe/Kyiv"),
+	("lagos", "Africa/Lagos"),
+	("lima", "America/Lima"),
+	("lisbon", "Europe/Lisbon"),
+	("london", "Europe/London"),
+	("los angeles", "America/Los_Angeles"),
+	("madrid", "Europe/Madrid"),
+	("manila", "Asia/Manila"),
+	("melbourne", "Australia/Melbourne"),
+	("mexico city", "America/Mexico_City"),
+	("miami", "America/New_York"),
+	("milan", "Europe/Rome"),
+	("montreal", "America/Toronto"),
+	("moscow", "Europe/Moscow"),
+	("mumbai", "Asia/Kolkata"),
+	("munich", "Europe/Berlin"),
+	("nairobi", "Africa/Nairobi"),
+	("new delhi", "Asia/Kolkata"),
+	("new york", "America/New_York"),
+	("oslo", "Europe/Oslo"),
+	("paris", "Europe/Paris"),
+	("perth", "Australia/Perth"),
+	("phoenix", "America/Phoenix"),
+	("prague", "Europe/Prague"),
+	("reykjavik", "Atlantic/Reykjavik"),
+	("rio de janeiro", "America/Sao_Paulo"),
+	("riyadh", "Asia/Riyadh"),
+	("rome", "Europe/Rome"),
+	("san francisco", "America/Los_Angeles"),
+	("santiago", "America/Santiago"),
+	("sao paulo", "America/Sao_Paulo"),
+	("seattle", "America/Los_Angeles"),
+	("seoul", "Asia/Seoul"),
+	("shanghai", "Asia/Shanghai"),
+	("singapore", "Asia/Singapore"),
+	("stockholm", "Europe/Stockholm"),
+	("sydney", "Australia/Sydney"),
+	("taipei", "Asia/Taipei"),
+	("tehran", "Asia/Tehran"),
+	("tel aviv", "Asia/Jerusalem"),
+	("tokyo", "Asia/Tokyo"),
+	("toronto", "America/Toronto"),
+	("vancouver", "America/Vancouver"),
+	("vienna", "Europe/Vienna"),
+	("warsaw", "Europe/Warsaw"),
+	("washington", "America/New_York"),
+	("wellington", "Pacific/Auckland"),
+	("zurich", "Europe/Zurich"),
+];
+
+/// Time zone abbreviations that mean the same offset everywhere, as POSIX time
+/// zones. An abbreviation is either standard or daylight saving time, so `EST`
+/// is 5 hours behind UTC even in the summer, when New York is on `EDT`.
+const ABBREVIATIONS: &[(&str, &str)] = &[
+	("utc", "UTC0"),
+	("gmt", "GMT0"),
+	("pst", "PST8"),
+	("pdt", "PDT7"),
+	("mst", "MST7"),
+	("mdt", "MDT6"),
+	("cdt", "CDT5"),
+	("edt", "EDT4"),
+	("akst", "AKST9"),
+	("akdt", "AKDT8"),
+	("hst", "HST10"),
+	("adt", "ADT3"),
+	("nst", "NST3:30"),
+	("ndt", "NDT2:30"),
+	("brt", "BRT3"),
+	("art", "ART3"),
+	("wet", "WET0"),
+	("west", "WEST-1"),
+	("cet", "CET-1"),
+	("cest", "CEST-2"),
+	("eet", "EET-2"),
+	("eest", "EEST-3"),
+	("msk", "MSK-3"),
+	("wat", "WAT-1"),
+	("cat", "CAT-2"),
+	("eat", "EAT-3"),
+	("sast", "SAST-2"),
+	("pkt", "PKT-5"),
+	("ict", "ICT-7"),
+	("wib", "WIB-7"),
+	("hkt", "HKT-8"),
+	("sgt", "SGT-8"),
+	("pht", "PHT-8"),
+	("jst", "JST-9"),
+	("kst", "KST-9"),
+	("awst", "AWST-8"),
+	("acst", "ACST-9:30"),
+	("acdt", "ACDT-10:30"),
+	("aest", "AEST-10"),
+	("aedt", "AEDT-11"),
+	("nzst", "NZST-12"),
+	("nzdt", "NZDT-13"),
+];
+
+/// Abbreviations that mean different time zones in different places, like
+/// `IST`, as POSIX time zones. Like the ambiguous unit `degree`, the region of
+/// the locale decides which one is meant.
+fn ambiguous_abbreviation(abbreviation: &str, region: &str) -> Option<Ambiguity<&'static str>> {
+	let ambiguity = match abbreviation {
+		"cst" => Ambiguity {
+			string: "CST",
+			candidates: &["CST6", "CST-8", "CST5"],
+			fallback: match region {
+				"CN" | "TW" => &"CST-8",
+				"CU" => &"CST5",
+				_ => &"CST6",
+			},
+		},
+		"est" => Ambiguity {
+			string: "EST",
+			candidates: &["EST5", "EST-10"],
+			fallback: match region {
+				"AU" => &"EST-10",
+				_ => &"EST5",
+			},
+		},
+		"ist" => Ambiguity {
+			string: "IST",
+			candidates: &["IST-5:30", "IST-1", "IST-2"],
+			fallback: match region {
+				"IE" => &"IST-1",
+				"IL" => &"IST-2",
+				_ => &"IST-5:30",
+			},
+		},
+		"bst" => Ambiguity {
+			string: "BST",
+			candidates: &["BST-1", "BST-6"],
+			fallback: match region {
+				"BD" => &"BST-6",
+				_ => &"BST-1",
+			},
+		},
+		"ast" => Ambiguity {
+			string: "AST",
+			candidates: &["AST4", "AST-3"],
+			fallback: match region {
+				"BH" | "IQ" | "KW" | "QA" | "SA" | "YE" => &"AST-3",
+				_ => &"AST4",
+			},
+		},
+		"gst" => Ambiguity {
+			string: "GST",
+			candidates: &["GST-4", "GST2"],
+			fallback: match region {
+				"GS" => &"GST2",
+				_ => &"GST-4",
+			},
+		},
+		"sst" => Ambiguity {
+			string: "SST",
+			candidates: &["SST11", "SST-8"],
+			fallback: match region {
+				"SG" => &"SST-8",
+				_ => &"SST11",
+			},
+		},
+		_ => return None,
+	};
+	Some(ambiguity)
+}
+
+/// Read a time zone at the start of the input: an IANA name like
+/// `Europe/Oslo`, a city like `New York`, or an abbreviation like `PST`.
+///
+/// Returns the time zone and the length of it in bytes.
+pub(crate) fn parse_time_zone(input: &str, region: &str) -> Option<(TimeZone, usize)> {
+	let name_length = input
+		.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '/'))
+		.unwrap_or(input.len());
+	let name = &input[..name_length];
+	if name.contains('/') {
+		return Some((tz::db().get(name).ok()?, name_length));
+	}
+
+	// the longest city, so `New York` isn't read as a shorter city
+	let city = CITIES
+		.iter()
+		.filter_map(|(city, zone)| Some((read_words(input, city)?, zone)))
+		.max_by_key(|(length, _)| *length);
+	if let Some((length, zone)) = city {
+		return Some((tz::db().get(zone).ok()?, length));
+	}
+
+	let abbreviation = name.to_ascii_lowercase();
+	let zone = ABBREVIATIONS
+		.iter()
+		.find(|(candidate, _)| *candidate == abbreviation)
+		.map(|(_, zone)| *zone)
+		// nothing else in the input hints at a time zone, so it's the one for
+		// the region
+		.or_else(|| Some(*ambiguous_abbreviation(&abbreviation, region)?.fallback))?;
+	Some((TimeZone::posix(zone).ok()?, name_length))
+}
+
+/// The name of a time zone, like `Europe/Oslo`, or `EST` for an abbreviation
+pub(crate) fn zone_name(zone: &TimeZone) -> String {
+	match zone.iana_name() {
+		Some(name) => name.to_string(),
+		None => zone
+			.to_offset_info(Timestamp::UNIX_EPOCH)
+			.abbreviation()
+			.to_string(),
+	}
+}
+
+/// The length of `words` at the start of the input, ignoring case and the
+/// amount of space between the words
+fn read_words(input: &str, words: &str) -> Option<usize> {
+	let mut rest = input;
+	for (i, word) in words.split(' ').enumerate() {
+		if i > 0 {
+			let trimmed = rest.trim_start_matches(' ');
+			if trimmed.len() == rest.len() {
+				return None;
+			}
+			rest = trimmed;
+		}
+		let prefix = rest.get(..word.len())?;
+		if !prefix.eq_ignore_ascii_case(word) {
+			return None;
+		}
+		rest = &rest[word.len()..];
+	}
+	if rest.starts_with(|c: char| c.is_alphanumeric() || c == '_') {
+		return None;
+	}
+	Some(input.len() - rest.len())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn parse(input: &str, region: &str) -> Option<(String, usize)> {
+		parse_time_zone(input, region).map(|(zone, length)| (zone_name(&zone), length))
+	}
+
+	#[test]
+	fn test_parse_time_zone() {
+		assert_eq!(
+			parse("Europe/Oslo + 1", ""),
+			Some(("Europe/Oslo".to_string(), 11))
+		);
+		assert_eq!(parse("Europe/Atlantis", ""), None);
+		assert_eq!(
+			parse("new  York", ""),
+			Some(("America/New_York".to_string(), 9))
+		);
+		assert_eq!(parse("newark", ""), None);
+		assert_eq!(parse("PST to CET", ""), Some(("PST".to_string(), 3)));
+		let offset = |input, region| {
+			let (zone, _) = parse_time_zone(input, region).unwrap();
+			zone.to_offset(Timestamp::UNIX_EPOCH).seconds() / 60
+		};
+		assert_eq!(offset("IST", "IN"), 330);
+		assert_eq!(offset("ist", "IE"), 60);
+		assert_eq!(offset("CST", "TW"), 480);
+		assert_eq!(offset("CST", ""), -360);
+		assert_eq!(offset("NST", ""), -210);
+		assert_eq!(parse("pst2", ""), None);
+	}
+}
@@ -1,7 +1,11 @@
-use crate::Number;
-use crate::currency;
+use crate::error::Error;
+use crate::rational::Rational;
+use crate::{Context, Number};
 use fastnum::{D128, dec128 as d};
 use std::cmp::Reverse;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
 
 #[derive(Clone, Copy, PartialEq, Debug)]
 /// An enum of all possible unit types, like [`Length`], [`DigitalStorage`] etc.
@@ -42,15 +46,21 @@ pub enum UnitType {
 	Frequency,
 	/// A unit of x, for example [`KilometersPerHour`]
 	Speed,
+	/// A unit of angle, for example [`Degree`] or [`Radian`]
+	Angle,
 	/// A unit of temperature, for example [`Kelvin`]
 	Temperature,
 	/// A unit of currency, for example [`EUR`]
 	Currency,
+	/// A unit defined with [`Context::define_unit`] that isn't any of the other types,
+	/// like milliampere hours
+	Custom,
 }
 impl UnitType {
 	fn primitive(&self) -> Vec<(Unit, isize)> {
 		let units = match self {
 			UnitType::Unknown => panic!("Unknown unit type for ambiguous unit"),
+			UnitType::Custom => panic!("Custom units have their own primitive unit"),
 			Time => vec![(Second, 1)],
 			Length => vec![(Meter, 1)],
 			Area => vec![(Meter, 2)],
@@ -68,6 +78,7 @@ impl UnitType {
 			Pressure => vec![(Kilogram, 1), (Second, -2), (Meter, -1)],
 			Frequency => vec![(Second, -1)],
 			Speed => vec![(Meter, 1), (Second, -1)],
+			Angle => vec![(Arcsecond, 1)],
 			Temperature => vec![(Kelvin, 1)],
 			Currency => vec![(EUR, 1)],
 		};
@@ -83,20 +94,35 @@ impl UnitType {
 }
 use UnitType::*;
 
+/// A word that means different things in different places, like `degree`,
+/// which is an angle or a temperature, or the time zone `IST`
 #[derive(Clone, Copy, PartialEq, Debug, Eq, PartialOrd, Ord, Hash)]
-pub struct Ambiguity {
+pub struct Ambiguity<T: 'static = Unit> {
 	pub string: &'static str,
-	pub candidates: &'static [Unit],
-	pub fallback: &'static Unit,
+	pub candidates: &'static [T],
+	/// What the word means in the region of the locale, if nothing hints at
+	/// one of the candidates
+	pub fallback: &'static T,
+}
+impl<T: Clone> Ambiguity<T> {
+	/// The candidate that matches a hint, like the angle for `degree` in
+	/// `sin(30 degrees)`, or else the fallback
+	pub fn resolve(&self, matches_hint: impl Fn(&T) -> bool) -> T {
+		self.candidates
+			.iter()
+			.find(|candidate| matches_hint(candidate))
+			.unwrap_or(self.fallback)
+			.clone()
+	}
 }
 
 /// Sort for display and comparison purposes.
-pub fn sort_units(primitives: &mut Vec<(Unit, isize)>) {
+pub fn sort_units(primitives: &mut [(Unit, isize)]) {
 	primitives.sort_by_key(|u| {
 		(
 			u.1 < 0,            // multiplications first
 			Reverse(u.1.abs()), // largest first, like "sqm seconds"
-			u.0,                // then sort by unit, to have a fully deterministic order
+			u.0.clone(),        // then sort by unit, to have a fully deterministic order
 		)
 	});
 }
@@ -104,7 +130,7 @@ pub fn sort_units(primitives: &mut Vec<(Unit, isize)>) {
 pub fn primitive_unit(unit: &[(Unit, isize)]) -> Vec<(Unit, isize)> {
 	let mut primitives: Vec<(Unit, isize)> = Vec::new();
 	for (unit, exponent) in unit {
-		for (primitive, primitive_exponent) in unit.category().primitive() {
+		for (primitive, primitive_exponent) in unit.primitive() {
 			let existing = primitives.iter_mut().find(|(u, _)| u == &primitive);
 			match existing {
 				Some(existing) => existing.1 += primitive_exponent * exponent,
@@ -120,7 +146,12 @@ pub fn primitive_unit(unit: &[(Unit, isize)]) -> Vec<(Unit, isize)> {
 fn reduce_unit(number: Number) -> Number {
 	let mut new_unit: Vec<(Unit, isize)> = Vec::new();
 	for (unit, exponent) in &number.unit {
-		if unit.category() == UnitType::Temperature {
+		// temperatures can't be combined, currencies would need exchange rates, and
+		// custom units of the custom type don't have to be compatible
+		if matches!(
+			unit.category(),
+			UnitType::Temperature | UnitType::Currency | UnitType::Custom
+		) {
 			new_unit.push((unit.clone(), *exponent));
 			continue;
 		}
@@ -133,18 +164,19 @@ fn reduce_unit(number: Number) -> Number {
 			new_unit.push((unit.clone(), *exponent))
 		}
 	}
-	convert(number.clone(), new_unit).unwrap_or(number)
+	convert(number.clone(), new_unit, &Context::default()).unwrap_or(number)
 }
 
 // Macro for creating units. Not possible to extend/change the default units
 // with this because the default units are imported into the lexer, parser
-// and evaluator
+// and evaluator. Units can be added at runtime with `Context::define_unit` instead.
 macro_rules! create_units {
 	( $( $variant:ident : $properties:expr ),*, ) => {
-		#[derive(Clone, Copy, PartialEq, Debug, Eq, PartialOrd, Ord, Hash)]
+		#[derive(Clone, PartialEq, Debug, Eq, PartialOrd, Ord, Hash)]
 		/// A Unit enum. Note that it can also be [`NoUnit`].
 		pub enum Unit {
 			Ambiguity(Ambiguity),
+			Custom(CustomUnit),
 			$($variant),*
 		}
 		use Unit::*;
@@ -153,6 +185,7 @@ macro_rules! create_units {
 			pub fn category(&self) -> UnitType {
 				match self {
 					Unit::Ambiguity(_) => UnitType::Unknown,
+					Unit::Custom(custom) => custom.0.category,
 					$(
 						Unit::$variant => $properties.0
 					),*
@@ -161,14 +194,26 @@ macro_rules! create_units {
 			pub fn weight(&self) -> D128 {
 				match self {
 					Unit::Ambiguity(ambiguity) => panic!("Ambiguous unit {}", ambiguity.string),
+					Unit::Custom(custom) => custom.0.weight,
 					$(
-						Unit::$variant => $properties.1
+						Unit::$variant => Weight::from($properties.1).decimal()
+					),*
+				}
+			}
+			/// The weight as a fraction, if it's rational
+			pub fn rational_weight(&self) -> Option<Rational> {
+				match self {
+					Unit::Ambiguity(ambiguity) => panic!("Ambiguous unit {}", ambiguity.string),
+					Unit::Custom(custom) => custom.0.rational_weight,
+					$(
+						Unit::$variant => Weight::from($properties.1).rational()
 					),*
 				}
 			}
 			pub(crate) fn singular(&self) -> &str {
 				match self {
 					Unit::Ambiguity(ambiguity) => panic!("Ambiguous unit {}", ambiguity.string),
+					Unit::Custom(custom) => custom.name(),
 					$(
 						Unit::$variant => $properties.2
 					),*
@@ -177,6 +222,7 @@ macro_rules! create_units {
 			pub(crate) fn plural(&self) -> &str {
 				match self {
 					Unit::Ambiguity(ambiguity) => panic!("Ambiguous unit {}", ambiguity.string),
+					Unit::Custom(custom) => &custom.0.plural,
 					$(
 						Unit::$variant => $properties.3
 					),*
@@ -186,9 +232,26 @@ macro_rules! create_units {
 	}
 }
 
+/// The weight of a unit, which is a decimal or a ratio of two decimals
+struct Weight(D128, D128);
+impl From<D128> for Weight {
+	fn from(weight: D128) -> Weight {
+		Weight(weight, d!(1))
+	}
+}
+impl Weight {
+	fn decimal(&self) -> D128 {
+		self.0 / self.1
+	}
+	fn rational(&self) -> Option<Rational> {
+		Rational::from_decimal(self.0)?.checked_div(Rational::from_decimal(self.1)?)
+	}
+}
+
+/// A weight that's inexact as a decimal, but exact as a fraction
 macro_rules! inexact {
 	($a:tt / $b:tt) => {
-		d!($a) / d!($b)
+		Weight(d!($a), d!($b))
 	};
 }
 create_units!(
@@ -430,6 +493,14 @@ create_units!(
 	FeetPerSecond:      (Speed, d!(0.3048), "foot per second", "feet per second"),
 	Knot:               (Speed, inexact!(463 / 900), "knot", "knots"),
 
+	// Arcseconds are the primitive angle unit so that most conversions are exact
+	Radian:             (Angle, d!(648000) / D128::PI, "radian", "radians"),
+	Degree:             (Angle, d!(3600), "degree", "degrees"),
+	Gradian:            (Angle, d!(3240), "gradian", "gradians"),
+	Arcminute:          (Angle, d!(60), "arcminute", "arcminutes"),
+	Arcsecond:          (Angle, d!(1), "arcsecond", "arcseconds"),
+	Turn:               (Angle, d!(1296000), "turn", "turns"),
+
 	Kelvin:             (Temperature, d!(0), "kelvin", "kelvin"),
 	Celsius:            (Temperature, d!(0), "celsius", "celsius"),
 	Fahrenheit:         (Temperature, d!(0), "fahrenheit", "fahrenheit"),
@@ -601,12 +672,201 @@ create_units!(
 	ZWG: (Currency, d!(0), "ZWG", "ZWG"),
 );
 
+impl Unit {
+	/// The unit in terms of primitive units, like `[(Meter, 1), (Second, -1)]` for speeds
+	fn primitive(&self) -> Vec<(Unit, isize)> {
+		match self {
+			Unit::Custom(custom) => custom.0.primitive.clone(),
+			_ => self.category().primitive(),
+		}
+	}
+}
+
+/// A unit defined at runtime with [`Context::define_unit`]. Units are the same
+/// if they come from the same definition.
+#[derive(Clone)]
+pub struct CustomUnit(Arc<CustomUnitDefinition>);
+impl PartialEq for CustomUnit {
+	fn eq(&self, other: &Self) -> bool {
+		Arc::ptr_eq(&self.0, &other.0)
+	}
+}
+impl Eq for CustomUnit {}
+impl PartialOrd for CustomUnit {
+	fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+		Some(self.cmp(other))
+	}
+}
+impl Ord for CustomUnit {
+	fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+		self.name()
+			.cmp(other.name())
+			.then_with(|| Arc::as_ptr(&self.0).cmp(&Arc::as_ptr(&other.0)))
+	}
+}
+impl Hash for CustomUnit {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		Arc::as_ptr(&self.0).hash(state);
+	}
+}
+impl fmt::Debug for CustomUnit {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_tuple("CustomUnit").field(&self.name()).finish()
+	}
+}
+impl CustomUnit {
+	/// The singular name of the unit
+	pub fn name(&self) -> &str {
+		&self.0.names[0]
+	}
+	/// Whether the name, plural or one of the aliases of the unit matches
+	fn is_written_as(&self, matches: impl Fn(&str) -> bool) -> bool {
+		self.0.names.iter().any(|name| matches(name)) || matches(&self.0.plural)
+	}
+}
+
+struct CustomUnitDefinition {
+	/// Words the unit can be written as, starting with the singular name
+	names: Vec<String>,
+	plural: String,
+	category: UnitType,
+	primitive: Vec<(Unit, isize)>,
+	weight: D128,
+	rational_weight: Option<Rational>,
+}
+
+/// Unit types a custom unit can belong to, besides [`UnitType::Custom`]
+const CUSTOM_UNIT_TYPES: [UnitType; 18] = [
+	Time,
+	Length,
+	Area,
+	Volume,
+	Mass,
+	DigitalStorage,
+	DataTransferRate,
+	FlopCount,
+	FlopRate,
+	Energy,
+	Power,
+	ElectricCurrent,
+	Resistance,
+	Voltage,
+	Pressure,
+	Frequency,
+	Speed,
+	Angle,
+];
+
+/// Find the custom unit written as `word`. Exact matches take priority over
+/// case-insensitive ones.
+pub(crate) fn find_custom_unit(word: &str, custom_units: &[CustomUnit]) -> Option<Unit> {
+	let find = |matches: &dyn Fn(&str) -> bool| {
+		custom_units
+			.iter()
+			.find(|custom| custom.is_written_as(matches))
+			.map(|custom| Unit::Custom(custom.clone()))
+	};
+	find(&|name| name == word).or_else(|| find(&|name| name.eq_ignore_ascii_case(word)))
+}
+
+/// Define a unit in the context. See [`Context::define_unit`]. The plural is
+/// used instead of the default one if it's given.
+pub(crate) fn define_unit(
+	context: &mut Context,
+	name: &str,
+	plural: Option<&str>,
+	aliases: &[&str],
+	definition: Number,
+) -> Result<Unit, Error> {
+	let existing = context
+		.custom_units
+		.iter()
+		.position(|custom| custom.name() == name);
+	let plural = match plural {
+		Some(plural) => plural.to_string(),
+		None if name.chars().any(|c| c.is_ascii_uppercase()) => name.to_string(),
+		None => format!("{name}s"),
+	};
+	for word in [name, &plural].into_iter().chain(aliases.iter().copied()) {
+		let tokens = crate::lexer::lex(word, false, context);
+		let is_free = match tokens.as_deref() {
+			Ok([token]) => match &token.token {
+				crate::Token::Identifier(_) => true,
+				crate::Token::Unit(units) => match (&units[0].0, existing) {
+					(Unit::Custom(custom), Some(index)) => *custom == context.custom_units[index],
+					_ => false,
+				},
+				_ => false,
+			},
+			_ => false,
+		};
+		if !crate::lexer::is_identifier(word) || !is_free {
+			return Err(Error::invalid_definition(format!(
+				"\"{word}\" can't be used as a unit name"
+			)));
+		}
+	}
+	let unsupported = definition.unit.iter().find(|(unit, _)| {
+		matches!(
+			unit.category(),
+			UnitType::Unknown | UnitType::Temperature | UnitType::Currency
+		)
+	});
+	if unsupported.is_some() {
+		return Err(Error::invalid_definition(
+			"Units can't be defined from temperatures, currencies or ambiguous units",
+		));
+	}
+	let weight = definition.value * combined_weight(&definition.unit);
+	let rational_weight = definition
+		.rational
+		.zip(combined_rational_weight(&definition.unit))
+		.and_then(|(value, unit_weight)| value.checked_mul(unit_weight));
+	if weight <= d!(0) {
+		return Err(Error::invalid_definition("Units must have a positive size"));
+	}
+	let primitive = definition.primitive_unit();
+	let category = CUSTOM_UNIT_TYPES
+		.into_iter()
+		.find(|unit_type| unit_type.primitive() == primitive)
+		.unwrap_or(UnitType::Custom);
+
+	let custom = CustomUnit(Arc::new(CustomUnitDefinition {
+		names: [name]
+			.iter()
+			.chain(aliases)
+			.map(|s| s.to_string())
+			.collect(),
+		plural,
+		category,
+		primitive,
+		weight,
+		rational_weight,
+	}));
+	match existing {
+		Some(index) => context.custom_units[index] = custom.clone(),
+		None => context.custom_units.push(custom.clone()),
+	}
+	Ok(Unit::Custom(custom))
+}
+
 fn combined_weight(unit: &[(Unit, isize)]) -> D128 {
 	unit.iter().fold(D128::from(1), |acc, (u, exp)| {
 		acc * integer_power(u.weight(), *exp)
 	})
 }
 
+/// Like [`combined_weight`], but as a fraction if the weights are rational
+fn combined_rational_weight(unit: &[(Unit, isize)]) -> Option<Rational> {
+	unit.iter()
+		.try_fold(Rational::from_integer(1), |acc, (u, exp)| {
+			acc.checked_mul(
+				u.rational_weight()?
+					.checked_pow(i64::try_from(*exp).ok()?)?,
+			)
+		})
+}
+
 fn integer_power(base: D128, exp: isize) -> D128 {
 	let positive = (0..exp.unsigned_abs()).fold(D128::from(1), |acc, _| acc * base);
 	if exp >= 0 {
@@ -633,16 +893,18 @@ fn non_currency_weight(unit: &[(Unit, isize)]) -> D128 {
 }
 
 /// Convert a [`Number`] to a specified [`Unit`].
-pub fn convert(number: Number, to_unit: Vec<(Unit, isize)>) -> Result<Number, String> {
+///
+/// Currency conversions use the exchange rates in the [`Context`].
+pub fn convert(
+	number: Number,
+	to_unit: Vec<(Unit, isize)>,
+	context: &Context,
+) -> Result<Number, Error> {
 	if number.unit == to_unit {
 		return Ok(number);
 	}
 	if number.primitive_unit() != primitive_unit(&to_unit) {
-		return Err(format!(
-			"Cannot convert {} to {}",
-			number,
-			Number::with_unit(d!(0), to_unit).plural()
-		));
+		return Err(Error::incompatible_units(&number.unit, &to_unit));
 	}
 	let value = number.value;
 	if number.primitive_unit() == Temperature.primitive() {
@@ -651,14 +913,10 @@ pub fn convert(number: Number, to_unit: Vec<(Unit, isize)>) -> Result<Number, St
 			|| number.unit[0].1 != 1
 			|| to_unit[0].1 != 1
 		{
-			return Err(format!(
-				"Cannot convert {} to {}",
-				number,
-				Number::with_unit(d!(0), to_unit).plural()
-			));
+			return Err(Error::incompatible_units(&number.unit, &to_unit));
 		}
 		let ok = |new_value| Ok(Number::with_unit(new_value, to_unit.clone()));
-		match (number.unit[0].0, to_unit[0].0) {
+		match (&number.unit[0].0, &to_unit[0].0) {
 			(Kelvin, Kelvin) => ok(value),
 			(Kelvin, Celsius) => ok(value - d!(273.15)),
 			(Kelvin, Fahrenheit) => ok(value * d!(1.8) - d!(459.67)),
@@ -668,11 +926,7 @@ pub fn convert(number: Number, to_unit: Vec<(Unit, isize)>) -> Result<Number, St
 			(Fahrenheit, Fahrenheit) => ok(value),
 			(Fahrenheit, Kelvin) => ok((value + d!(459.67)) * d!(5) / d!(9)),
 			(Fahrenheit, Celsius) => ok((value - d!(32)) / d!(1.8)),
-			_ => Err(format!(
-				"Error converting temperature {} to {}",
-				number,
-				Number::with_unit(d!(0), to_unit).plural()
-			)),
+			_ => Err(Error::incompatible_units(&number.unit, &to_unit)),
 		}
 	} else if number.contains_category(Currency) && contains_category(&to_unit, Currency) {
 		// Handle compound units with currency, like "EUR/liter"
@@ -681,14 +935,14 @@ pub fn convert(number: Number, to_unit: Vec<(Unit, isize)>) -> Result<Number, St
 			.unit
 			.iter()
 			.find(|(u, _)| u.category() == Currency)
-			.map(|(u, _)| *u);
+			.map(|(u, _)| u.clone());
 		let to_currency = to_unit
 			.iter()
 			.find(|(u, _)| u.category() == Currency)
-			.map(|(u, _)| *u);
+			.map(|(u, _)| u.clone());
 
 		if let (Some(from_curr), Some(to_curr)) = (from_currency, to_currency) {
-			let rate = currency::get_exchange_rate(from_curr, to_curr)?;
+			let rate = context.exchange_rate(from_curr, to_curr)?;
 
 			// Calculate the ratio of non-currency parts
 			let source_non_currency = non_currency_weight(&number.unit);
@@ -696,64 +950,134 @@ pub fn convert(number: Number, to_unit: Vec<(Unit, isize)>) -> Result<Number, St
 
 			let value = number.value * rate * source_non_currency / target_non_currency;
 
-			Ok(Number {
-				value,
-				unit: to_unit.to_vec(),
-			})
+			Ok(Number::with_unit(value, to_unit.to_vec()))
 		} else {
-			Err("Currency conversion requires both units to have currency".to_string())
+			Err(Error::incompatible_units(&number.unit, &to_unit))
 		}
 	} else {
 		let source_weight = combined_weight(&number.unit);
 		let target_weight = combined_weight(&to_unit);
-
-		Ok(Number {
-			value: number.value * source_weight / target_weight,
-			unit: to_unit.to_vec(),
-		})
+		let ratio = combined_rational_weight(&number.unit)
+			.zip(combined_rational_weight(&to_unit))
+			.and_then(|(source, target)| source.checked_div(target));
+
+		Ok(number.map(
+			|value| value * source_weight / target_weight,
+			|value| value.checked_mul(ratio?),
+			to_unit,
+		))
 	}
 }
 
 /// If one of two provided [`Number`]s has a larger [`Unit`] than the other, convert
 /// the large one to the unit of the small one.
-pub fn convert_to_lowest(left: Number, right: Number) -> Result<(Number, Number), String> {
+pub fn convert_to_lowest(
+	left: Number,
+	right: Number,
+	context: &Context,
+) -> Result<(Number, Number), Error> {
 	assert!(left.primitive_unit() == right.primitive_unit());
-	if combined_weight(&left.unit) == combined_weight(&right.unit) {
+	if left.contains_category(Currency) && left.unit != right.unit {
+		// currencies don't have a size, so use the left one
+		let right_converted = convert(right, left.unit.clone(), context)?;
+		Ok((left, right_converted))
+	} else if combined_weight(&left.unit) == combined_weight(&right.unit) {
 		Ok((left, right))
 	} else if combined_weight(&left.unit) > combined_weight(&right.unit) {
-		let left_converted = convert(left, right.unit.clone())?;
+		let left_converted = convert(left, right.unit.clone(), context)?;
 		Ok((left_converted, right))
 	} else {
-		let right_converted = convert(right, left.unit.clone())?;
+		let right_converted = convert(right, left.unit.clone(), context)?;
 		Ok((left, right_converted))
 	}
 }
 
+/// Split a [`Number`] into several units of the same type, like 1.68 m into
+/// 5 feet and 6.14 inches.
+///
+/// The units are used from largest to smallest. Every unit but the smallest
+/// gets a whole number, and the remainder goes in the smallest unit.
+pub fn split_into_units(
+	number: Number,
+	units: &[Unit],
+	context: &Context,
+) -> Result<Vec<Number>, Error> {
+	let mut units = units.to_vec();
+	units.sort_by(|a, b| b.weight().cmp(&a.weight()));
+	let negative = number.value.is_sign_negative();
+	let mut remaining = number.map(
+		|value| value.abs(),
+		Rational::checked_abs,
+		number.unit.clone(),
+	);
+	let mut parts = Vec::new();
+	for (index, unit) in units.iter().enumerate() {
+		let converted = convert(remaining, vec![(unit.clone(), 1)], context)?;
+		if index == units.len() - 1 {
+			parts.push(converted);
+			break;
+		}
+		let whole = converted.map(
+			|value| value.floor(),
+			|value| Some(Rational::from_integer(value.floor())),
+			converted.unit.clone(),
+		);
+		remaining = subtract(converted, whole.clone(), context)?;
+		parts.push(whole);
+	}
+	if negative {
+		for part in &mut parts {
+			*part = part.map(|value| -value, Rational::checked_neg, part.unit.clone());
+		}
+	}
+	Ok(parts)
+}
+
 /// Return the sum of two [`Number`]s
-pub fn add(left: Number, right: Number) -> Result<Number, String> {
+pub fn add(left: Number, right: Number, context: &Context) -> Result<Number, Error> {
 	if left.unit == right.unit {
-		Ok(Number::with_unit(left.value + right.value, left.unit))
+		Ok(left.combine(
+			&right,
+			|a, b| a + b,
+			Rational::checked_add,
+			left.unit.clone(),
+		))
 	} else if left.primitive_unit() == right.primitive_unit()
 		&& !left.contains_category(Temperature)
 	{
-		let (left, right) = convert_to_lowest(left, right)?;
-		Ok(Number::with_unit(left.value + right.value, left.unit))
+		let (left, right) = convert_to_lowest(left, right, context)?;
+		Ok(left.combine(
+			&right,
+			|a, b| a + b,
+			Rational::checked_add,
+			left.unit.clone(),
+		))
 	} else {
-		Err(format!("Cannot add {} and {}", left, right))
+		Err(Error::incompatible_units(&left.unit, &right.unit))
 	}
 }
 
 /// Subtract a [`Number`] from another [`Number`]
-pub fn subtract(left: Number, right: Number) -> Result<Number, String> {
+pub fn subtract(left: Number, right: Number, context: &Context) -> Result<Number, Error> {
 	if left.unit == right.unit {
-		Ok(Number::with_unit(left.value - right.value, left.unit))
+		Ok(left.combine(
+			&right,
+			|a, b| a - b,
+			Rational::checked_sub,
+			left.unit.clone(),
+		))
 	} else if left.primitive_unit() == right.primitive_unit()
 		&& !left.contains_category(Temperature)
 	{
-		let (left, right) = convert_to_lowest(left, right)?;
-		Ok(Number::with_unit(left.value - right.value, left.unit))
+		let (left, right) = convert_to_lowest(left, right, context)?;
+		Ok(left.combine(
+			&right,
+			|a, b| a - b,
+			Rational::checked_sub,
+			left.unit.clone(),
+		))
 	} else {
-		Err(format!("Cannot subtract {} by {}", left, right))
+		Err(Error::incompatible_units(&left.unit, &right.unit))
 	}
 }
 
@@ -766,6 +1090,16 @@ pub fn subtract(left: Number, right: Number) -> Result<Number, String> {
 /// Other units are passed through.
 pub fn to_ideal_unit(number: Number) -> Number {
 	let number = reduce_unit(number);
+	let ideal = ideal_unit(number.clone());
+	// convert again to keep the number exact if it's rational
+	match number.rational {
+		Some(_) => convert(number, ideal.unit.clone(), &Context::default()).unwrap_or(ideal),
+		None => ideal,
+	}
+}
+
+/// The number in the unit [`to_ideal_unit`] picks
+fn ideal_unit(number: Number) -> Number {
 	let value = number.value * combined_weight(&number.unit);
 	let primitive = number.primitive_unit();
 	if primitive == Length.primitive() {
@@ -925,9 +1259,9 @@ pub fn to_ideal_unit(number: Number) -> Number {
 				let unit = candidates
 					.iter()
 					.rev()
-					.find(|&&u| value >= u.weight())
-					.copied()
-					.unwrap_or(candidates[0]);
+					.find(|&u| value >= u.weight())
+					.unwrap_or(&candidates[0])
+					.clone();
 				return Number::with_basic_unit(value / unit.weight(), unit);
 			}
 		}
@@ -939,9 +1273,9 @@ pub fn to_ideal_unit(number: Number) -> Number {
 		let unit = candidates
 			.iter()
 			.rev()
-			.find(|&&u| value >= u.weight())
-			.copied()
-			.unwrap_or(candidates[0]);
+			.find(|&u| value >= u.weight())
+			.unwrap_or(&candidates[0])
+			.clone();
 		return Number::with_basic_unit(value / unit.weight(), unit);
 	}
 
@@ -953,17 +1287,23 @@ pub fn to_ideal_unit(number: Number) -> Number {
 /// Units are converted accordingly.
 ///
 /// Temperatures don't work
-pub fn multiply(left: Number, right: Number) -> Result<Number, String> {
-	if left.contains_category(Temperature) || right.contains_category(Temperature) {
-		Err(format!("Cannot multiply {} and {}", left, right))
+pub fn multiply(left: Number, right: Number) -> Result<Number, Error> {
+	if left.contains_category(Temperature) {
+		Err(Error::unsupported_unit("multiplication", &left.unit))
+	} else if right.contains_category(Temperature) {
+		Err(Error::unsupported_unit("multiplication", &right.unit))
 	} else {
 		multiply_any(left, right)
 	}
 }
 
-pub(crate) fn multiply_any(left: Number, right: Number) -> Result<Number, String> {
-	let mut new_number = left;
-	new_number.value *= right.value;
+pub(crate) fn multiply_any(left: Number, right: Number) -> Result<Number, Error> {
+	let mut new_number = left.combine(
+		&right,
+		|a, b| a * b,
+		Rational::checked_mul,
+		left.unit.clone(),
+	);
 	for (r_unit, r_exp) in right.unit {
 		let existing = new_number.unit.iter_mut().find(|(u, _)| u == &r_unit);
 		match existing {
@@ -979,17 +1319,23 @@ pub(crate) fn multiply_any(left: Number, right: Number) -> Result<Number, String
 /// Units are converted accordingly.
 ///
 /// Temperatures don't work.
-pub fn divide(left: Number, right: Number) -> Result<Number, String> {
-	if left.contains_category(Temperature) || right.contains_category(Temperature) {
-		Err(format!("Cannot divide {} by {}", left, right))
+pub fn divide(left: Number, right: Number) -> Result<Number, Error> {
+	if left.contains_category(Temperature) {
+		Err(Error::unsupported_unit("division", &left.unit))
+	} else if right.contains_category(Temperature) {
+		Err(Error::unsupported_unit("division", &right.unit))
 	} else {
 		divide_any(left, right)
 	}
 }
 
-pub fn divide_any(left: Number, right: Number) -> Result<Number, String> {
-	let mut new_number = left;
-	new_number.value = new_number.value / right.value;
+pub fn divide_any(left: Number, right: Number) -> Result<Number, Error> {
+	let mut new_number = left.combine(
+		&right,
+		|a, b| a / b,
+		Rational::checked_div,
+		left.unit.clone(),
+	);
 	for (r_unit, r_exp) in right.unit {
 		let existing = new_number.unit.iter_mut().find(|(u, _)| u == &r_unit);
 		match existing {
@@ -1005,44 +1351,88 @@ pub fn divide_any(left: Number, right: Number) -> Result<Number, String> {
 /// `left` and `right` need to have the same [`UnitType`], and the result will have that same [`UnitType`].
 ///
 /// Temperatures don't work.
-pub fn modulo(left: Number, right: Number) -> Result<Number, String> {
-	if left.contains_category(Temperature) || right.contains_category(Temperature) {
-		Err(format!("Cannot modulo {} by {}", left, right))
+pub fn modulo(left: Number, right: Number, context: &Context) -> Result<Number, Error> {
+	if left.contains_category(Temperature) {
+		Err(Error::unsupported_unit("modulo", &left.unit))
+	} else if right.contains_category(Temperature) {
+		Err(Error::unsupported_unit("modulo", &right.unit))
 	} else if left.primitive_unit() == right.primitive_unit() {
 		// 5 km % 3 m
-		let (left, right) = convert_to_lowest(left, right)?;
-		Ok(Number::with_unit(left.value % right.value, left.unit))
+		let (left, right) = convert_to_lowest(left, right, context)?;
+		Ok(left.combine(
+			&right,
+			|a, b| a % b,
+			Rational::checked_rem,
+			left.unit.clone(),
+		))
 	} else {
-		Err(format!("Cannot modulo {} by {}", left, right))
+		Err(Error::incompatible_units(&left.unit, &right.unit))
 	}
 }
 
+/// Divide a [`Number`] by another [`Number`], rounding towards zero.
+///
+/// `left` and `right` need to have the same [`UnitType`], which gives a number without a unit, or `right` can be unitless, which keeps the unit of `left`.
+///
+/// Temperatures don't work.
+pub fn integer_divide(left: Number, right: Number, context: &Context) -> Result<Number, Error> {
+	if left.contains_category(Temperature) {
+		Err(Error::unsupported_unit("integer division", &left.unit))
+	} else if right.contains_category(Temperature) {
+		Err(Error::unsupported_unit("integer division", &right.unit))
+	} else if right.value.is_zero() {
+		Err(Error::domain("Division by zero"))
+	} else if left.primitive_unit() == right.primitive_unit() {
+		// 5 km div 3 m
+		let (left, right) = convert_to_lowest(left, right, context)?;
+		Ok(left.combine(&right, truncated_quotient, rational_quotient, vec![]))
+	} else if right.is_unitless() {
+		Ok(left.combine(
+			&right,
+			truncated_quotient,
+			rational_quotient,
+			left.unit.clone(),
+		))
+	} else {
+		Err(Error::incompatible_units(&left.unit, &right.unit))
+	}
+}
+
+/// Divide and round towards zero. Subtracting the remainder first makes the
+/// division exact, so the result isn't marked as inexact.
+fn truncated_quotient(left: D128, right: D128) -> D128 {
+	(left - left % right) / right
+}
+
+/// Like [`truncated_quotient`], but with fractions
+fn rational_quotient(left: Rational, right: Rational) -> Option<Rational> {
+	Some(Rational::from_integer(left.checked_div(right)?.trunc()))
+}
+
 /// Returns a [`Number`] to the power of another [`Number`]
 ///
 /// - If you take [`Length`] to the power of [`NoType`], the result has a unit of [`Area`].
 /// - If you take [`Length`] to the power of [`Length`], the result has a unit of [`Area`]
 /// - If you take [`Length`] to the power of [`Area`], the result has a unit of [`Volume`]
 /// - etc.
-pub fn pow(left: Number, right: Number) -> Result<Number, String> {
+pub fn pow(left: Number, right: Number) -> Result<Number, Error> {
 	// I tried converting `right` to use powi, but somehow that was slower
-	if left.contains_category(Temperature) || right.has_unit() {
-		Err(format!("Cannot raise {} to the power of {}", left, right))
+	if left.contains_category(Temperature) {
+		Err(Error::unsupported_unit("exponentiation", &left.unit))
+	} else if right.has_unit() {
+		Err(Error::unsupported_unit("an exponent", &right.unit))
 	} else if left.is_unitless() {
-		let result = left.value.pow(right.value);
-		let new_number = Number::new_unitless(result);
-		Ok(new_number)
+		Ok(rational_pow(&left, &right, vec![]))
 	} else {
 		let exp: isize = match (right.value.try_into(), right.value.is_integral()) {
 			(Ok(exp), true) => exp,
 			_ => {
-				return Err(format!(
-					"Cannot raise {} to the power of {}. Numbers with units can only be raised to integer powers",
-					left, right
+				return Err(Error::domain(
+					"Numbers with units can only be raised to integer powers",
 				));
 			}
 		};
-		let result = left.value.pow(right.value);
-		let mut new_number = Number::with_unit(result, left.unit);
+		let mut new_number = rational_pow(&left, &right, left.unit.clone());
 		for (_, unit_exp) in new_number.unit.iter_mut() {
 			*unit_exp *= exp;
 		}
@@ -1051,6 +1441,20 @@ pub fn pow(left: Number, right: Number) -> Result<Number, String> {
 	}
 }
 
+/// Raise a number to a power, exactly if the number is rational and the
+/// exponent is an integer
+fn rational_pow(left: &Number, right: &Number, unit: Vec<(Unit, isize)>) -> Number {
+	let exponent = right
+		.rational
+		.filter(Rational::is_integer)
+		.and_then(|exponent| i64::try_from(exponent.numerator()).ok());
+	left.map(
+		|value| value.pow(right.value),
+		|value| value.checked_pow(exponent?),
+		unit,
+	)
+}
+
 #[cfg(test)]
 mod tests {
 	use super::*;
@@ -1068,6 +1472,7 @@ mod tests {
 	}
 
 	#[test]
+	#[allow(clippy::excessive_precision)]
 	fn test_convert() {
 		pub fn convert_test(value: f64, unit: Unit, to_unit: Unit) -> f64 {
 			use std::str::FromStr;
@@ -1076,11 +1481,9 @@ mod tests {
 			let value_d128 = D128::from_str(value_string, Context::default()).unwrap();
 			let number = Number::with_basic_unit(value_d128, unit);
 
-			let result = convert(number, vec![(to_unit, 1)]);
+			let result = convert(number, vec![(to_unit, 1)], &crate::Context::default());
 			let string_result = &result.unwrap().value.to_string();
-			let float_result = f64::from_str(string_result).unwrap();
-
-			return float_result;
+			f64::from_str(string_result).unwrap()
 		}
 
 		assert_float_eq!(convert_test(1000.0, Nanosecond, Microsecond), 1.0);
@@ -1440,4 +1843,58 @@ mod tests {
 		assert_float_eq!(convert_test(80.33, Fahrenheit, Kelvin), 300.0);
 		assert_float_eq!(convert_test(5.0, Fahrenheit, Celsius), -15.0);
 	}
+
+	#[test]
+	fn test_custom_units() {
+		use crate::{Context, EvalOptions, eval_with_context};
+		let mut context = Context::new("en-US");
+		let eval_test = |context: &mut Context, input: &str, expected: &str| {
+			let output = eval_with_context(input, &EvalOptions::new(), context).unwrap();
+			assert_eq!(output.value.to_string(), expected, "{input}");
+		};
+
+		let sprint = Number::with_basic_unit(d!(2), Week);
+		context.define_unit("sprint", &["spr"], sprint).unwrap();
+		eval_test(&mut context, "6 weeks to sprints", "3 sprints");
+		eval_test(&mut context, "1 spr + 3 days", "17 days");
+		eval_test(&mut context, "1 sprint to h", "336 hours");
+		let sprint = find_custom_unit("Sprint", &context.custom_units).unwrap();
+		assert_eq!(
+			Number::with_basic_unit(d!(1), sprint).primitive_unit(),
+			vec![(Second, 1)],
+		);
+
+		let mah = Number::with_unit(d!(1), vec![(Milliampere, 1), (Hour, 1)]);
+		context.define_unit("mAh", &[], mah).unwrap();
+		eval_test(&mut context, "3000 mAh / 2 A to min", "90 minutes");
+		eval_test(&mut context, "3 mAh", "3 mAh");
+
+		context
+			.define_unit("crate", &[], Number::new_unitless(d!(24)))
+			.unwrap();
+		eval_test(&mut context, "2 crates + 3", "51");
+		context
+			.define_unit("crate", &[], Number::new_unitless(d!(12)))
+			.unwrap();
+		eval_test(&mut context, "2 crates + 3", "27");
+		assert_eq!(context.custom_units.len(), 3);
+
+		// units are only defined in their own context
+		assert!(
+			eval_with_context("1 sprint", &EvalOptions::new(), &mut Context::new("en-US")).is_err()
+		);
+
+		let meter = Number::with_basic_unit(d!(1), Meter);
+		assert!(context.define_unit("km", &[], meter.clone()).is_err());
+		assert!(
+			context
+				.define_unit("thing", &["spr"], meter.clone())
+				.is_err()
+		);
+		assert!(context.define_unit("x y", &[], meter).is_err());
+		let heat = Number::with_basic_unit(d!(1), Celsius);
+		assert!(context.define_unit("heat", &[], heat).is_err());
+		let nothing = Number::new_unitless(d!(0));
+		assert!(context.define_unit("nothing", &[], nothing).is_err());
+	}
 }